@@ -0,0 +1,43 @@
+use crate::model::groups::SavedGroups;
+use crate::model::journal::StorageError;
+
+use serenity::prelude::TypeMap;
+use tokio::sync::{Mutex, RwLock};
+use typemap_rev::TypeMapKey;
+
+use std::{
+    fs::{self, File},
+    sync::Arc,
+};
+
+const GROUPS_FILE: &str = "data/groups.json";
+const TEMP_FILE: &str = "data/groups.json.tmp";
+
+pub type GroupsType = Arc<Mutex<SavedGroups>>;
+
+pub struct Groups;
+
+impl TypeMapKey for Groups {
+    type Value = GroupsType;
+}
+
+pub async fn restore_groups(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let groups: SavedGroups = match File::open(GROUPS_FILE) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(_e) => SavedGroups::default(),
+    };
+
+    data.write()
+        .await
+        .insert::<Groups>(GroupsType::new(Mutex::new(groups)));
+    Ok(())
+}
+
+/// Writes every group to disk, replacing the previous file only once the new one is complete.
+pub fn save_groups(groups: &SavedGroups) -> Result<(), StorageError> {
+    let file = File::create(TEMP_FILE)?;
+    serde_json::to_writer_pretty(&file, groups)?;
+    file.sync_all()?;
+    fs::rename(TEMP_FILE, GROUPS_FILE)?;
+    Ok(())
+}
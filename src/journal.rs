@@ -0,0 +1,64 @@
+use crate::model::{
+    accounts::Accounts,
+    ids::GuildId,
+    journal::{Storage, StorageError},
+    ledger::Book,
+};
+use crate::persistence::Autosave;
+
+use serenity::prelude::TypeMap;
+use tokio::sync::{Mutex, RwLock};
+use typemap_rev::TypeMapKey;
+
+use std::sync::Arc;
+
+pub type JournalType = Arc<Mutex<Box<dyn Storage>>>;
+
+pub struct Journal;
+
+impl TypeMapKey for Journal {
+    type Value = JournalType;
+}
+
+/// Runs `operation` on the guild's book, holding the balances and journal locks throughout.
+pub async fn transact<T, E: From<StorageError>>(
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: GuildId,
+    operation: impl FnOnce(&mut Book) -> Result<T, E>,
+) -> Result<T, E> {
+    transact_in_group(data, guild_id, None, operation).await
+}
+
+/// Runs `operation` on the book of `group`'s sub-ledger, or of the whole guild without a group.
+pub async fn transact_in_group<T, E: From<StorageError>>(
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: GuildId,
+    group: Option<&str>,
+    operation: impl FnOnce(&mut Book) -> Result<T, E>,
+) -> Result<T, E> {
+    let (accounts_lock, journal_lock, autosave) = {
+        let data_read = data.read().await;
+        match (data_read.get::<Accounts>(), data_read.get::<Journal>()) {
+            (Some(accounts), Some(journal)) => (
+                accounts.clone(),
+                journal.clone(),
+                data_read.get::<Autosave>().cloned(),
+            ),
+            _ => {
+                return Err(StorageError::Invalid("could not get lock".to_string()).into());
+            }
+        }
+    };
+
+    let mut accounts = accounts_lock.write().await;
+    let mut journal = journal_lock.lock().await;
+
+    let result =
+        operation(&mut Book::new(guild_id, &mut accounts, journal.as_mut()).in_group(group));
+
+    if let (Ok(_), Some(autosave)) = (&result, autosave) {
+        autosave.mark_dirty();
+    }
+
+    result
+}
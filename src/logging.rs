@@ -1,28 +1,261 @@
-use crate::model::commands::TransactionType;
+use crate::model::{
+    accounts::Transfer,
+    currency::Currency,
+    ids::{GuildId, UserId},
+    transaction::{TransactionKind, TransactionType},
+    utils::{format_money, Money},
+};
 
-use serenity::prelude::TypeMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    model::{
+        id::{ChannelId, InteractionId},
+        misc::Mentionable,
+    },
+    prelude::TypeMap,
+};
 use tokio::sync::RwLock;
 use typemap_rev::TypeMapKey;
 
-use std::io::Write;
-use std::{fs::File, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    sync::Arc,
+};
 
-pub async fn log(data: Arc<RwLock<TypeMap>>, transaction: TransactionType) {
-    if let TransactionType::None = transaction {
-        return;
+/// The transaction log, whose lines from before it held JSON records are plain text.
+pub const LOG_FILE: &str = "data/transactions.log";
+
+/// Where a transaction was made.
+pub struct Origin {
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    /// The command or button that made it, missing for recurring bills.
+    pub interaction_id: Option<InteractionId>,
+}
+
+/// A line of the transaction log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Journal sequence number the transaction was recorded under.
+    pub id: Option<u64>,
+    pub timestamp: DateTime<Utc>,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interaction_id: Option<InteractionId>,
+    pub kind: TransactionKind,
+    pub initiator: UserId,
+    /// Amount entered for the transaction, in the minor unit of `currency`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Money>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voids: Option<u64>,
+    pub transfers: Vec<Transfer>,
+}
+
+impl LogRecord {
+    /// Whether `user` made the transaction or any balance it changed is theirs.
+    pub fn involves(&self, user: UserId) -> bool {
+        self.initiator == user
+            || self
+                .transfers
+                .iter()
+                .any(|transfer| transfer.creditor == user || transfer.debtor == user)
+    }
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, "#{} ", id)?;
+        }
+
+        let amount = format_money(
+            self.amount.unwrap_or_default(),
+            self.currency.unwrap_or_default(),
+        );
+        let description = self.description.as_deref().unwrap_or_default();
+
+        match self.kind {
+            TransactionKind::Owe => {
+                let recipient = match self.transfers.first() {
+                    Some(transfer) => transfer.creditor,
+                    None => return Err(fmt::Error),
+                };
+                write!(
+                    f,
+                    "{} owes {} to {} for \"{}\"",
+                    self.initiator.mention(),
+                    amount,
+                    recipient.mention(),
+                    description
+                )
+            }
+            TransactionKind::Bill => {
+                write!(f, "{} billed {} to", self.initiator.mention(), amount)?;
+                for transfer in &self.transfers {
+                    write!(
+                        f,
+                        " {} ({})",
+                        transfer.debtor.mention(),
+                        format_money(transfer.amount, transfer.currency)
+                    )?;
+                }
+                write!(f, " for \"{}\"", description)
+            }
+            TransactionKind::Settle => {
+                let (payer, payee) = match self.transfers.first() {
+                    Some(transfer) => (transfer.creditor, transfer.debtor),
+                    None => return Err(fmt::Error),
+                };
+                write!(
+                    f,
+                    "{} paid {} to {} (recorded by {})",
+                    payer.mention(),
+                    amount,
+                    payee.mention(),
+                    self.initiator.mention()
+                )
+            }
+            TransactionKind::Void => write!(
+                f,
+                "{} voided transaction #{}",
+                self.initiator.mention(),
+                self.voids.unwrap_or_default()
+            ),
+            TransactionKind::Simplify => {
+                write!(f, "{} simplified debts:", self.initiator.mention())?;
+                // existing debts are cancelled by negative transfers, the rest are the new plan
+                for transfer in self
+                    .transfers
+                    .iter()
+                    .filter(|transfer| transfer.amount.is_positive())
+                {
+                    write!(
+                        f,
+                        " {} owes {} to {};",
+                        transfer.debtor.mention(),
+                        format_money(transfer.amount, transfer.currency),
+                        transfer.creditor.mention()
+                    )?;
+                }
+                Ok(())
+            }
+            TransactionKind::Convert => {
+                write!(
+                    f,
+                    "{} converted debts into {}:",
+                    self.initiator.mention(),
+                    self.currency.unwrap_or_default()
+                )?;
+                // each debt is cancelled in its old currency and then recorded in the new one
+                for pair in self.transfers.chunks(2) {
+                    if let [old, new] = pair {
+                        write!(
+                            f,
+                            " {} owes {} ({}) to {};",
+                            new.debtor.mention(),
+                            format_money(new.amount, new.currency),
+                            format_money(-old.amount, old.currency),
+                            new.creditor.mention()
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+        }
     }
+}
+
+/// Appends a record of `transaction` to the log, unless there was no transaction.
+pub async fn log(
+    data: Arc<RwLock<TypeMap>>,
+    origin: Origin,
+    id: Option<u64>,
+    transaction: TransactionType,
+) -> io::Result<()> {
+    let (kind, initiator) = match (transaction.kind(), transaction.initiator()) {
+        (Some(kind), Some(initiator)) => (kind, initiator),
+        _ => return Ok(()),
+    };
 
     let log_lock = {
         let data_read = data.read().await;
         match data_read.get::<Log>() {
             Some(data) => data.clone(),
-            None => return,
+            None => return Ok(()),
         }
     };
 
+    let record = LogRecord {
+        id,
+        timestamp: Utc::now(),
+        guild_id: origin.guild_id,
+        channel_id: origin.channel_id,
+        interaction_id: origin.interaction_id,
+        kind,
+        initiator,
+        amount: transaction.amount(),
+        currency: transaction.currency(),
+        description: transaction.description().map(str::to_string),
+        voids: transaction.voids(),
+        transfers: transaction.transfers(),
+    };
+
+    let line = serde_json::to_string(&record)?;
     let mut log_file = log_lock.write().await;
+    writeln!(log_file, "{}", line)
+}
+
+/// The latest `limit` records in the log for `guild_id`, oldest first, limited to those involving
+/// `user` if one is given.
+pub async fn read_log(
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: Option<GuildId>,
+    user: Option<UserId>,
+    limit: usize,
+) -> io::Result<Vec<LogRecord>> {
+    let log_lock = match data.read().await.get::<Log>() {
+        Some(log) => log.clone(),
+        None => return Ok(Vec::new()),
+    };
 
-    writeln!(log_file, "{}", transaction).unwrap();
+    // held so that no record is read while it's half written
+    let _log_file = log_lock.read().await;
+    parse_log(BufReader::new(File::open(LOG_FILE)?), guild_id, user, limit)
+}
+
+/// Parses the latest `limit` records for `guild_id` from a log, limited to those involving `user`
+/// if one is given, skipping lines that aren't records.
+fn parse_log(
+    reader: impl BufRead,
+    guild_id: Option<GuildId>,
+    user: Option<UserId>,
+    limit: usize,
+) -> io::Result<Vec<LogRecord>> {
+    let mut records = VecDeque::new();
+    for line in reader.lines() {
+        let record: LogRecord = match serde_json::from_str(&line?) {
+            Ok(record) => record,
+            Err(_e) => continue,
+        };
+        if record.guild_id != guild_id || user.is_some_and(|user| !record.involves(user)) {
+            continue;
+        }
+        if records.len() == limit {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+    Ok(records.into())
 }
 
 pub struct Log;
@@ -30,3 +263,88 @@ pub struct Log;
 impl TypeMapKey for Log {
     type Value = Arc<RwLock<File>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::currency::USD;
+
+    use std::io::Cursor;
+
+    const GUILD: GuildId = GuildId(1);
+    const ALICE: UserId = UserId(10);
+    const BOB: UserId = UserId(20);
+    const CAROL: UserId = UserId(30);
+
+    fn owe(id: u64, guild_id: Option<GuildId>) -> LogRecord {
+        let amount = Money::new(500).unwrap();
+        LogRecord {
+            id: Some(id),
+            timestamp: Utc::now(),
+            guild_id,
+            channel_id: ChannelId(2),
+            interaction_id: None,
+            kind: TransactionKind::Owe,
+            initiator: ALICE,
+            amount: Some(amount),
+            currency: Some(USD),
+            description: Some("pizza".to_string()),
+            voids: None,
+            transfers: vec![Transfer {
+                creditor: BOB,
+                debtor: ALICE,
+                amount,
+                currency: USD,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = owe(3, Some(GUILD));
+        let line = serde_json::to_string(&record).unwrap();
+        let parsed: LogRecord = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(parsed.to_string(), record.to_string());
+        assert_eq!(parsed.timestamp, record.timestamp);
+        assert_eq!(
+            parsed.to_string(),
+            "#3 <@10> owes $5.00 to <@20> for \"pizza\""
+        );
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let mut log = "[debt] #1 an entry from before records\n".to_string();
+        for record in [
+            owe(2, Some(GUILD)),
+            owe(3, None),
+            owe(4, Some(GUILD)),
+            owe(5, Some(GUILD)),
+        ] {
+            log.push_str(&serde_json::to_string(&record).unwrap());
+            log.push('\n');
+        }
+
+        let ids = |records: Vec<LogRecord>| -> Vec<Option<u64>> {
+            records.into_iter().map(|record| record.id).collect()
+        };
+        assert_eq!(
+            ids(parse_log(Cursor::new(&log), Some(GUILD), None, 10).unwrap()),
+            vec![Some(2), Some(4), Some(5)]
+        );
+        assert_eq!(
+            ids(parse_log(Cursor::new(&log), Some(GUILD), None, 2).unwrap()),
+            vec![Some(4), Some(5)]
+        );
+        assert_eq!(
+            ids(parse_log(Cursor::new(&log), None, Some(BOB), 10).unwrap()),
+            vec![Some(3)]
+        );
+        // direct messages are only shown to whoever they involve
+        assert!(parse_log(Cursor::new(&log), None, Some(CAROL), 10)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,29 +1,34 @@
+mod groups;
+mod journal;
 mod logging;
 mod model;
+mod pending;
 mod persistence;
+mod recurring;
+mod reminders;
+mod settings;
+mod storage;
 
-use logging::{log, Log};
-use model::{
-    accounts::{Accounts, AccountsType},
-    commands::{
-        balance::{balance_command, balance_handler},
-        bill::{bill_command, bill_handler},
-        owe::{owe_command, owe_handler},
-        HandleCommandError,
-    },
-};
-use persistence::{read_accounts_file, write_accounts_file};
+use groups::restore_groups;
+use logging::{log, Log, Origin, LOG_FILE};
+use model::commands::{registry::CommandRegistry, HandleCommandError};
+use pending::{expire_pending, restore_pending};
+use persistence::{autosave, restore_accounts, write_accounts_file};
+use recurring::{restore_recurring, schedule_recurring};
+use reminders::send_reminders;
+use settings::restore_settings;
 
 use dotenv::dotenv;
 use serenity::{
     async_trait,
-    client::{Client, Context, EventHandler},
+    client::{bridge::gateway::GatewayIntents, Client, Context, EventHandler},
     futures::StreamExt,
     model::{
         gateway::Ready,
         interactions::{
-            application_command::ApplicationCommand, Interaction,
-            InteractionResponseType::ChannelMessageWithSource,
+            application_command::ApplicationCommand,
+            Interaction, InteractionApplicationCommandCallbackDataFlags as ResponseFlags,
+            InteractionResponseType::{ChannelMessageWithSource, UpdateMessage},
         },
     },
     prelude::TypeMap,
@@ -33,73 +38,175 @@ use signal_hook_tokio::Signals;
 use tokio::sync::RwLock;
 
 use std::{
-    collections::HashMap,
     fs::{create_dir_all, OpenOptions},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
-struct Handler;
+struct Handler {
+    commands: CommandRegistry,
+    /// Whether the background tasks have been started, which is only done on the first `ready`,
+    /// not again whenever the client reconnects.
+    started: AtomicBool,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::ApplicationCommand(command) = interaction {
-            let content = match command.data.name.as_str() {
-                "balance" => balance_handler(&ctx, &command).await,
-                "owe" => owe_handler(&ctx, &command).await,
-                "bill" => bill_handler(&ctx, &command).await,
-                _ => Err(HandleCommandError),
-            };
-
-            let reply = match content {
-                Ok(result) => {
-                    log(ctx.data.clone(), result.transaction).await;
-                    result.response
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                let content = self.commands.handle(&ctx, &command).await;
+
+                // errors are only shown to whoever used the command
+                let (reply, components, embed, flags) = match content {
+                    Ok(result) => {
+                        let origin = Origin {
+                            guild_id: command.guild_id.map(Into::into),
+                            channel_id: command.channel_id,
+                            interaction_id: Some(command.id),
+                        };
+                        if let Err(e) =
+                            log(ctx.data.clone(), origin, result.id, result.transaction).await
+                        {
+                            println!("Could not log transaction: {}", e);
+                        }
+                        (
+                            result.response,
+                            result.components,
+                            result.embed,
+                            if result.ephemeral {
+                                ResponseFlags::EPHEMERAL
+                            } else {
+                                ResponseFlags::empty()
+                            },
+                        )
+                    }
+                    Err(e) => {
+                        if let HandleCommandError::Internal(detail) = &e {
+                            println!("Error handling /{}: {}", command.data.name, detail);
+                        }
+                        (e.to_string(), None, None, ResponseFlags::EPHEMERAL)
+                    }
+                };
+
+                if let Err(e) = command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.content(reply).flags(flags);
+                                if let Some(embed) = embed {
+                                    message.add_embed(embed);
+                                }
+                                if let Some(components) = components {
+                                    message.components(|c| {
+                                        *c = components;
+                                        c
+                                    });
+                                }
+                                message
+                            })
+                    })
+                    .await
+                {
+                    println!("Cannot respond to slash command: {}", e);
                 }
-                Err(_e) => "Error handling command".to_string(),
-            };
-
-            if let Err(e) = command
-                .create_interaction_response(&ctx.http, |response| {
-                    response
-                        .kind(ChannelMessageWithSource)
-                        .interaction_response_data(|message| message.content(reply))
-                })
-                .await
-            {
-                println!("Cannot respond to slash command: {}", e);
             }
+            Interaction::MessageComponent(component) => {
+                let content = self.commands.handle_component(&ctx, &component).await;
+
+                // successful interactions replace the message holding the component, while
+                // errors are only shown to whoever interacted with it
+                let response = match content {
+                    Ok(result) => {
+                        let origin = Origin {
+                            guild_id: component.guild_id.map(Into::into),
+                            channel_id: component.channel_id,
+                            interaction_id: Some(component.id),
+                        };
+                        if let Err(e) =
+                            log(ctx.data.clone(), origin, result.id, result.transaction).await
+                        {
+                            println!("Could not log transaction: {}", e);
+                        }
+                        let components = result.components.unwrap_or_default();
+                        component
+                            .create_interaction_response(&ctx.http, |response| {
+                                response
+                                    .kind(UpdateMessage)
+                                    .interaction_response_data(|message| {
+                                        message
+                                            .content(result.response)
+                                            .embeds(result.embed)
+                                            .components(|c| {
+                                                *c = components;
+                                                c
+                                            })
+                                    })
+                            })
+                            .await
+                    }
+                    Err(e) => {
+                        if let HandleCommandError::Internal(detail) = &e {
+                            println!(
+                                "Error handling component {}: {}",
+                                component.data.custom_id, detail
+                            );
+                        }
+                        component
+                            .create_interaction_response(&ctx.http, |response| {
+                                response
+                                    .kind(ChannelMessageWithSource)
+                                    .interaction_response_data(|message| {
+                                        message
+                                            .content(e.to_string())
+                                            .flags(ResponseFlags::EPHEMERAL)
+                                    })
+                            })
+                            .await
+                    }
+                };
+
+                if let Err(e) = response {
+                    println!("Cannot respond to message component: {}", e);
+                }
+            }
+            _ => {}
         }
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
 
-        {
-            let mut data = ctx.data.write().await;
-            data.insert::<Accounts>(AccountsType::new(RwLock::new(HashMap::new())));
-            data.insert::<Log>(Arc::new(RwLock::new(
-                OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open("data/transactions.log")
-                    .unwrap(),
-            )));
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
         }
 
-        read_accounts_file(ctx.data.clone()).await;
+        match OpenOptions::new().create(true).append(true).open(LOG_FILE) {
+            Ok(log_file) => {
+                let mut data = ctx.data.write().await;
+                data.insert::<Log>(Arc::new(RwLock::new(log_file)));
+            }
+            Err(e) => println!(
+                "Could not open {}, transactions won't be logged: {}",
+                LOG_FILE, e
+            ),
+        }
 
-        let signals = match Signals::new(&[SIGTERM, SIGINT]) {
+        let signals = match Signals::new([SIGTERM, SIGINT]) {
             Ok(signals) => signals,
             Err(_e) => std::process::exit(1),
         };
         tokio::spawn(handle_signals(signals, ctx.data.clone()));
+        tokio::spawn(autosave(ctx.data.clone()));
+        tokio::spawn(expire_pending(ctx.data.clone()));
+        tokio::spawn(schedule_recurring(ctx.http.clone(), ctx.data.clone()));
+        tokio::spawn(send_reminders(ctx.http.clone(), ctx.data.clone()));
 
         let commands = ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
-            commands
-                .create_application_command(balance_command)
-                .create_application_command(owe_command)
-                .create_application_command(bill_command)
+            self.commands.create_commands(commands)
         })
         .await;
 
@@ -109,10 +216,12 @@ impl EventHandler for Handler {
 
 async fn handle_signals(signals: Signals, data: Arc<RwLock<TypeMap>>) {
     let mut signals = signals.fuse();
-    while let Some(signal) = signals.next().await {
+    if let Some(signal) = signals.next().await {
         match signal {
             SIGTERM | SIGINT => {
-                write_accounts_file(data).await;
+                if let Err(e) = write_accounts_file(data).await {
+                    println!("Could not save balances: {}", e);
+                }
                 std::process::exit(0);
             }
             _ => unreachable!(),
@@ -136,12 +245,44 @@ async fn main() {
         .parse()
         .expect("application id is not a valid id");
 
+    // members are only cached, so that roles can be billed, once the intent is enabled for the bot
+    let mut intents = GatewayIntents::non_privileged();
+    if std::env::var("GUILD_MEMBERS_INTENT").is_ok() {
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
+
     let mut client = Client::builder(token)
-        .event_handler(Handler)
+        .intents(intents)
+        .event_handler(Handler {
+            commands: CommandRegistry::new(),
+            started: AtomicBool::new(false),
+        })
         .application_id(application_id)
         .await
         .expect("Error creating client");
 
+    // the saved state is restored before connecting, so no command is handled without it
+    if let Err(e) = restore_accounts(client.data.clone()).await {
+        eprintln!("Could not restore balances: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = restore_settings(client.data.clone()).await {
+        eprintln!("Could not restore settings: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = restore_pending(client.data.clone()).await {
+        eprintln!("Could not restore pending requests: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = restore_recurring(client.data.clone()).await {
+        eprintln!("Could not restore recurring bills: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = restore_groups(client.data.clone()).await {
+        eprintln!("Could not restore groups: {}", e);
+        std::process::exit(1);
+    }
+
     if let Err(e) = client.start().await {
         println!("An error occurred while running the client: {:?}", e);
     }
@@ -1,4 +1,17 @@
-mod utils;
-
 pub mod accounts;
+pub mod amount;
 pub mod commands;
+pub mod convert;
+pub mod currency;
+pub mod groups;
+pub mod ids;
+pub mod journal;
+pub mod ledger;
+pub mod pending;
+pub mod recurring;
+pub mod reminders;
+pub mod schedule;
+pub mod simplify;
+pub mod split;
+pub mod transaction;
+pub mod utils;
@@ -1,13 +1,69 @@
-use serenity::model::id::UserId;
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+use super::utils::{Money, MoneyOverflowError};
+
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use typemap_rev::TypeMapKey;
 
 use std::{collections::HashMap, sync::Arc};
 
-pub type AccountsType = Arc<RwLock<HashMap<UserId, HashMap<UserId, i64>>>>;
+/// Scope used for commands issued outside of a guild, such as direct messages.
+pub const DM_SCOPE: GuildId = GuildId(0);
+
+pub type Ledger = HashMap<UserId, HashMap<UserId, Money>>;
+
+/// A guild's balances, with a separate ledger for each currency.
+pub type Ledgers = HashMap<Currency, Ledger>;
+
+pub type AccountsType = Arc<RwLock<HashMap<GuildId, Ledgers>>>;
 
 pub struct Accounts;
 
 impl TypeMapKey for Accounts {
     type Value = AccountsType;
 }
+
+/// A single change to a ledger: `debtor` now owes `creditor` an additional `amount`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub creditor: UserId,
+    pub debtor: UserId,
+    pub amount: Money,
+    #[serde(default)]
+    pub currency: Currency,
+}
+
+/// The guild whose balances an interaction in `guild_id` uses, or the DM scope outside of one.
+pub fn ledger_scope(guild_id: Option<impl Into<GuildId>>) -> GuildId {
+    guild_id.map_or(DM_SCOPE, Into::into)
+}
+
+/// Applies `transfer` to `ledger`, leaving it unchanged if either balance would overflow.
+pub fn apply_transfer(ledger: &mut Ledger, transfer: &Transfer) -> Result<(), MoneyOverflowError> {
+    let credit = balance(ledger, transfer.creditor, transfer.debtor)
+        .checked_add(transfer.amount)
+        .ok_or(MoneyOverflowError)?;
+    let debt = balance(ledger, transfer.debtor, transfer.creditor)
+        .checked_sub(transfer.amount)
+        .ok_or(MoneyOverflowError)?;
+
+    ledger
+        .entry(transfer.creditor)
+        .or_default()
+        .insert(transfer.debtor, credit);
+    ledger
+        .entry(transfer.debtor)
+        .or_default()
+        .insert(transfer.creditor, debt);
+    Ok(())
+}
+
+/// How much `other` owes `user` in `ledger`, negative when `user` owes `other`.
+pub fn balance(ledger: &Ledger, user: UserId, other: UserId) -> Money {
+    ledger
+        .get(&user)
+        .and_then(|account| account.get(&other))
+        .copied()
+        .unwrap_or_default()
+}
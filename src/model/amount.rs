@@ -0,0 +1,428 @@
+use super::currency::Currency;
+use super::utils::{Money, ParseMoneyError};
+
+use std::fmt::{self, Display, Formatter};
+
+/// Separators allowed between groups of three digits, as in `1,234.50` or `1'234.50`.
+const GROUP_SEPARATORS: [char; 2] = [',', '\''];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(String),
+    Currency(Currency),
+    Operator(char),
+    Open,
+    Close,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Currency(currency) => write!(f, "{}", currency),
+            Token::Operator(operator) => write!(f, "{}", operator),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+/// An exact fraction, so that amounts are only rounded once the whole expression is evaluated.
+#[derive(Debug, Clone, Copy)]
+struct Ratio {
+    numerator: i128,
+    /// Always positive.
+    denominator: i128,
+}
+
+impl Ratio {
+    fn new(numerator: i128, denominator: i128) -> Result<Ratio, ParseMoneyError> {
+        if denominator == 0 {
+            return Err(ParseMoneyError::DivisionByZero);
+        }
+
+        let divisor = gcd(numerator, denominator);
+        let sign = denominator.signum();
+        Ok(Ratio {
+            numerator: sign * numerator / divisor,
+            denominator: sign * denominator / divisor,
+        })
+    }
+
+    fn add(self, other: Ratio) -> Result<Ratio, ParseMoneyError> {
+        let numerator = self
+            .numerator
+            .checked_mul(other.denominator)
+            .zip(other.numerator.checked_mul(self.denominator))
+            .and_then(|(left, right)| left.checked_add(right));
+        let denominator = self.denominator.checked_mul(other.denominator);
+        match (numerator, denominator) {
+            (Some(numerator), Some(denominator)) => Ratio::new(numerator, denominator),
+            _ => Err(ParseMoneyError::TooLarge),
+        }
+    }
+
+    fn mul(self, other: Ratio) -> Result<Ratio, ParseMoneyError> {
+        match (
+            self.numerator.checked_mul(other.numerator),
+            self.denominator.checked_mul(other.denominator),
+        ) {
+            (Some(numerator), Some(denominator)) => Ratio::new(numerator, denominator),
+            _ => Err(ParseMoneyError::TooLarge),
+        }
+    }
+
+    fn div(self, other: Ratio) -> Result<Ratio, ParseMoneyError> {
+        if other.numerator == 0 {
+            return Err(ParseMoneyError::DivisionByZero);
+        }
+        self.mul(Ratio {
+            numerator: other.denominator,
+            denominator: other.numerator,
+        })
+    }
+
+    fn neg(self) -> Ratio {
+        Ratio {
+            numerator: -self.numerator,
+            denominator: self.denominator,
+        }
+    }
+
+    /// Rounds to a whole number of `currency`'s minor unit, with halves rounded away from zero.
+    fn to_money(self, currency: Currency) -> Result<Money, ParseMoneyError> {
+        let scaled = 10_i128
+            .checked_pow(currency.exponent())
+            .and_then(|scale| self.numerator.checked_mul(scale))
+            .ok_or(ParseMoneyError::TooLarge)?;
+
+        let quotient = scaled / self.denominator;
+        let remainder = scaled % self.denominator;
+        // compared without doubling the remainder, which could overflow
+        let rounded = if remainder.abs() >= self.denominator - remainder.abs() {
+            quotient + scaled.signum()
+        } else {
+            quotient
+        };
+
+        i64::try_from(rounded)
+            .ok()
+            .and_then(Money::new)
+            .ok_or(ParseMoneyError::TooLarge)
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+/// Evaluates an amount and the currency it is in, which is `default` unless a symbol or code is
+/// written with it.
+///
+/// Amounts may group digits in threes with commas or apostrophes, have up to as many decimal
+/// places as the currency, and have the symbol or code before or after them, as in `$1,234.5`,
+/// `.99 EUR` or `-¥500`. They can also be added, subtracted, multiplied and divided, with
+/// parentheses, as in `45.20+12.80` or `(120 + 30) / 3`. Numbers a total is multiplied or divided
+/// by may have any number of decimal places, and the result is rounded to the currency's minor
+/// unit with halves rounded away from zero.
+pub fn evaluate(input: &str, default: Currency) -> Result<(Money, Currency), ParseMoneyError> {
+    let mut currency = None;
+    let mut tokens = Vec::new();
+    for token in tokenize(input)? {
+        match token {
+            Token::Currency(found) => match currency {
+                Some(first) if first != found => {
+                    return Err(ParseMoneyError::MixedCurrencies(first, found))
+                }
+                _ => currency = Some(found),
+            },
+            token => tokens.push(token),
+        }
+    }
+
+    if tokens.is_empty() && currency.is_none() {
+        return Err(ParseMoneyError::Empty);
+    }
+
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        currency: currency.unwrap_or(default),
+    };
+    let total = parser.expression(false)?;
+    if let Some(token) = parser.peek() {
+        return Err(ParseMoneyError::UnexpectedToken(token.to_string()));
+    }
+
+    Ok((total.to_money(parser.currency)?, parser.currency))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseMoneyError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(index, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.' || GROUP_SEPARATORS.contains(&c)) {
+                    break;
+                }
+                end = index + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Number(input[start..end].to_string()));
+        } else if c.is_alphabetic() {
+            let mut end = start;
+            while let Some(&(index, c)) = chars.peek() {
+                if !c.is_alphabetic() {
+                    break;
+                }
+                end = index + c.len_utf8();
+                chars.next();
+            }
+            let word = &input[start..end];
+            match Currency::from_code(word) {
+                Some(currency) => tokens.push(Token::Currency(currency)),
+                None if word.chars().count() == 3 => {
+                    return Err(ParseMoneyError::UnknownCurrency(word.to_uppercase()))
+                }
+                None => return Err(ParseMoneyError::UnexpectedToken(word.to_string())),
+            }
+        } else {
+            chars.next();
+            tokens.push(match c {
+                '+' | '-' | '*' | '/' => Token::Operator(c),
+                '−' => Token::Operator('-'),
+                '×' => Token::Operator('*'),
+                '÷' => Token::Operator('/'),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => match Currency::from_symbol(&c.to_string()) {
+                    Some(currency) => Token::Currency(currency),
+                    None => return Err(ParseMoneyError::UnexpectedCharacter(c)),
+                },
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    currency: Currency,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&Token, ParseMoneyError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or(ParseMoneyError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Sums of products, where `factor` is set when the expression is a multiplier or divisor
+    /// rather than an amount.
+    fn expression(&mut self, factor: bool) -> Result<Ratio, ParseMoneyError> {
+        let mut total = self.product(factor)?;
+        while let Some(&Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            self.position += 1;
+            let term = self.product(factor)?;
+            total = match operator {
+                '+' => total.add(term)?,
+                _ => total.add(term.neg())?,
+            };
+        }
+        Ok(total)
+    }
+
+    fn product(&mut self, factor: bool) -> Result<Ratio, ParseMoneyError> {
+        let mut total = self.operand(factor)?;
+        while let Some(&Token::Operator(operator @ ('*' | '/'))) = self.peek() {
+            self.position += 1;
+            let operand = self.operand(true)?;
+            total = match operator {
+                '*' => total.mul(operand)?,
+                _ => total.div(operand)?,
+            };
+        }
+        Ok(total)
+    }
+
+    fn operand(&mut self, factor: bool) -> Result<Ratio, ParseMoneyError> {
+        match self.next()?.clone() {
+            Token::Operator('-') => Ok(self.operand(factor)?.neg()),
+            Token::Operator('+') => self.operand(factor),
+            Token::Open => {
+                let total = self.expression(factor)?;
+                match self.next()? {
+                    Token::Close => Ok(total),
+                    token => Err(ParseMoneyError::UnexpectedToken(token.to_string())),
+                }
+            }
+            Token::Number(number) if factor => parse_number(&number, None),
+            Token::Number(number) => parse_number(&number, Some(self.currency)),
+            token => Err(ParseMoneyError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+/// Parses a decimal number with optional grouping, with at most as many decimal places as
+/// `currency` if given.
+fn parse_number(number: &str, currency: Option<Currency>) -> Result<Ratio, ParseMoneyError> {
+    let invalid = || ParseMoneyError::InvalidNumber(number.to_string());
+
+    let (whole, fraction) = match number.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (number, None),
+    };
+
+    if whole.contains(GROUP_SEPARATORS) {
+        let mut groups = whole.split(GROUP_SEPARATORS);
+        let first_valid = groups
+            .next()
+            .is_some_and(|group| (1..=3).contains(&group.len()));
+        if !first_valid || !groups.all(|group| group.len() == 3) {
+            return Err(ParseMoneyError::InvalidGrouping(number.to_string()));
+        }
+    }
+
+    let fraction = match fraction {
+        Some("") => return Err(invalid()),
+        Some(fraction) if !fraction.chars().all(|c| c.is_ascii_digit()) => {
+            return Err(if fraction.contains(GROUP_SEPARATORS) {
+                ParseMoneyError::InvalidGrouping(number.to_string())
+            } else {
+                invalid()
+            });
+        }
+        Some(fraction) => fraction,
+        None => "",
+    };
+
+    let decimals = fraction.len() as u32;
+    if let Some(currency) = currency.filter(|currency| decimals > currency.exponent()) {
+        return Err(ParseMoneyError::TooManyDecimals(currency));
+    }
+
+    let digits: String = whole
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .chain(fraction.chars())
+        .collect();
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    match (digits.parse::<i128>().ok(), 10_i128.checked_pow(decimals)) {
+        (Some(numerator), Some(denominator)) => Ratio::new(numerator, denominator),
+        _ => Err(ParseMoneyError::TooLarge),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::currency::{EUR, JPY, USD};
+    use super::*;
+
+    fn amount(input: &str, default: Currency) -> Result<(i64, Currency), ParseMoneyError> {
+        evaluate(input, default).map(|(money, currency)| (money.minor(), currency))
+    }
+
+    #[test]
+    fn test_evaluate_numbers() {
+        assert_eq!(Ok((123450, USD)), amount("1,234.50", USD));
+        assert_eq!(Ok((123450, USD)), amount("1'234.5", USD));
+        assert_eq!(Ok((1250, USD)), amount("12.5", USD));
+        assert_eq!(Ok((99, USD)), amount(".99", USD));
+        assert_eq!(Ok((100000000, USD)), amount("1,000,000", USD));
+        assert_eq!(Ok((1200000, JPY)), amount("1,200,000", JPY));
+    }
+
+    #[test]
+    fn test_evaluate_currencies() {
+        assert_eq!(Ok((500, EUR)), amount("€5", USD));
+        assert_eq!(Ok((500, EUR)), amount("5€", USD));
+        assert_eq!(Ok((500, EUR)), amount("5 eur", USD));
+        assert_eq!(Ok((-500, EUR)), amount("-€5", USD));
+        assert_eq!(Ok((-500, EUR)), amount("€-5", USD));
+        assert_eq!(Ok((1800, EUR)), amount("€12 + €6", USD));
+        assert_eq!(
+            Err(ParseMoneyError::MixedCurrencies(EUR, USD)),
+            amount("€12 + $6", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::UnknownCurrency("XYZ".to_string())),
+            amount("12 xyz", USD)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_expressions() {
+        assert_eq!(Ok((5800, USD)), amount("45.20+12.80", USD));
+        assert_eq!(Ok((4000, USD)), amount("120/3", USD));
+        assert_eq!(Ok((3333, USD)), amount("100/3", USD));
+        assert_eq!(Ok((6667, USD)), amount("200/3", USD));
+        assert_eq!(Ok((5000, USD)), amount("(120 + 30) / 3", USD));
+        assert_eq!(Ok((1075, USD)), amount("10 * 1.075", USD));
+        assert_eq!(Ok((-1000, USD)), amount("10 - 20", USD));
+        assert_eq!(Ok((1000, USD)), amount("5 × 2", USD));
+        assert_eq!(
+            Ok((1, JPY)),
+            amount(
+                "170141183460469231731687303715884105726 / 170141183460469231731687303715884105727",
+                JPY
+            )
+        );
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert_eq!(Err(ParseMoneyError::Empty), amount(" ", USD));
+        assert_eq!(Err(ParseMoneyError::UnexpectedEnd), amount("$", USD));
+        assert_eq!(Err(ParseMoneyError::UnexpectedEnd), amount("12 +", USD));
+        assert_eq!(
+            Err(ParseMoneyError::UnexpectedToken(")".to_string())),
+            amount("(12))", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::UnexpectedCharacter('#')),
+            amount("#12", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::TooManyDecimals(USD)),
+            amount("12.345", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::TooManyDecimals(JPY)),
+            amount("5.0", JPY)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::InvalidGrouping("12,34".to_string())),
+            amount("12,34", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::InvalidNumber("1.2.3".to_string())),
+            amount("1.2.3", USD)
+        );
+        assert_eq!(Err(ParseMoneyError::DivisionByZero), amount("12/0", USD));
+        assert_eq!(
+            Err(ParseMoneyError::TooLarge),
+            amount("92233720368547758.08", USD)
+        );
+    }
+}
@@ -1,90 +1,242 @@
+pub mod audit;
 pub mod balance;
 pub mod bill;
+pub mod confirm;
+pub mod convert;
+pub mod currency;
+pub mod embeds;
+pub mod group;
+pub mod history;
 pub mod owe;
+pub mod privacy;
+pub mod recurring;
+pub mod registry;
+pub mod reminders;
+pub mod settle;
+pub mod simplify;
+pub mod void;
 
-use super::utils::format_money;
+use super::currency::Currency;
+use super::ids::UserId;
+use super::journal::StorageError;
+use super::ledger::LedgerError;
+use super::split::SplitError;
+use super::transaction::TransactionType;
 
-use serenity::model::prelude::User;
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateComponents, CreateEmbed},
+    client::Context,
+    model::{
+        interactions::{
+            application_command::ApplicationCommandInteraction,
+            message_component::MessageComponentInteraction,
+        },
+        misc::Mentionable,
+    },
+};
 
 use std::{
     error::Error,
-    fmt::{Display, Formatter, Result, Write},
+    fmt::{self, Display, Formatter},
 };
 
-#[derive(Debug, Clone)]
-pub struct HandleCommandError;
+/// Longest message Discord accepts.
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Why a command failed, shown to whoever used it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleCommandError {
+    /// An option the command needs was not given.
+    MissingOption(&'static str),
+    /// An option was given a value that can't be used.
+    InvalidOption {
+        option: &'static str,
+        reason: String,
+    },
+    /// The user isn't allowed to do what they asked.
+    NotAllowed(String),
+    /// The command was understood but can't be carried out.
+    Rejected(String),
+    /// Something went wrong that the user can't fix, which is logged instead of shown.
+    Internal(String),
+}
+
+impl HandleCommandError {
+    pub fn invalid(option: &'static str, reason: impl Display) -> HandleCommandError {
+        HandleCommandError::InvalidOption {
+            option,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn internal(detail: impl Display) -> HandleCommandError {
+        HandleCommandError::Internal(detail.to_string())
+    }
+
+    /// An option the command wasn't registered with, or with a value of the wrong type.
+    pub fn unexpected_option(name: &str) -> HandleCommandError {
+        HandleCommandError::Internal(format!("unexpected option {}", name))
+    }
+
+    pub fn admin_only(action: &str) -> HandleCommandError {
+        HandleCommandError::NotAllowed(format!("Only administrators can {}", action))
+    }
+}
 
 impl Display for HandleCommandError {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "could not get content")
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            HandleCommandError::MissingOption(option) => {
+                write!(f, "The `{}` option is required", option)
+            }
+            HandleCommandError::InvalidOption { option, reason } => {
+                write!(f, "Invalid `{}`: {}", option, reason)
+            }
+            HandleCommandError::NotAllowed(reason) => write!(f, "{}", reason),
+            HandleCommandError::Rejected(reason) => write!(f, "{}", reason),
+            HandleCommandError::Internal(_detail) => {
+                write!(f, "Something went wrong, please try again later")
+            }
+        }
     }
 }
 
 impl Error for HandleCommandError {}
 
+#[derive(Default)]
 pub struct CommandResult {
     pub response: String,
     pub transaction: TransactionType,
+    /// Journal sequence number the transaction was recorded under, if any.
+    pub id: Option<u64>,
+    pub components: Option<CreateComponents>,
+    /// Shown below the response, which may be left empty.
+    pub embed: Option<CreateEmbed>,
+    /// Only show the response to whoever used the command.
+    pub ephemeral: bool,
 }
 
-pub struct OweTransaction {
-    initiator: User,
-    amount: i64,
-    recipient: User,
-    description: String,
-}
+/// A slash command, along with any buttons it creates.
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    /// Name the command is registered under, which also prefixes the custom id of its components.
+    fn name(&self) -> &'static str;
+
+    /// Describes the command and its options, after the name has been set.
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand;
 
-impl Display for OweTransaction {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(
-            f,
-            "{} owes {} to {} for \"{}\"",
-            self.initiator.tag(),
-            format_money(self.amount),
-            self.recipient.tag(),
-            self.description
-        )
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError>;
+
+    /// Handles a component whose custom id starts with the command's name.
+    async fn handle_component(
+        &self,
+        _ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        Err(HandleCommandError::internal(format!(
+            "unexpected component {}",
+            component.data.custom_id
+        )))
     }
 }
 
-pub struct BillTransaction {
-    initiator: User,
-    amount: i64,
-    recipients: Vec<User>,
-    description: String,
+/// Looks up the currency code given for `option`.
+pub fn currency_option(option: &'static str, code: &str) -> Result<Currency, HandleCommandError> {
+    Currency::from_code(code.trim()).ok_or_else(|| {
+        HandleCommandError::invalid(option, format!("{} is not a currency code", code.trim()))
+    })
 }
 
-impl Display for BillTransaction {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        let mut recipient_list = String::new();
-
-        for recipient in &self.recipients {
-            write!(recipient_list, " {}", recipient.tag()).unwrap();
+/// Rejects users to bill that name anyone twice, as values are matched to users by position.
+///
+/// `caller` is first in `participants` when `include_self` was given.
+pub fn distinct_users(
+    participants: &[UserId],
+    caller: UserId,
+    include_self: bool,
+) -> Result<(), HandleCommandError> {
+    for (i, user) in participants.iter().enumerate() {
+        if !participants[..i].contains(user) {
+            continue;
         }
+        if include_self && *user == caller {
+            return Err(HandleCommandError::invalid(
+                "include_self",
+                "you are already one of the users",
+            ));
+        }
+        return Err(HandleCommandError::invalid(
+            "user",
+            format!("{} is listed more than once", user.mention()),
+        ));
+    }
+    Ok(())
+}
+
+/// Why a command failed when the journal could not be read.
+pub fn read_failed(e: StorageError) -> HandleCommandError {
+    HandleCommandError::internal(format!("could not read journal: {}", e))
+}
 
-        write!(
-            f,
-            "{} billed {} to{} for \"{}\"",
-            self.initiator.tag(),
-            format_money(self.amount),
-            recipient_list,
-            self.description
-        )
+impl From<StorageError> for HandleCommandError {
+    fn from(e: StorageError) -> Self {
+        record_failed(e)
     }
 }
 
-pub enum TransactionType {
-    Owe(OweTransaction),
-    Bill(BillTransaction),
-    None,
+/// Why a command failed when the ledger refused it.
+pub fn ledger_failed(e: LedgerError) -> HandleCommandError {
+    match e {
+        LedgerError::Storage(e) => record_failed(e),
+        LedgerError::Split(SplitError::NoParticipants) => {
+            HandleCommandError::Rejected("Choose at least one user to bill".to_string())
+        }
+        LedgerError::Split(e @ SplitError::UnknownMode(_)) => {
+            HandleCommandError::invalid("split", e)
+        }
+        LedgerError::Split(e @ SplitError::TooLarge) => HandleCommandError::invalid("amount", e),
+        LedgerError::Split(e) => HandleCommandError::invalid("values", e),
+        e @ (LedgerError::NotPositive | LedgerError::MoreThanOutstanding(..)) => {
+            HandleCommandError::invalid("amount", e)
+        }
+        LedgerError::NothingOutstanding => {
+            HandleCommandError::Rejected("There is nothing outstanding to settle".to_string())
+        }
+        LedgerError::NotFound(seq) => {
+            HandleCommandError::Rejected(format!("There is no transaction #{} here", seq))
+        }
+        LedgerError::VoidsAnother(seq) => HandleCommandError::Rejected(format!(
+            "#{} voids another transaction and can't be voided itself",
+            seq
+        )),
+        LedgerError::AlreadyVoided(seq) => {
+            HandleCommandError::Rejected(format!("Transaction #{} was already voided", seq))
+        }
+        LedgerError::NotInitiator(_seq) => HandleCommandError::NotAllowed(
+            "Only whoever recorded a transaction or an administrator can void it".to_string(),
+        ),
+        LedgerError::NothingToUndo => {
+            HandleCommandError::Rejected("You have no transactions left to undo".to_string())
+        }
+    }
 }
 
-impl Display for TransactionType {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        match self {
-            TransactionType::Owe(x) => write!(f, "{}", x),
-            TransactionType::Bill(x) => write!(f, "{}", x),
-            TransactionType::None => unreachable!(),
+/// Why a command failed when its transaction could not be recorded.
+///
+/// Amounts too large to keep are explained to the user, anything else is an internal error.
+pub fn record_failed(e: StorageError) -> HandleCommandError {
+    match e {
+        StorageError::Overflow(e) => {
+            HandleCommandError::Rejected(format!("Could not record transaction: {}", e))
         }
+        e => HandleCommandError::internal(format!("could not record transaction: {}", e)),
     }
 }
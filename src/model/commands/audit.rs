@@ -0,0 +1,135 @@
+use super::{CommandResult, HandleCommandError, SlashCommand, MAX_MESSAGE_LENGTH};
+
+use super::super::utils::*;
+
+use crate::logging::read_log;
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::{
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            ApplicationCommandOptionType,
+        },
+        misc::Mentionable,
+    },
+};
+
+use std::fmt::Write;
+
+const DEFAULT_RECORDS: i64 = 10;
+const MAX_RECORDS: i64 = 25;
+
+pub struct AuditCommand;
+
+#[async_trait]
+impl SlashCommand for AuditCommand {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show the latest entries of this server's transaction log")
+            .create_option(|option| {
+                option
+                    .name("count")
+                    .description("How many entries to show")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut count = DEFAULT_RECORDS;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "count" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        if !(1..=MAX_RECORDS).contains(value) {
+                            return Err(HandleCommandError::invalid(
+                                "count",
+                                format!("must be between 1 and {}", MAX_RECORDS),
+                            ));
+                        }
+                        count = *value;
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        if command.guild_id.is_some() && !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("read the transaction log"));
+        }
+
+        // outside a server, only the caller's own transactions are shown
+        let user = match command.guild_id {
+            Some(_guild_id) => None,
+            None => Some(command.user.id.into()),
+        };
+        let records = match read_log(
+            ctx.data.clone(),
+            command.guild_id.map(Into::into),
+            user,
+            count as usize,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(e) => {
+                return Err(HandleCommandError::internal(format!(
+                    "could not read transaction log: {}",
+                    e
+                )))
+            }
+        };
+
+        if records.is_empty() {
+            return Ok(CommandResult {
+                response: "No transactions have been logged here".to_string(),
+                ephemeral: true,
+                ..Default::default()
+            });
+        }
+
+        // the newest entries are kept when they don't all fit in a message
+        let mut lines = Vec::new();
+        let mut length = 0;
+        for record in records.iter().rev() {
+            let mut line = String::new();
+            if let Err(e) = writeln!(
+                line,
+                "<t:{}:f> in {}: {}",
+                record.timestamp.timestamp(),
+                record.channel_id.mention(),
+                record
+            ) {
+                return Err(HandleCommandError::internal(e));
+            }
+            if length + line.len() > MAX_MESSAGE_LENGTH {
+                break;
+            }
+            length += line.len();
+            lines.push(line);
+        }
+        let response: String = lines.into_iter().rev().collect();
+
+        Ok(CommandResult {
+            response,
+            ephemeral: true,
+            ..Default::default()
+        })
+    }
+}
@@ -1,48 +1,311 @@
-use super::{CommandResult, HandleCommandError, TransactionType};
+use super::group::{find_group, in_group};
+use super::{read_failed, CommandResult, HandleCommandError, SlashCommand};
 
-use super::super::accounts::AccountsType;
+use super::super::accounts::{ledger_scope, AccountsType};
+use super::super::currency::Currency;
+use super::super::ids::UserId;
+use super::super::ledger::{balances, summarize, BalanceSummary};
+use super::super::transaction::TransactionType;
 use super::super::utils::*;
 
+use crate::settings::{guild_settings, user_settings};
+
 use serenity::{
-    builder::CreateApplicationCommand, client::Context,
-    model::interactions::application_command::ApplicationCommandInteraction,
+    async_trait,
+    builder::{CreateApplicationCommand, CreateEmbed},
+    client::Context,
+    model::{
+        id,
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            ApplicationCommandOptionType,
+        },
+        misc::Mentionable,
+        prelude::User,
+    },
 };
 
-use std::fmt::Write;
+use std::{collections::HashMap, fmt::Write};
+
+const OWED_COLOUR: u32 = 0x2ecc71;
+const OWING_COLOUR: u32 = 0xe74c3c;
+const SETTLED_COLOUR: u32 = 0x95a5a6;
+
+pub struct BalanceCommand;
+
+#[async_trait]
+impl SlashCommand for BalanceCommand {
+    fn name(&self) -> &'static str {
+        "balance"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Get balance")
+            .create_option(|option| {
+                option
+                    .name("user")
+                    .description("Whose balance to show, if they allow it with /privacy")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("group")
+                    .description("Only show the balance within this group")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut user = &command.user;
+        let mut group = None;
 
-pub fn balance_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command.name("balance").description("Get balance")
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "user" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::User(value, _member)) => {
+                        user = value;
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "group" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        group = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let privacy = user_settings(ctx.data.clone(), user.id.into()).await;
+        if user.id != command.user.id && !privacy.queryable {
+            return Err(HandleCommandError::NotAllowed(format!(
+                "{} keeps their balance private",
+                user.tag()
+            )));
+        }
+
+        let group = match group {
+            Some(name) => Some(find_group(ctx, command, name).await?.0),
+            None => None,
+        };
+
+        let scope = ledger_scope(command.guild_id);
+        let balances = match &group {
+            Some(name) => {
+                let journal = match get_journal_lock(ctx).await {
+                    Ok(journal_lock) => journal_lock,
+                    Err(e) => return Err(HandleCommandError::internal(e)),
+                };
+                let ledgers = match journal.lock().await.group_ledgers(scope, name) {
+                    Ok(ledgers) => ledgers,
+                    Err(e) => return Err(read_failed(e)),
+                };
+                balances(&HashMap::from([(scope, ledgers)]), scope, user.id.into())
+            }
+            None => {
+                let accounts: AccountsType = match get_accounts_lock(ctx).await {
+                    Ok(accounts_lock) => accounts_lock,
+                    Err(e) => return Err(HandleCommandError::internal(e)),
+                };
+                let balances = balances(&*accounts.read().await, scope, user.id.into());
+                balances
+            }
+        };
+
+        let pending = match get_pending_lock(ctx).await {
+            Ok(pending_lock) => {
+                pending_lock
+                    .lock()
+                    .await
+                    .pending_for(scope, group.as_deref(), user.id.into())
+            }
+            Err(e) => return Err(HandleCommandError::internal(e)),
+        };
+
+        let mut result = if guild_settings(ctx.data.clone(), scope).await.plain_text {
+            balance_text(ctx, user, group.as_deref(), &balances, &pending).await?
+        } else {
+            balance_embed(command, user, group.as_deref(), &balances, &pending)?
+        };
+        // shown to everyone only when both sides of every balance listed are public
+        let mut public = privacy.public;
+        for (_currency, counterparty, _amount) in balances.iter().chain(&pending) {
+            if !public {
+                break;
+            }
+            public = user_settings(ctx.data.clone(), *counterparty).await.public;
+        }
+        result.ephemeral = !public;
+
+        Ok(result)
+    }
 }
 
-pub async fn balance_handler(
-    ctx: &Context,
+/// The balance of `user` as an embed, phrased for whoever used the command.
+///
+/// Requests still waiting for confirmation are listed separately and left out of the totals.
+fn balance_embed(
     command: &ApplicationCommandInteraction,
+    user: &User,
+    group: Option<&str>,
+    balances: &[(Currency, UserId, Money)],
+    pending: &[(Currency, UserId, Money)],
 ) -> Result<CommandResult, HandleCommandError> {
-    let accounts: AccountsType = match get_accounts_lock(ctx).await {
-        Ok(accounts_lock) => accounts_lock,
-        Err(_e) => return Err(HandleCommandError),
+    let summaries = match summarize(balances) {
+        Ok(summaries) => summaries,
+        Err(e) => {
+            return Err(HandleCommandError::Rejected(format!(
+                "Could not total the balances: {}",
+                e
+            )))
+        }
     };
 
-    let mut response = format!("{}'s balance:\n", command.user.tag());
-
-    let accounts_read = accounts.read().await;
-    if let Some(account) = accounts_read.get(&command.user.id) {
-        for (id, &balance) in account {
-            if let Ok(user) = id.to_user(ctx).await {
-                if let Err(_e) = writeln!(
-                    response,
-                    "`{:<32}{:>16}`",
-                    user.tag(),
-                    format_money(balance)
-                ) {
-                    return Err(HandleCommandError);
-                }
-            }
+    let (owed_to, owes) = if user.id == command.user.id {
+        ("you".to_string(), "You owe".to_string())
+    } else {
+        (user.name.clone(), format!("{} owes", user.name))
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed.colour(balance_colour(&summaries));
+
+    let in_group = in_group(group);
+    if summaries.is_empty() {
+        embed.description(format!("{} is all settled up{}", user.mention(), in_group));
+    } else {
+        embed.description(format!("{}'s balance{}", user.mention(), in_group));
+    }
+
+    for summary in &summaries {
+        // only label fields with their currency when there's more than one
+        let suffix = if summaries.len() > 1 {
+            format!(" ({})", summary.currency.code())
+        } else {
+            String::new()
+        };
+
+        if !summary.owed.is_empty() {
+            embed.field(
+                format!("Owed to {}{}", owed_to, suffix),
+                list_balances(&summary.owed, summary.currency),
+                true,
+            );
+        }
+        if !summary.owing.is_empty() {
+            embed.field(
+                format!("{}{}", owes, suffix),
+                list_balances(&summary.owing, summary.currency),
+                true,
+            );
         }
+
+        let net = if summary.net.is_positive() {
+            format!(
+                "{} owed to {}",
+                format_money(summary.net, summary.currency),
+                owed_to
+            )
+        } else if summary.net.is_negative() {
+            format!("{} {}", owes, format_money(-summary.net, summary.currency))
+        } else {
+            "Settled".to_string()
+        };
+        embed.field(format!("Net{}", suffix), net, false);
+    }
+
+    if !pending.is_empty() {
+        embed.field("Awaiting confirmation", list_pending(pending), false);
+    }
+
+    Ok(CommandResult {
+        embed: Some(embed),
+        ..Default::default()
+    })
+}
+
+/// The balance of `user` as a monospace table, for servers that don't show embeds.
+async fn balance_text(
+    ctx: &Context,
+    user: &User,
+    group: Option<&str>,
+    balances: &[(Currency, UserId, Money)],
+    pending: &[(Currency, UserId, Money)],
+) -> Result<CommandResult, HandleCommandError> {
+    let mut response = format!("{}'s balance{}:\n", user.tag(), in_group(group));
+    write_balance_lines(ctx, &mut response, balances).await?;
+
+    if !pending.is_empty() {
+        response.push_str("Awaiting confirmation:\n");
+        write_balance_lines(ctx, &mut response, pending).await?;
     }
 
     Ok(CommandResult {
         response,
         transaction: TransactionType::None,
+        id: None,
+        ..Default::default()
     })
 }
+
+async fn write_balance_lines(
+    ctx: &Context,
+    response: &mut String,
+    balances: &[(Currency, UserId, Money)],
+) -> Result<(), HandleCommandError> {
+    for &(currency, other, balance) in balances {
+        if let Ok(other) = id::UserId::from(other).to_user(ctx).await {
+            if let Err(e) = writeln!(
+                response,
+                "`{:<32}{:>16}`",
+                other.tag(),
+                format_money(balance, currency)
+            ) {
+                return Err(HandleCommandError::internal(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One line per user, mentioning them so their name shows without looking them up.
+fn list_balances(balances: &[(UserId, Money)], currency: Currency) -> String {
+    balances
+        .iter()
+        .map(|(user, amount)| format!("{} {}", user.mention(), format_money(*amount, currency)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// One line per unconfirmed part, negative where the user would owe the other user.
+fn list_pending(pending: &[(Currency, UserId, Money)]) -> String {
+    pending
+        .iter()
+        .map(|(currency, user, amount)| {
+            format!("{} {}", user.mention(), format_money(*amount, *currency))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Green when the user is owed on the whole, red when they owe, and grey otherwise.
+fn balance_colour(summaries: &[BalanceSummary]) -> u32 {
+    let owed = summaries.iter().any(|summary| summary.net.is_positive());
+    let owing = summaries.iter().any(|summary| summary.net.is_negative());
+    match (owed, owing) {
+        (true, false) => OWED_COLOUR,
+        (false, true) => OWING_COLOUR,
+        _ => SETTLED_COLOUR,
+    }
+}
@@ -1,124 +1,460 @@
-use super::{BillTransaction, CommandResult, HandleCommandError, TransactionType};
+use super::confirm::{answer_handler, confirmation_expiry, request_confirmation};
+use super::group::{find_group, in_group};
+use super::{distinct_users, ledger_failed, CommandResult, HandleCommandError, SlashCommand};
 
+use super::super::accounts::ledger_scope;
+use super::super::ids::UserId;
+use super::super::ledger::bill_shares;
+use super::super::pending::PendingRequest;
+use super::super::split::{SplitError, SplitMode};
+use super::super::transaction::TransactionType;
 use super::super::utils::*;
 
+use crate::journal::transact_in_group;
+
 use serenity::{
+    async_trait,
     builder::CreateApplicationCommand,
     client::Context,
     model::{
-        interactions::application_command::{
-            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
-            ApplicationCommandOptionType,
+        id::RoleId,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::MessageComponentInteraction,
         },
+        misc::Mentionable,
         prelude::User,
     },
 };
 
-use std::collections::HashMap;
-
-pub fn bill_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    let mut command = command
-        .name("bill")
-        .description("Bill user(s) for transaction")
-        .create_option(|option| {
-            option
-                .name("amount")
-                .description("Amount in dollars")
-                .kind(ApplicationCommandOptionType::String)
-                .required(true)
-        })
-        .create_option(|option| {
-            option
-                .name("description")
-                .description("Transaction description")
-                .kind(ApplicationCommandOptionType::String)
-                .required(true)
-        });
-
-    for i in 0..10 {
-        command = command.create_option(|option| {
-            option
-                .name(format!("user{}", i))
-                .description("User to bill")
-                .kind(ApplicationCommandOptionType::User)
-                .required(false)
-        })
+use std::{collections::BTreeSet, fmt::Write};
+
+pub struct BillCommand;
+
+#[async_trait]
+impl SlashCommand for BillCommand {
+    fn name(&self) -> &'static str {
+        "bill"
     }
-    command
-}
 
-pub async fn bill_handler(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResult, HandleCommandError> {
-    let mut amount = None;
-    let mut description = None;
-    let mut users = Vec::new();
-
-    for option in &command.data.options {
-        match option.name.as_ref() {
-            "amount" => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
-                    amount = Some(parse_money(value));
-                }
-                _ => return Err(HandleCommandError),
-            },
-            "description" => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
-                    description = Some(value);
-                }
-                _ => return Err(HandleCommandError),
-            },
-            _ => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
-                    users.push(user);
-                }
-                _ => return Err(HandleCommandError),
-            },
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        let mut command = command
+            .description("Bill user(s) for transaction")
+            .create_option(|option| {
+                option
+                    .name("amount")
+                    .description("Amount, optionally with a currency symbol or code")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("description")
+                    .description("Transaction description")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("split")
+                    .description(
+                        "How to divide the amount, defaults to billing each user the full amount",
+                    )
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("each user pays the full amount", "each")
+                    .add_string_choice("split evenly", "equal")
+                    .add_string_choice("split by shares", "shares")
+                    .add_string_choice("split by percentages", "percent")
+                    .add_string_choice("exact amounts", "exact")
+            })
+            .create_option(|option| {
+                option
+                    .name("values")
+                    .description(
+                        "Space separated shares, percentages or amounts for each user in order",
+                    )
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("include_self")
+                    .description("Include yourself in the split, listed before the other users")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("role")
+                    .description("Bill everyone with this role, listed after the other users")
+                    .kind(ApplicationCommandOptionType::Role)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("voice")
+                    .description(
+                        "Bill everyone in your voice channel, listed after the other users",
+                    )
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("group")
+                    .description("Bill everyone in a group, in the group's own balances")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            });
+
+        for i in 0..10 {
+            command = command.create_option(|option| {
+                option
+                    .name(format!("user{}", i))
+                    .description("User to bill")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(false)
+            })
         }
+        command
     }
 
-    if let Some(Ok(amount)) = amount {
-        if let Some(description) = description {
-            let accounts = match get_accounts_lock(ctx).await {
-                Ok(accounts_lock) => accounts_lock,
-                Err(_e) => return Err(HandleCommandError),
-            };
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let default_currency = get_default_currency(ctx, command.guild_id).await;
+
+        let mut amount = None;
+        let mut description = None;
+        let mut mode = None;
+        let mut values = None;
+        let mut include_self = false;
+        let mut role = None;
+        let mut voice = false;
+        let mut group = None;
+        let mut users = Vec::new();
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "amount" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        amount = Some(parse_amount(value, default_currency));
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "description" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        description = Some(value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "split" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        mode = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "values" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        values = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "include_self" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        include_self = *value;
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "role" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Role(value)) => {
+                        role = Some(value.id);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "voice" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        voice = *value;
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "group" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        group = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                name => match (&option.resolved, name.strip_prefix("user")) {
+                    (
+                        Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)),
+                        Some(index),
+                    ) => {
+                        users.push((index.parse::<usize>().unwrap_or(usize::MAX), user));
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+            }
+        }
 
-            let mut user_vec = Vec::<User>::new();
+        // values are matched to users in the order of their options, not the order they were entered
+        users.sort_by_key(|(index, _user)| *index);
+
+        // members of a role, channel or group can't be told apart by their order to be given values
+        if values.is_some() && (role.is_some() || voice || group.is_some()) {
+            return Err(HandleCommandError::invalid(
+                "values",
+                "values can only be given for user0 ... user9, not with a role, voice channel or group",
+            ));
+        }
 
+        let mut participants: Vec<&User> = users.into_iter().map(|(_index, user)| user).collect();
+        if include_self {
+            participants.insert(0, &command.user);
+        }
+        let mut participant_ids: Vec<UserId> =
+            participants.iter().map(|user| user.id.into()).collect();
+        distinct_users(&participant_ids, command.user.id.into(), include_self)?;
+
+        let group = match group {
+            Some(name) => Some(find_group(ctx, command, name).await?),
+            None => None,
+        };
+
+        // everyone else is resolved now, so the bill keeps who was in the role or channel at the time
+        let mut members = resolve_members(ctx, command, role, voice).await?;
+        if let Some((_name, group)) = &group {
+            members.extend(group.members.iter().copied());
+        }
+        add_members(
+            &mut participant_ids,
+            members,
+            command.user.id.into(),
+            include_self,
+        );
+
+        // a group's balances are only ever between its members
+        if let Some((name, group)) = &group {
+            if let Some(outsider) = std::iter::once(UserId::from(command.user.id))
+                .chain(participant_ids.iter().copied())
+                .find(|user| !group.members.contains(user))
             {
-                let mut accounts = accounts.write().await;
-                for receiver in &users {
-                    let receiver_entry = accounts.entry(receiver.id).or_insert(HashMap::new());
-                    *receiver_entry.entry(command.user.id).or_insert(0) -= amount;
+                return Err(HandleCommandError::Rejected(format!(
+                    "{} isn't a member of group {}, which only its members can bill or be billed in",
+                    outsider.mention(),
+                    name
+                )));
+            }
+        }
+        let group = group.map(|(name, _group)| name);
 
-                    let sender_entry = accounts.entry(command.user.id).or_insert(HashMap::new());
-                    *sender_entry.entry(receiver.id).or_insert(0) += amount;
+        let (amount, currency) = match amount {
+            Some(Ok(amount)) => amount,
+            Some(Err(e)) => return Err(HandleCommandError::invalid("amount", e)),
+            None => return Err(HandleCommandError::MissingOption("amount")),
+        };
+        let description = match description {
+            Some(description) => description,
+            None => return Err(HandleCommandError::MissingOption("description")),
+        };
 
-                    user_vec.push((*receiver).clone());
-                }
+        let mode = match SplitMode::parse(mode.unwrap_or("each"), values, currency) {
+            Ok(mode) => mode,
+            Err(e @ SplitError::UnknownMode(_)) => {
+                return Err(HandleCommandError::invalid("split", e))
+            }
+            Err(e) => return Err(HandleCommandError::invalid("values", e)),
+        };
+
+        if let Some(expires) = confirmation_expiry(ctx.data.clone(), command.guild_id).await {
+            let shares = match bill_shares(command.user.id.into(), &participant_ids, amount, &mode)
+            {
+                Ok(shares) => shares,
+                Err(e) => return Err(ledger_failed(e.into())),
+            };
+            if !shares.is_empty() {
+                let request = PendingRequest {
+                    group,
+                    ..PendingRequest::bill(
+                        ledger_scope(command.guild_id),
+                        command.user.id.into(),
+                        amount,
+                        currency,
+                        description,
+                        shares,
+                        expires,
+                    )
+                };
+                return request_confirmation(ctx, self.name(), request).await;
             }
+        }
+
+        let scope = ledger_scope(command.guild_id);
+        let result = transact_in_group(ctx.data.clone(), scope, group.as_deref(), |book| {
+            book.bill(
+                command.user.id.into(),
+                &participant_ids,
+                amount,
+                currency,
+                &mode,
+                description,
+            )
+        })
+        .await;
+        let (id, bill) = match result {
+            Ok(recorded) => recorded,
+            Err(e) => return Err(ledger_failed(e)),
+        };
 
-            let response = format!(
-                "{} billed {} to {} users for {}",
+        let in_group = in_group(group.as_deref());
+        let response = if let SplitMode::Each = mode {
+            format!(
+                "{} billed {} to {} users for {}{} (#{})",
                 command.user.tag(),
-                format_money(amount),
-                users.len(),
-                description
+                format_money(amount, currency),
+                bill.recipients.len(),
+                description,
+                in_group,
+                id
+            )
+        } else {
+            let mut response = format!(
+                "{} split {} {} between {} users for {}{} (#{})\n",
+                command.user.tag(),
+                format_money(amount, currency),
+                mode.name(),
+                participant_ids.len(),
+                description,
+                in_group,
+                id
             );
 
-            return Ok(CommandResult {
-                response,
-                transaction: TransactionType::Bill(BillTransaction {
-                    initiator: command.user.clone(),
-                    amount,
-                    recipients: user_vec,
-                    description: description.to_string(),
-                }),
-            });
+            for (recipient, share) in &bill.recipients {
+                let tag = match participants
+                    .iter()
+                    .find(|user| UserId::from(user.id) == *recipient)
+                {
+                    Some(user) => user.tag(),
+                    None => match ctx.cache.user(*recipient).await {
+                        Some(user) => user.tag(),
+                        None => recipient.to_string(),
+                    },
+                };
+                if let Err(e) = writeln!(
+                    response,
+                    "`{:<32}{:>16}`",
+                    tag,
+                    format_money(*share, currency)
+                ) {
+                    return Err(HandleCommandError::internal(e));
+                }
+            }
+
+            response
+        };
+
+        Ok(CommandResult {
+            response,
+            transaction: TransactionType::Bill(bill),
+            id: Some(id),
+            ..Default::default()
+        })
+    }
+
+    async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        answer_handler(ctx, component).await
+    }
+}
+
+/// Everyone with `role` and in the caller's voice channel if `voice`, as the guild cache has them
+/// when the command is run.
+async fn resolve_members(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    role: Option<RoleId>,
+    voice: bool,
+) -> Result<Vec<UserId>, HandleCommandError> {
+    if role.is_none() && !voice {
+        return Ok(Vec::new());
+    }
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            return Err(HandleCommandError::NotAllowed(
+                "Roles and voice channels can only be billed in a server".to_string(),
+            ))
+        }
+    };
+
+    let mut members = BTreeSet::new();
+
+    if let Some(role) = role {
+        // the @everyone role shares its id with the guild and isn't listed in members' roles
+        let found = ctx
+            .cache
+            .guild_field(guild_id, |guild| {
+                guild
+                    .members
+                    .values()
+                    .filter(|member| !member.user.bot)
+                    .filter(|member| role.0 == guild_id.0 || member.roles.contains(&role))
+                    .map(|member| UserId::from(member.user.id))
+                    .collect::<Vec<_>>()
+            })
+            .await;
+        match found {
+            Some(found) if !found.is_empty() => members.extend(found),
+            _ => {
+                return Err(HandleCommandError::Rejected(
+                    "No members of that role are known to the bot, which needs the server members \
+                     intent to see them"
+                        .to_string(),
+                ))
+            }
+        }
+    }
+
+    if voice {
+        let user_id = command.user.id;
+        let found = ctx
+            .cache
+            .guild_field(guild_id, |guild| {
+                let channel_id = guild.voice_states.get(&user_id)?.channel_id?;
+                Some(
+                    guild
+                        .voice_states
+                        .values()
+                        .filter(|state| state.channel_id == Some(channel_id))
+                        .filter(|state| {
+                            !state.member.as_ref().is_some_and(|member| member.user.bot)
+                        })
+                        .map(|state| UserId::from(state.user_id))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .await
+            .flatten();
+        match found {
+            Some(found) => members.extend(found),
+            None => {
+                return Err(HandleCommandError::Rejected(
+                    "You need to be in a voice channel to bill everyone in it".to_string(),
+                ))
+            }
         }
     }
 
-    Err(HandleCommandError)
+    Ok(members.into_iter().collect())
 }
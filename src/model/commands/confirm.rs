@@ -0,0 +1,430 @@
+use super::convert::convert_debts;
+use super::group::group_names;
+use super::{CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::convert::RateTable;
+use super::super::ids::GuildId;
+use super::super::pending::{Confirmation, PendingError, PendingRequest, PendingRequests};
+use super::super::transaction::{TransactionKind, TransactionType};
+use super::super::utils::*;
+
+use crate::journal::transact_in_group;
+use crate::pending::save_pending;
+use crate::settings::{exchange_rates, guild_settings, update_guild_settings, GuildSettings};
+
+use chrono::{DateTime, Utc};
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateComponents},
+    client::Context,
+    model::{
+        id,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::{ButtonStyle, MessageComponentInteraction},
+        },
+        misc::Mentionable,
+    },
+    prelude::TypeMap,
+};
+use tokio::sync::RwLock;
+
+use std::{fmt::Write, sync::Arc};
+
+/// Longest a request can wait for confirmation, a month.
+const MAX_CONFIRMATION_HOURS: i64 = 24 * 30;
+
+pub struct ConfirmationCommand;
+
+#[async_trait]
+impl SlashCommand for ConfirmationCommand {
+    fn name(&self) -> &'static str {
+        "confirmation"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or set whether /owe and /bill wait for the other users to accept")
+            .create_option(|option| {
+                option
+                    .name("required")
+                    .description("Hold transactions until the other users accept them")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("hours")
+                    .description("How long transactions wait to be accepted before expiring")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut required = None;
+        let mut hours = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "required" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        required = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "hours" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        if !(1..=MAX_CONFIRMATION_HOURS).contains(value) {
+                            return Err(HandleCommandError::invalid(
+                                "hours",
+                                format!("must be between 1 and {}", MAX_CONFIRMATION_HOURS),
+                            ));
+                        }
+                        hours = Some(*value as u32);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let scope = ledger_scope(command.guild_id);
+
+        if required.is_none() && hours.is_none() {
+            let settings = guild_settings(ctx.data.clone(), scope).await;
+            return Ok(CommandResult {
+                response: describe_confirmation(&settings),
+                ..Default::default()
+            });
+        }
+
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Confirmation can only be required in a server".to_string(),
+            ));
+        }
+        if !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only(
+                "change confirmation settings",
+            ));
+        }
+
+        if let Err(e) = update_guild_settings(ctx.data.clone(), scope, |settings| {
+            if let Some(required) = required {
+                settings.require_confirmation = required;
+            }
+            if let Some(hours) = hours {
+                settings.confirmation_hours = Some(hours);
+            }
+        })
+        .await
+        {
+            return Err(HandleCommandError::internal(format!(
+                "could not save settings: {}",
+                e
+            )));
+        }
+
+        let settings = guild_settings(ctx.data.clone(), scope).await;
+        Ok(CommandResult {
+            response: format!(
+                "{} updated confirmation: {}",
+                command.user.tag(),
+                describe_confirmation(&settings)
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+fn describe_confirmation(settings: &GuildSettings) -> String {
+    if settings.require_confirmation {
+        format!(
+            "/owe and /bill wait up to {} hours for the other users to accept",
+            settings.confirmation_hours()
+        )
+    } else {
+        "/owe and /bill are recorded without waiting for confirmation".to_string()
+    }
+}
+
+/// When a request made now in `guild_id` should expire, if the guild requires confirmation.
+pub async fn confirmation_expiry(
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: Option<impl Into<GuildId>>,
+) -> Option<DateTime<Utc>> {
+    let settings = guild_settings(data, guild_id?.into()).await;
+    settings
+        .confirmation_window()
+        .map(|window| Utc::now() + window)
+}
+
+/// Holds `request` until its parties answer it, showing them buttons to accept or dispute it.
+///
+/// `name` is the command the request was made with, which handles the buttons.
+pub async fn request_confirmation(
+    ctx: &Context,
+    name: &str,
+    request: PendingRequest,
+) -> Result<CommandResult, HandleCommandError> {
+    let pending = match get_pending_lock(ctx).await {
+        Ok(pending_lock) => pending_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let mut pending = pending.lock().await;
+    hold_for_confirmation(&mut pending, name, request)
+}
+
+/// Adds `request` to `pending`, returning the message asking its parties to answer it.
+pub fn hold_for_confirmation(
+    pending: &mut PendingRequests,
+    name: &str,
+    request: PendingRequest,
+) -> Result<CommandResult, HandleCommandError> {
+    let id = pending.insert(request.clone());
+    if let Err(e) = save_pending(pending) {
+        pending.remove(id);
+        return Err(HandleCommandError::internal(format!(
+            "could not save pending requests: {}",
+            e
+        )));
+    }
+
+    describe_request(name, id, &request)
+}
+
+/// Accepts or disputes the part of a pending request involving whoever pressed the button.
+///
+/// Accepted parts are recorded straight away, and the initiator is sent a message about disputes.
+pub async fn answer_handler(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+) -> Result<CommandResult, HandleCommandError> {
+    let (name, accept, id) = match parse_custom_id(&component.data.custom_id) {
+        Some(parsed) => parsed,
+        None => {
+            return Err(HandleCommandError::internal(format!(
+                "invalid confirmation button {}",
+                component.data.custom_id
+            )))
+        }
+    };
+
+    let pending_lock = match get_pending_lock(ctx).await {
+        Ok(pending_lock) => pending_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let mut pending = pending_lock.lock().await;
+
+    let request = match pending.get_mut(id) {
+        Some(request) => request,
+        None => {
+            return Err(HandleCommandError::Rejected(
+                "This request is no longer waiting for confirmation".to_string(),
+            ))
+        }
+    };
+
+    let user = component.user.id.into();
+    let now = Utc::now();
+
+    let (transaction, seq) = if accept {
+        let transaction = match request.accept(user, now) {
+            Ok(transaction) => transaction,
+            Err(e) => return Err(answer_failed(e)),
+        };
+        let (rates, groups) = match request.kind {
+            TransactionKind::Convert => (
+                exchange_rates(ctx.data.clone(), request.guild_id).await,
+                group_names(ctx, request.guild_id).await?,
+            ),
+            _ => (RateTable::default(), Vec::new()),
+        };
+        let result: Result<_, HandleCommandError> = transact_in_group(
+            ctx.data.clone(),
+            request.guild_id,
+            request.group.as_deref(),
+            |book| {
+                // a conversion converts whatever is owed between the two of them by now
+                let transaction = match transaction {
+                    TransactionType::Convert(convert) => TransactionType::Convert(convert_debts(
+                        book,
+                        convert.initiator,
+                        &[convert.initiator, user],
+                        convert.currency,
+                        &rates,
+                        &groups,
+                    )?),
+                    transaction => transaction,
+                };
+                let seq = book.record(&transaction)?;
+                Ok((transaction, seq))
+            },
+        )
+        .await;
+        let (transaction, seq) = result?;
+        request.answer(user, Confirmation::Accepted(seq));
+        (transaction, Some(seq))
+    } else {
+        if let Err(e) = request.dispute(user, now) {
+            return Err(answer_failed(e));
+        }
+        request.answer(user, Confirmation::Disputed);
+        notify_dispute(ctx, component, request).await;
+        (TransactionType::None, None)
+    };
+
+    let request = request.clone();
+    if request.is_answered() {
+        pending.remove(id);
+    }
+    if let Err(e) = save_pending(&pending) {
+        println!("Could not save pending requests: {}", e);
+    }
+
+    let mut result = describe_request(name, id, &request)?;
+    result.transaction = transaction;
+    result.id = seq;
+    Ok(result)
+}
+
+fn answer_failed(e: PendingError) -> HandleCommandError {
+    match e {
+        PendingError::NotAParty => HandleCommandError::NotAllowed(
+            "Only the users asked to confirm this can answer it".to_string(),
+        ),
+        PendingError::AlreadyAnswered => {
+            HandleCommandError::Rejected("You already answered this".to_string())
+        }
+        PendingError::Expired => {
+            HandleCommandError::Rejected("This request expired before it was answered".to_string())
+        }
+    }
+}
+
+/// Lets the initiator know their request was disputed, since they may not be watching the channel.
+async fn notify_dispute(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+    request: &PendingRequest,
+) {
+    let message = match request.kind {
+        TransactionKind::Convert => {
+            format!("{} disputed {}", component.user.tag(), request.description)
+        }
+        _ => format!(
+            "{} disputed your request for {} for {}",
+            component.user.tag(),
+            format_money(request.amount, request.currency),
+            request.description
+        ),
+    };
+
+    let sent = match id::UserId::from(request.initiator)
+        .create_dm_channel(&ctx.http)
+        .await
+    {
+        Ok(channel) => channel.say(&ctx.http, message).await.map(|_message| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = sent {
+        println!("Cannot notify {} of dispute: {}", request.initiator, e);
+    }
+}
+
+fn parse_custom_id(custom_id: &str) -> Option<(&str, bool, u64)> {
+    let mut fields = custom_id.split(':');
+    let name = fields.next()?;
+    let accept = match fields.next()? {
+        "accept" => true,
+        "dispute" => false,
+        _ => return None,
+    };
+    let id = fields.next()?.parse().ok()?;
+    Some((name, accept, id))
+}
+
+/// Lists each party's answer so far, with buttons to answer while any are still waiting.
+fn describe_request(
+    name: &str,
+    id: u64,
+    request: &PendingRequest,
+) -> Result<CommandResult, HandleCommandError> {
+    let amount = format_money(request.amount, request.currency);
+    let mut response = match request.kind {
+        TransactionKind::Owe => format!(
+            "{} says they owe {} for {}\n",
+            request.initiator.mention(),
+            amount,
+            request.description
+        ),
+        TransactionKind::Convert => format!(
+            "{} asks to convert the debts between you in other currencies into {}, so you would owe them:\n",
+            request.initiator.mention(),
+            request.currency
+        ),
+        _ => format!(
+            "{} billed {} for {}\n",
+            request.initiator.mention(),
+            amount,
+            request.description
+        ),
+    };
+
+    for &(party, share, confirmation) in &request.parties {
+        let answer = match confirmation {
+            Confirmation::Pending => "waiting".to_string(),
+            Confirmation::Accepted(seq) => format!("accepted (#{})", seq),
+            Confirmation::Disputed => "disputed".to_string(),
+        };
+        if let Err(e) = writeln!(
+            response,
+            "{} {}: {}",
+            party.mention(),
+            format_money(share, request.currency),
+            answer
+        ) {
+            return Err(HandleCommandError::internal(e));
+        }
+    }
+
+    let mut components = CreateComponents::default();
+    if !request.is_answered() {
+        response.push_str(&format!(
+            "Waiting for confirmation until <t:{}:f>",
+            request.expires.timestamp()
+        ));
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .style(ButtonStyle::Success)
+                    .label("Accept")
+                    .custom_id(format!("{}:accept:{}", name, id))
+            })
+            .create_button(|button| {
+                button
+                    .style(ButtonStyle::Danger)
+                    .label("Dispute")
+                    .custom_id(format!("{}:dispute:{}", name, id))
+            })
+        });
+    }
+
+    Ok(CommandResult {
+        response,
+        components: Some(components),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,415 @@
+use super::confirm::{answer_handler, request_confirmation};
+use super::group::group_names;
+use super::{currency_option, CommandResult, HandleCommandError, SlashCommand, MAX_MESSAGE_LENGTH};
+
+use super::super::accounts::ledger_scope;
+use super::super::convert::{conversion, has_debts_to_convert, ConvertError, Rate, RateTable};
+use super::super::currency::Currency;
+use super::super::ids::UserId;
+use super::super::ledger::Book;
+use super::super::pending::PendingRequest;
+use super::super::transaction::{ConvertTransaction, TransactionType};
+use super::super::utils::*;
+
+use crate::journal::transact;
+use crate::settings::{exchange_rates, guild_settings, update_guild_settings};
+
+use chrono::{Duration, Utc};
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::{
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::MessageComponentInteraction,
+        },
+        misc::Mentionable,
+    },
+};
+
+pub struct RateCommand;
+
+#[async_trait]
+impl SlashCommand for RateCommand {
+    fn name(&self) -> &'static str {
+        "rate"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or set the exchange rate between two currencies")
+            .create_option(|option| {
+                option
+                    .name("from")
+                    .description("Currency code to convert from")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("to")
+                    .description("Currency code to convert to")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("rate")
+                    .description("How much one unit of the first currency is worth in the second")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut from = None;
+        let mut to = None;
+        let mut rate = None;
+
+        for option in &command.data.options {
+            match (option.name.as_ref(), &option.resolved) {
+                ("from", Some(ApplicationCommandInteractionDataOptionValue::String(value))) => {
+                    from = Some(currency_option("from", value)?);
+                }
+                ("to", Some(ApplicationCommandInteractionDataOptionValue::String(value))) => {
+                    to = Some(currency_option("to", value)?);
+                }
+                ("rate", Some(ApplicationCommandInteractionDataOptionValue::String(value))) => {
+                    match Rate::parse(value) {
+                        Some(value) => rate = Some(value),
+                        None => {
+                            return Err(HandleCommandError::invalid(
+                                "rate",
+                                "rates are positive numbers with at most six decimal places",
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            (Some(_from), Some(_to)) => {
+                return Err(HandleCommandError::invalid(
+                    "to",
+                    "the currencies must be different",
+                ))
+            }
+            (None, _) => return Err(HandleCommandError::MissingOption("from")),
+            (_, None) => return Err(HandleCommandError::MissingOption("to")),
+        };
+
+        let scope = ledger_scope(command.guild_id);
+
+        let rate = match rate {
+            Some(rate) => rate,
+            None => {
+                let response = match exchange_rates(ctx.data.clone(), scope).await.get(from, to) {
+                    Some(rate) => format!("1 {} is worth {} {}", from, rate, to),
+                    None => format!("No exchange rate from {} to {} is set", from, to),
+                };
+                return Ok(CommandResult {
+                    response,
+                    ..Default::default()
+                });
+            }
+        };
+
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Exchange rates can only be set in a server".to_string(),
+            ));
+        }
+        if !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("set exchange rates"));
+        }
+
+        if let Err(e) = update_guild_settings(ctx.data.clone(), scope, |settings| {
+            settings.rates.set(from, to, rate)
+        })
+        .await
+        {
+            return Err(HandleCommandError::internal(format!(
+                "could not save settings: {}",
+                e
+            )));
+        }
+
+        Ok(CommandResult {
+            response: format!(
+                "{} set the exchange rate to 1 {} = {} {}",
+                command.user.tag(),
+                from,
+                rate,
+                to
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct ConvertCommand;
+
+#[async_trait]
+impl SlashCommand for ConvertCommand {
+    fn name(&self) -> &'static str {
+        "convert"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Convert debts in other currencies into one currency")
+            .create_option(|option| {
+                option
+                    .name("currency")
+                    .description("Currency code to convert into")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("everyone")
+                    .description(
+                        "Convert everyone's debts instead of only yours, for administrators",
+                    )
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut target = None;
+        let mut everyone = false;
+
+        for option in &command.data.options {
+            match (option.name.as_ref(), &option.resolved) {
+                ("currency", Some(ApplicationCommandInteractionDataOptionValue::String(value))) => {
+                    target = Some(currency_option("currency", value)?);
+                }
+                (
+                    "everyone",
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)),
+                ) => {
+                    everyone = *value;
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let target = match target {
+            Some(target) => target,
+            None => return Err(HandleCommandError::MissingOption("currency")),
+        };
+
+        if everyone && !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("convert everyone's debts"));
+        }
+
+        let scope = ledger_scope(command.guild_id);
+        let rates = exchange_rates(ctx.data.clone(), scope).await;
+        let groups = group_names(ctx, scope).await?;
+
+        if !everyone {
+            return request_conversion(ctx, command, target, &rates, &groups).await;
+        }
+
+        let result: Result<_, HandleCommandError> = transact(ctx.data.clone(), scope, |book| {
+            let transaction =
+                convert_debts(book, command.user.id.into(), &[], target, &rates, &groups)?;
+            let id = book.record(&TransactionType::Convert(transaction.clone()))?;
+            Ok((id, transaction))
+        })
+        .await;
+        let (id, transaction) = result?;
+
+        let mut response = format!(
+            "{} converted {} debts into {} (#{}):\n",
+            command.user.tag(),
+            transaction.transfers.len() / 2,
+            target,
+            id
+        );
+        // each debt is cancelled in its old currency and then recorded in the new one
+        let lines: Vec<String> = transaction
+            .transfers
+            .chunks(2)
+            .filter_map(|pair| match pair {
+                [old, new] => Some(format!(
+                    "`{:>16}{:>16}` {} to {}",
+                    format_money(-old.amount, old.currency),
+                    format_money(new.amount, new.currency),
+                    new.debtor.mention(),
+                    new.creditor.mention()
+                )),
+                _ => None,
+            })
+            .collect();
+        for (i, line) in lines.iter().enumerate() {
+            let more = format!("...and {} more", lines.len() - i);
+            if response.len() + line.len() + 1 + more.len() > MAX_MESSAGE_LENGTH {
+                response.push_str(&more);
+                break;
+            }
+            response.push_str(line);
+            response.push('\n');
+        }
+
+        Ok(CommandResult {
+            response,
+            transaction: TransactionType::Convert(transaction),
+            id: Some(id),
+            ..Default::default()
+        })
+    }
+
+    async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        answer_handler(ctx, component).await
+    }
+}
+
+/// Asks everyone the caller has debts in other currencies with to agree to converting them, as
+/// the conversion changes what they owe or are owed too.
+async fn request_conversion(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    target: Currency,
+    rates: &RateTable,
+    groups: &[String],
+) -> Result<CommandResult, HandleCommandError> {
+    let accounts = match get_accounts_lock(ctx).await {
+        Ok(accounts_lock) => accounts_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+
+    let scope = ledger_scope(command.guild_id);
+    let user = UserId::from(command.user.id);
+    transact(ctx.data.clone(), scope, |book| {
+        check_groups(book, &[user], target, groups)
+    })
+    .await?;
+
+    // only a preview, as each part is converted again at the balances when it's accepted
+    let transfers = match accounts.read().await.get(&scope) {
+        Some(ledgers) => match conversion(ledgers, &[user], target, rates) {
+            Ok(transfers) => transfers,
+            Err(e) => return Err(convert_failed(e)),
+        },
+        None => Vec::new(),
+    };
+
+    let mut parties: Vec<(UserId, Money)> = Vec::new();
+    for transfer in transfers
+        .iter()
+        .filter(|transfer| transfer.currency == target)
+    {
+        let (party, owed) = if transfer.creditor == user {
+            (transfer.debtor, transfer.amount)
+        } else {
+            (transfer.creditor, -transfer.amount)
+        };
+        match parties.iter_mut().find(|(other, _owed)| *other == party) {
+            Some((_other, total)) => match total.checked_add(owed) {
+                Some(sum) => *total = sum,
+                None => return Err(convert_failed(ConvertError::Overflow)),
+            },
+            None => parties.push((party, owed)),
+        }
+    }
+
+    if parties.is_empty() {
+        return Ok(CommandResult {
+            response: format!("There are no debts to convert into {}", target),
+            ..Default::default()
+        });
+    }
+
+    let hours = guild_settings(ctx.data.clone(), scope)
+        .await
+        .confirmation_hours();
+    let request = PendingRequest::conversion(
+        scope,
+        user,
+        target,
+        parties,
+        Utc::now() + Duration::hours(hours.into()),
+    );
+    request_confirmation(ctx, "convert", request).await
+}
+
+/// Converts the debts in `book` involving every one of `users` into `target`, as `initiator`.
+///
+/// `groups` are the guild's groups, none of which may hold any of the debts.
+pub fn convert_debts(
+    book: &mut Book,
+    initiator: UserId,
+    users: &[UserId],
+    target: Currency,
+    rates: &RateTable,
+    groups: &[String],
+) -> Result<ConvertTransaction, HandleCommandError> {
+    check_groups(book, users, target, groups)?;
+    let transfers = match conversion(&book.ledgers()?, users, target, rates) {
+        Ok(transfers) => transfers,
+        Err(e) => return Err(convert_failed(e)),
+    };
+    if transfers.is_empty() {
+        return Err(HandleCommandError::Rejected(format!(
+            "There are no debts to convert into {}",
+            target
+        )));
+    }
+
+    Ok(ConvertTransaction {
+        initiator,
+        currency: target,
+        transfers,
+    })
+}
+
+/// Rejects converting debts that were recorded in a group, as the conversion is only recorded in
+/// the guild's balances and the group's would be left in the old currency.
+fn check_groups(
+    book: &mut Book,
+    users: &[UserId],
+    target: Currency,
+    groups: &[String],
+) -> Result<(), HandleCommandError> {
+    for group in groups {
+        if has_debts_to_convert(&book.group_ledgers(group)?, users, target) {
+            return Err(HandleCommandError::Rejected(format!(
+                "Could not convert debts: some were recorded in group {}, settle them there first",
+                group
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn convert_failed(e: ConvertError) -> HandleCommandError {
+    HandleCommandError::Rejected(format!("Could not convert debts: {}", e))
+}
@@ -0,0 +1,102 @@
+use super::{currency_option, CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::utils::*;
+
+use crate::settings::update_guild_settings;
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+pub struct CurrencyCommand;
+
+#[async_trait]
+impl SlashCommand for CurrencyCommand {
+    fn name(&self) -> &'static str {
+        "currency"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or set the currency of amounts entered without one")
+            .create_option(|option| {
+                option
+                    .name("code")
+                    .description("ISO 4217 currency code, such as EUR or JPY")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut currency = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "code" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        currency = Some(currency_option("code", value)?);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let currency = match currency {
+            Some(currency) => currency,
+            None => {
+                let currency = get_default_currency(ctx, command.guild_id).await;
+                return Ok(CommandResult {
+                    response: format!("Amounts without a currency are in {}", currency),
+                    ..Default::default()
+                });
+            }
+        };
+
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "The default currency can only be set in a server".to_string(),
+            ));
+        }
+        if !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("set the default currency"));
+        }
+
+        if let Err(e) = update_guild_settings(
+            ctx.data.clone(),
+            ledger_scope(command.guild_id),
+            |settings| settings.currency = Some(currency),
+        )
+        .await
+        {
+            return Err(HandleCommandError::internal(format!(
+                "could not save settings: {}",
+                e
+            )));
+        }
+
+        Ok(CommandResult {
+            response: format!(
+                "{} set the default currency to {}",
+                command.user.tag(),
+                currency
+            ),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use super::{CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::utils::*;
+
+use crate::settings::{guild_settings, update_guild_settings};
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+pub struct EmbedsCommand;
+
+#[async_trait]
+impl SlashCommand for EmbedsCommand {
+    fn name(&self) -> &'static str {
+        "embeds"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or set whether responses use embeds or plain text")
+            .create_option(|option| {
+                option
+                    .name("enabled")
+                    .description("Use embeds, or plain text for servers where embeds are disabled")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut enabled = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "enabled" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        enabled = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let scope = ledger_scope(command.guild_id);
+
+        let enabled = match enabled {
+            Some(enabled) => enabled,
+            None => {
+                let plain_text = guild_settings(ctx.data.clone(), scope).await.plain_text;
+                return Ok(CommandResult {
+                    response: format!("Responses use {}", response_style(!plain_text)),
+                    ..Default::default()
+                });
+            }
+        };
+
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Embeds can only be turned on or off in a server".to_string(),
+            ));
+        }
+        if !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("turn embeds on or off"));
+        }
+
+        if let Err(e) = update_guild_settings(ctx.data.clone(), scope, |settings| {
+            settings.plain_text = !enabled
+        })
+        .await
+        {
+            return Err(HandleCommandError::internal(format!(
+                "could not save settings: {}",
+                e
+            )));
+        }
+
+        Ok(CommandResult {
+            response: format!(
+                "{} set responses to use {}",
+                command.user.tag(),
+                response_style(enabled)
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+fn response_style(embeds: bool) -> &'static str {
+    if embeds {
+        "embeds"
+    } else {
+        "plain text"
+    }
+}
@@ -0,0 +1,345 @@
+use super::{CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::groups::{group_name, Group, GroupError};
+use super::super::ids::{GuildId, UserId};
+use super::super::utils::*;
+
+use crate::groups::save_groups;
+
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateApplicationCommandOption},
+    client::Context,
+    model::{
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            ApplicationCommandOptionType,
+        },
+        misc::Mentionable,
+    },
+};
+
+use std::fmt::Write;
+
+pub struct GroupCommand;
+
+#[async_trait]
+impl SlashCommand for GroupCommand {
+    fn name(&self) -> &'static str {
+        "group"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Save groups of users to bill together")
+            .create_option(|option| {
+                users_option(
+                    name_option(option, "create", "Create a group, with you as a member"),
+                    false,
+                )
+            })
+            .create_option(|option| {
+                users_option(name_option(option, "add", "Add users to a group"), true)
+            })
+            .create_option(|option| {
+                name_option(option, "remove", "Remove a user from a group").create_sub_option(
+                    |option| {
+                        option
+                            .name("user")
+                            .description("User to remove, defaults to you")
+                            .kind(ApplicationCommandOptionType::User)
+                            .required(false)
+                    },
+                )
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("List this server's groups, or the members of one")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("name")
+                            .description("Name of the group")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(false)
+                    })
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Groups can only be made in a server".to_string(),
+            ));
+        }
+
+        let subcommand = match command.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Err(HandleCommandError::internal("missing subcommand")),
+        };
+
+        let mut name = None;
+        let mut users = Vec::new();
+
+        for option in &subcommand.options {
+            match option.name.as_ref() {
+                "name" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        name = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                option_name if option_name.starts_with("user") => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
+                        users.push(UserId::from(user.id));
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let groups = match get_groups_lock(ctx).await {
+            Ok(groups_lock) => groups_lock,
+            Err(e) => return Err(HandleCommandError::internal(e)),
+        };
+        let mut groups = groups.lock().await;
+        let guild_id = ledger_scope(command.guild_id);
+
+        if subcommand.name == "list" {
+            return list(
+                &groups.in_guild(guild_id),
+                name.map(|name| (name, groups.get(guild_id, name))),
+            );
+        }
+
+        let name = match name {
+            Some(name) => name,
+            None => return Err(HandleCommandError::MissingOption("name")),
+        };
+
+        // changes are made to a copy, kept only once it has been saved
+        let mut updated = groups.clone();
+        let caller = UserId::from(command.user.id);
+        let response = match subcommand.name.as_ref() {
+            "create" => {
+                let members = std::iter::once(caller).chain(users);
+                let name = match updated.create(guild_id, name, caller, members) {
+                    Ok(name) => name,
+                    Err(e) => return Err(group_failed(e)),
+                };
+                format!("{} created group {}", command.user.tag(), name)
+            }
+            "add" | "remove" => {
+                let group = match updated.get_mut(guild_id, name) {
+                    Ok(group) => group,
+                    Err(e) => return Err(group_failed(e)),
+                };
+                let may_change = group.owner == caller || is_admin(command.member.as_ref());
+
+                if subcommand.name == "add" {
+                    if !may_change {
+                        return Err(not_owner(name));
+                    }
+                    let added = users
+                        .into_iter()
+                        .filter(|&user| group.members.insert(user))
+                        .count();
+                    format!(
+                        "{} added {} users to group {}",
+                        command.user.tag(),
+                        added,
+                        name
+                    )
+                } else {
+                    let user = users.first().copied().unwrap_or(caller);
+                    // anyone can leave a group, but only its owner can remove others
+                    if user != caller && !may_change {
+                        return Err(not_owner(name));
+                    }
+                    if !group.members.remove(&user) {
+                        return Err(HandleCommandError::Rejected(format!(
+                            "{} is not in group {}",
+                            user.mention(),
+                            name
+                        )));
+                    }
+                    format!(
+                        "{} removed {} from group {}",
+                        command.user.tag(),
+                        user.mention(),
+                        name
+                    )
+                }
+            }
+            _ => return Err(HandleCommandError::unexpected_option(&subcommand.name)),
+        };
+
+        if let Err(e) = save_groups(&updated) {
+            return Err(HandleCommandError::internal(format!(
+                "could not save groups: {}",
+                e
+            )));
+        }
+        *groups = updated;
+
+        Ok(CommandResult {
+            response,
+            ..Default::default()
+        })
+    }
+}
+
+fn name_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(ApplicationCommandOptionType::SubCommand)
+        .create_sub_option(|option| {
+            option
+                .name("name")
+                .description("Name of the group")
+                .kind(ApplicationCommandOptionType::String)
+                .required(true)
+        })
+}
+
+fn users_option(
+    mut option: &mut CreateApplicationCommandOption,
+    required: bool,
+) -> &mut CreateApplicationCommandOption {
+    for i in 0..10 {
+        option = option.create_sub_option(|option| {
+            option
+                .name(format!("user{}", i))
+                .description("User to add to the group")
+                .kind(ApplicationCommandOptionType::User)
+                .required(required && i == 0)
+        })
+    }
+    option
+}
+
+/// Lists the groups in a guild, or the members of the one named.
+fn list(
+    groups: &[(&str, &Group)],
+    named: Option<(&str, Result<&Group, GroupError>)>,
+) -> Result<CommandResult, HandleCommandError> {
+    let mut response = String::new();
+
+    match named {
+        Some((_name, Err(e))) => return Err(group_failed(e)),
+        Some((name, Ok(group))) => {
+            response = format!(
+                "Group {} of {} members, owned by {}:",
+                name,
+                group.members.len(),
+                group.owner.mention()
+            );
+            for member in &group.members {
+                if let Err(e) = write!(response, " {}", member.mention()) {
+                    return Err(HandleCommandError::internal(e));
+                }
+            }
+        }
+        None if groups.is_empty() => response = "There are no groups here".to_string(),
+        None => {
+            for (name, group) in groups {
+                if let Err(e) = writeln!(
+                    response,
+                    "{}: {} members, owned by {}",
+                    name,
+                    group.members.len(),
+                    group.owner.mention()
+                ) {
+                    return Err(HandleCommandError::internal(e));
+                }
+            }
+        }
+    }
+
+    Ok(CommandResult {
+        response,
+        ..Default::default()
+    })
+}
+
+fn not_owner(name: &str) -> HandleCommandError {
+    HandleCommandError::NotAllowed(format!(
+        "Only whoever created group {} or an administrator can change it",
+        name
+    ))
+}
+
+/// Why a command naming a group failed.
+pub fn group_failed(e: GroupError) -> HandleCommandError {
+    match e {
+        GroupError::InvalidName(_) => HandleCommandError::invalid("name", e),
+        GroupError::AlreadyExists(_) | GroupError::NotFound(_) => {
+            HandleCommandError::Rejected(e.to_string())
+        }
+    }
+}
+
+/// Where a command scoped to `group` took effect, to follow what it says it did.
+pub fn in_group(group: Option<&str>) -> String {
+    match group {
+        Some(name) => format!(" in group {}", name),
+        None => String::new(),
+    }
+}
+
+/// The group `name` as it is stored and its members, for commands scoped to a group.
+pub async fn find_group(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    name: &str,
+) -> Result<(String, Group), HandleCommandError> {
+    let groups = match get_groups_lock(ctx).await {
+        Ok(groups_lock) => groups_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let groups = groups.lock().await;
+
+    let found = group_name(name).and_then(|name| {
+        let group = groups.get(ledger_scope(command.guild_id), &name)?.clone();
+        Ok((name, group))
+    });
+    match found {
+        Ok(found) => Ok(found),
+        Err(e @ GroupError::InvalidName(_)) => Err(HandleCommandError::invalid("group", e)),
+        Err(e) => Err(group_failed(e)),
+    }
+}
+
+/// The names of the groups in `guild_id`, whose sub-ledgers commands on the whole guild must
+/// keep in step with.
+pub async fn group_names(
+    ctx: &Context,
+    guild_id: GuildId,
+) -> Result<Vec<String>, HandleCommandError> {
+    let groups = match get_groups_lock(ctx).await {
+        Ok(groups_lock) => groups_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let groups = groups.lock().await;
+
+    Ok(groups
+        .in_guild(guild_id)
+        .into_iter()
+        .map(|(name, _group)| name.to_string())
+        .collect())
+}
@@ -0,0 +1,358 @@
+use super::{read_failed, CommandResult, HandleCommandError, SlashCommand, MAX_MESSAGE_LENGTH};
+
+use super::super::accounts::ledger_scope;
+use super::super::currency::Currency;
+use super::super::ids::{GuildId, UserId};
+use super::super::journal::EntryFilter;
+use super::super::ledger::history;
+use super::super::transaction::TransactionKind;
+use super::super::utils::*;
+
+use crate::settings::user_settings;
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateComponents},
+    client::Context,
+    model::{
+        id,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::{ButtonStyle, MessageComponentInteraction},
+        },
+    },
+};
+
+use std::{collections::BTreeMap, fmt::Write};
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 25;
+
+/// Everything needed to render one page of history, encoded into the paging buttons.
+struct HistoryQuery {
+    user: UserId,
+    counterparty: Option<UserId>,
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+    /// Newest journal entry included, so later transactions don't shift pages while browsing.
+    upto: u64,
+    page: usize,
+}
+
+impl HistoryQuery {
+    fn custom_id(&self, page: usize) -> String {
+        format!(
+            "history:{}:{}:{}:{}:{}:{}",
+            self.user,
+            self.counterparty.map_or(0, |user| user.0),
+            self.since.map_or(0, |since| since.timestamp()),
+            self.limit,
+            self.upto,
+            page
+        )
+    }
+
+    fn parse(custom_id: &str) -> Option<HistoryQuery> {
+        let mut fields = custom_id.strip_prefix("history:")?.split(':');
+        let mut next = || fields.next()?.parse::<u64>().ok();
+
+        let user = UserId(next()?);
+        let counterparty = Some(next()?).filter(|&id| id != 0).map(UserId);
+        let since = match next()? {
+            0 => None,
+            timestamp => Some(Utc.timestamp_opt(timestamp as i64, 0).single()?),
+        };
+        let limit = (next()? as usize).clamp(1, MAX_LIMIT);
+        let upto = next()?;
+        let page = next()? as usize;
+
+        Some(HistoryQuery {
+            user,
+            counterparty,
+            since,
+            limit,
+            upto,
+            page,
+        })
+    }
+}
+
+pub struct HistoryCommand;
+
+#[async_trait]
+impl SlashCommand for HistoryCommand {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("List your transactions")
+            .create_option(|option| {
+                option
+                    .name("user")
+                    .description("Only show transactions with this user")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("since")
+                    .description("Only show transactions on or after this date (YYYY-MM-DD)")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("limit")
+                    .description("Transactions per page")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut counterparty = None;
+        let mut since = None;
+        let mut limit = DEFAULT_LIMIT;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "user" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
+                        counterparty = Some(user.id.into());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "since" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                            // the paging buttons only hold dates from 1970 on
+                            Ok(date) if date.year() < 1970 => {
+                                return Err(HandleCommandError::invalid(
+                                    "since",
+                                    "dates before 1970 aren't supported",
+                                ))
+                            }
+                            Ok(date) => {
+                                since = date
+                                    .and_hms_opt(0, 0, 0)
+                                    .map(|since| Utc.from_utc_datetime(&since));
+                            }
+                            Err(_e) => {
+                                return Err(HandleCommandError::invalid(
+                                    "since",
+                                    "dates are written as YYYY-MM-DD, like 2024-01-31",
+                                ))
+                            }
+                        }
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "limit" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        limit = (*value).clamp(1, MAX_LIMIT as i64) as usize;
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let journal = match get_journal_lock(ctx).await {
+            Ok(journal_lock) => journal_lock,
+            Err(e) => return Err(HandleCommandError::internal(e)),
+        };
+        let upto = match journal.lock().await.last_seq() {
+            Ok(upto) => upto,
+            Err(e) => return Err(read_failed(e)),
+        };
+
+        let query = HistoryQuery {
+            user: command.user.id.into(),
+            counterparty,
+            since,
+            limit,
+            upto,
+            page: 0,
+        };
+
+        let mut result = render_history(ctx, ledger_scope(command.guild_id), &query).await?;
+        result.ephemeral = !user_settings(ctx.data.clone(), command.user.id.into())
+            .await
+            .public;
+
+        Ok(result)
+    }
+
+    /// Shows another page of a history listing, only for the user it belongs to.
+    async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let query = match HistoryQuery::parse(&component.data.custom_id) {
+            Some(query) if query.user == UserId::from(component.user.id) => query,
+            Some(_query) => {
+                return Err(HandleCommandError::NotAllowed(
+                    "Only whoever asked for this history can change its page".to_string(),
+                ))
+            }
+            None => {
+                return Err(HandleCommandError::internal(format!(
+                    "invalid history button {}",
+                    component.data.custom_id
+                )))
+            }
+        };
+
+        render_history(ctx, ledger_scope(component.guild_id), &query).await
+    }
+}
+
+async fn render_history(
+    ctx: &Context,
+    scope: GuildId,
+    query: &HistoryQuery,
+) -> Result<CommandResult, HandleCommandError> {
+    let journal = match get_journal_lock(ctx).await {
+        Ok(journal_lock) => journal_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+
+    let filter = EntryFilter {
+        guild_id: Some(scope),
+        involving: Some(query.user),
+        counterparty: query.counterparty,
+        since: query.since,
+        upto: Some(query.upto),
+        ..Default::default()
+    };
+
+    let page = match history(
+        journal.lock().await.as_ref(),
+        &filter,
+        query.page,
+        query.limit,
+    ) {
+        Ok(page) => page,
+        Err(e) => return Err(read_failed(e)),
+    };
+
+    let mut response = match query.counterparty {
+        Some(other) => match id::UserId::from(other).to_user(ctx).await {
+            Ok(other) => format!("Transactions with {}", other.tag()),
+            Err(e) => {
+                return Err(HandleCommandError::internal(format!(
+                    "could not fetch user: {}",
+                    e
+                )))
+            }
+        },
+        None => "Transactions".to_string(),
+    };
+    if let Err(e) = writeln!(response, " (page {} of {}):", page.page + 1, page.pages) {
+        return Err(HandleCommandError::internal(e));
+    }
+
+    if page.entries.is_empty() {
+        response.push_str("No transactions found");
+    }
+
+    let mut lines = Vec::new();
+    for (entry, voided) in &page.entries {
+        let date = entry.timestamp.map_or("unknown".to_string(), |timestamp| {
+            timestamp.format("%Y-%m-%d").to_string()
+        });
+
+        let mut summary = match entry.kind {
+            TransactionKind::Owe => "owe",
+            TransactionKind::Bill => "bill",
+            TransactionKind::Settle => "settle",
+            TransactionKind::Void => "void",
+            TransactionKind::Simplify => "simplify",
+            TransactionKind::Convert => "convert",
+        }
+        .to_string();
+        if let Some(description) = &entry.description {
+            summary = format!("{} \"{}\"", summary, description);
+        }
+        if let Some(original) = entry.voids {
+            summary = format!("{} #{}", summary, original);
+        }
+        if *voided {
+            summary.push_str(" (voided)");
+        }
+
+        let net = match entry.net_for(query.user, query.counterparty) {
+            Ok(net) => format_net(&net),
+            Err(e) => e.to_string(),
+        };
+
+        lines.push(format!(
+            "`#{:<6}{:<12}{:>16}` {}",
+            entry.seq, date, net, summary
+        ));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let more = format!("...and {} more", lines.len() - i);
+        if response.len() + line.len() + 1 + more.len() > MAX_MESSAGE_LENGTH {
+            response.push_str(&more);
+            break;
+        }
+        response.push_str(line);
+        response.push('\n');
+    }
+
+    let mut components = CreateComponents::default();
+    if page.pages > 1 {
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .style(ButtonStyle::Secondary)
+                    .label("Previous")
+                    .custom_id(query.custom_id(page.page.saturating_sub(1)))
+                    .disabled(page.page == 0)
+            })
+            .create_button(|button| {
+                button
+                    .style(ButtonStyle::Secondary)
+                    .label("Next")
+                    .custom_id(query.custom_id(page.page + 1))
+                    .disabled(page.page + 1 >= page.pages)
+            })
+        });
+    }
+
+    Ok(CommandResult {
+        response,
+        components: Some(components),
+        ..Default::default()
+    })
+}
+
+/// Lists the non-zero amounts in each currency, or a zero amount if there are none.
+fn format_net(net: &BTreeMap<Currency, Money>) -> String {
+    let amounts: Vec<String> = net
+        .iter()
+        .filter(|(_currency, &amount)| amount != Money::ZERO)
+        .map(|(&currency, &amount)| format_money(amount, currency))
+        .collect();
+
+    if amounts.is_empty() {
+        format_money(Money::ZERO, net.keys().next().copied().unwrap_or_default())
+    } else {
+        amounts.join(", ")
+    }
+}
@@ -1,118 +1,166 @@
-use super::{CommandResult, HandleCommandError, OweTransaction, TransactionType};
+use super::confirm::{answer_handler, confirmation_expiry, request_confirmation};
+use super::{ledger_failed, CommandResult, HandleCommandError, SlashCommand};
 
+use super::super::accounts::ledger_scope;
+use super::super::pending::{Confirmation, PendingRequest};
+use super::super::transaction::{TransactionKind, TransactionType};
 use super::super::utils::*;
 
+use crate::journal::transact;
+
 use serenity::{
+    async_trait,
     builder::CreateApplicationCommand,
     client::Context,
-    model::interactions::application_command::{
-        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
-        ApplicationCommandOptionType,
+    model::interactions::{
+        application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            ApplicationCommandOptionType,
+        },
+        message_component::MessageComponentInteraction,
     },
 };
 
-use std::collections::HashMap;
-
-pub fn owe_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command
-        .name("owe")
-        .description("Owe a user")
-        .create_option(|option| {
-            option
-                .name("amount")
-                .description("Amount in dollars")
-                .kind(ApplicationCommandOptionType::String)
-                .required(true)
-        })
-        .create_option(|option| {
-            option
-                .name("description")
-                .description("Transaction description")
-                .kind(ApplicationCommandOptionType::String)
-                .required(true)
-        })
-        .create_option(|option| {
-            option
-                .name("user")
-                .description("User to owe")
-                .kind(ApplicationCommandOptionType::User)
-                .required(true)
-        })
-}
+pub struct OweCommand;
 
-pub async fn owe_handler(
-    ctx: &Context,
-    command: &ApplicationCommandInteraction,
-) -> Result<CommandResult, HandleCommandError> {
-    let mut amount = None;
-    let mut description = None;
-    let mut user_opt = None;
+#[async_trait]
+impl SlashCommand for OweCommand {
+    fn name(&self) -> &'static str {
+        "owe"
+    }
 
-    for option in &command.data.options {
-        match option.name.as_ref() {
-            "amount" => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
-                    amount = Some(parse_money(value));
-                }
-                _ => return Err(HandleCommandError),
-            },
-            "description" => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
-                    description = Some(value);
-                }
-                _ => return Err(HandleCommandError),
-            },
-            "user" => match &option.resolved {
-                Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
-                    user_opt = Some(user);
-                }
-                _ => return Err(HandleCommandError),
-            },
-            _ => return Err(HandleCommandError),
-        }
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Owe a user")
+            .create_option(|option| {
+                option
+                    .name("amount")
+                    .description("Amount, optionally with a currency symbol or code")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("description")
+                    .description("Transaction description")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("user")
+                    .description("User to owe")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(true)
+            })
     }
 
-    if let Some(Ok(amount)) = amount {
-        if let Some(description) = description {
-            if let Some(receiver) = user_opt {
-                let accounts = match get_accounts_lock(ctx).await {
-                    Ok(accounts_lock) => accounts_lock,
-                    Err(_e) => return Err(HandleCommandError),
-                };
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let default_currency = get_default_currency(ctx, command.guild_id).await;
+
+        let mut amount = None;
+        let mut description = None;
+        let mut user_opt = None;
 
-                {
-                    let mut accounts = accounts.write().await;
-                    {
-                        let receiver_entry = accounts.entry(receiver.id).or_insert(HashMap::new());
-                        *receiver_entry.entry(command.user.id).or_insert(0) += amount;
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "amount" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        amount = Some(parse_amount(value, default_currency));
                     }
-                    {
-                        let sender_entry =
-                            accounts.entry(command.user.id).or_insert(HashMap::new());
-                        *sender_entry.entry(receiver.id).or_insert(0) -= amount;
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "description" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        description = Some(value);
                     }
-                }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "user" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
+                        user_opt = Some(user);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
 
-                let response = format!(
-                    "{} owes {} to {} for {}",
-                    command.user.tag(),
-                    format_money(amount),
-                    receiver.tag(),
-                    description
-                );
+        let (amount, currency) = match amount {
+            Some(Ok(amount)) => amount,
+            Some(Err(e)) => return Err(HandleCommandError::invalid("amount", e)),
+            None => return Err(HandleCommandError::MissingOption("amount")),
+        };
+        let description = match description {
+            Some(description) => description,
+            None => return Err(HandleCommandError::MissingOption("description")),
+        };
+        let receiver = match user_opt {
+            Some(receiver) => receiver,
+            None => return Err(HandleCommandError::MissingOption("user")),
+        };
 
-                return Ok(CommandResult {
-                    response,
-                    transaction: TransactionType::Owe(OweTransaction {
-                        initiator: command.user.clone(),
-                        amount,
-                        recipient: receiver.clone(),
-                        description: description.to_string(),
-                    }),
-                });
+        if receiver.id != command.user.id {
+            if let Some(expires) = confirmation_expiry(ctx.data.clone(), command.guild_id).await {
+                let request = PendingRequest {
+                    guild_id: ledger_scope(command.guild_id),
+                    group: None,
+                    kind: TransactionKind::Owe,
+                    initiator: command.user.id.into(),
+                    amount,
+                    currency,
+                    description: description.to_string(),
+                    expires,
+                    parties: vec![(receiver.id.into(), amount, Confirmation::Pending)],
+                };
+                return request_confirmation(ctx, self.name(), request).await;
             }
         }
+
+        let result = transact(ctx.data.clone(), ledger_scope(command.guild_id), |book| {
+            book.owe(
+                command.user.id.into(),
+                receiver.id.into(),
+                amount,
+                currency,
+                description,
+            )
+        })
+        .await;
+        let (id, transaction) = match result {
+            Ok(recorded) => recorded,
+            Err(e) => return Err(ledger_failed(e)),
+        };
+
+        let response = format!(
+            "{} owes {} to {} for {} (#{})",
+            command.user.tag(),
+            format_money(amount, currency),
+            receiver.tag(),
+            description,
+            id
+        );
+
+        Ok(CommandResult {
+            response,
+            transaction: TransactionType::Owe(transaction),
+            id: Some(id),
+            ..Default::default()
+        })
     }
 
-    Err(HandleCommandError)
+    async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        answer_handler(ctx, component).await
+    }
 }
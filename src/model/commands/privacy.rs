@@ -0,0 +1,112 @@
+use super::{CommandResult, HandleCommandError, SlashCommand};
+
+use crate::settings::{update_user_settings, user_settings, UserSettings};
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+pub struct PrivacyCommand;
+
+#[async_trait]
+impl SlashCommand for PrivacyCommand {
+    fn name(&self) -> &'static str {
+        "privacy"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or choose who can see your balance and history")
+            .create_option(|option| {
+                option
+                    .name("public")
+                    .description("Show your balance and history to the whole channel")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("queryable")
+                    .description("Let others look up your balance with /balance")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut public = None;
+        let mut queryable = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "public" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        public = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "queryable" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        queryable = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        if public.is_some() || queryable.is_some() {
+            if let Err(e) =
+                update_user_settings(ctx.data.clone(), command.user.id.into(), |settings| {
+                    if let Some(public) = public {
+                        settings.public = public;
+                    }
+                    if let Some(queryable) = queryable {
+                        settings.queryable = queryable;
+                    }
+                })
+                .await
+            {
+                return Err(HandleCommandError::internal(format!(
+                    "could not save settings: {}",
+                    e
+                )));
+            }
+        }
+
+        let settings = user_settings(ctx.data.clone(), command.user.id.into()).await;
+
+        Ok(CommandResult {
+            response: describe_privacy(&settings),
+            ephemeral: true,
+            ..Default::default()
+        })
+    }
+}
+
+fn describe_privacy(settings: &UserSettings) -> String {
+    let shown = if settings.public {
+        "Your balance and history are shown to the whole channel"
+    } else {
+        "Your balance and history are only shown to you"
+    };
+    let queried = if settings.queryable {
+        "others can look up your balance"
+    } else {
+        "others can't look up your balance"
+    };
+    format!("{}, and {}", shown, queried)
+}
@@ -0,0 +1,651 @@
+use super::bill::BillCommand;
+use super::confirm::{confirmation_expiry, hold_for_confirmation};
+use super::{distinct_users, ledger_failed, CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::ids::UserId;
+use super::super::ledger::bill_shares;
+use super::super::pending::PendingRequest;
+use super::super::recurring::{DueRuns, RecurringBill};
+use super::super::schedule::Schedule;
+use super::super::split::{SplitError, SplitMode};
+use super::super::transaction::TransactionType;
+use super::super::utils::*;
+
+use crate::journal::transact;
+use crate::logging::{log, Origin};
+use crate::pending::Pending;
+use crate::recurring::save_recurring;
+
+use chrono::{DateTime, Duration, Utc};
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateApplicationCommandOption, CreateComponents},
+    client::Context,
+    http::Http,
+    model::{
+        id::ChannelId,
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+            ApplicationCommandInteractionDataOptionValue, ApplicationCommandOptionType,
+        },
+        misc::Mentionable,
+    },
+    prelude::TypeMap,
+};
+use tokio::sync::RwLock;
+
+use std::{fmt::Write, sync::Arc};
+
+/// Runs billed this many minutes after they were due mention when they were due.
+const LATE_MINUTES: i64 = 5;
+
+pub struct RecurringCommand;
+
+#[async_trait]
+impl SlashCommand for RecurringCommand {
+    fn name(&self) -> &'static str {
+        "recurring"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Bill users on a schedule")
+            .create_option(create_options)
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("List this server's recurring bills")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+            })
+            .create_option(|option| id_option(option, "pause", "Stop billing a recurring bill"))
+            .create_option(|option| {
+                id_option(
+                    option,
+                    "resume",
+                    "Start billing a paused recurring bill again",
+                )
+            })
+            .create_option(|option| id_option(option, "delete", "Delete a recurring bill"))
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Recurring bills can only be made in a server".to_string(),
+            ));
+        }
+
+        let subcommand = match command.data.options.first() {
+            Some(subcommand) => subcommand,
+            None => return Err(HandleCommandError::internal("missing subcommand")),
+        };
+
+        match subcommand.name.as_ref() {
+            "create" => create(ctx, command, &subcommand.options).await,
+            "list" => list(ctx, command).await,
+            "pause" | "resume" | "delete" => change(ctx, command, subcommand).await,
+            _ => Err(HandleCommandError::unexpected_option(&subcommand.name)),
+        }
+    }
+}
+
+fn create_options(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    let mut option = option
+        .name("create")
+        .description("Create a bill that repeats on a schedule")
+        .kind(ApplicationCommandOptionType::SubCommand)
+        .create_sub_option(|option| {
+            option
+                .name("amount")
+                .description("Amount, optionally with a currency symbol or code")
+                .kind(ApplicationCommandOptionType::String)
+                .required(true)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("description")
+                .description("Transaction description")
+                .kind(ApplicationCommandOptionType::String)
+                .required(true)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("schedule")
+                .description("When to bill in UTC, as cron fields such as 0 9 1 * * or @monthly")
+                .kind(ApplicationCommandOptionType::String)
+                .required(true)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("split")
+                .description(
+                    "How to divide the amount, defaults to billing each user the full amount",
+                )
+                .kind(ApplicationCommandOptionType::String)
+                .required(false)
+                .add_string_choice("each user pays the full amount", "each")
+                .add_string_choice("split evenly", "equal")
+                .add_string_choice("split by shares", "shares")
+                .add_string_choice("split by percentages", "percent")
+                .add_string_choice("exact amounts", "exact")
+        })
+        .create_sub_option(|option| {
+            option
+                .name("values")
+                .description(
+                    "Space separated shares, percentages or amounts for each user in order",
+                )
+                .kind(ApplicationCommandOptionType::String)
+                .required(false)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("include_self")
+                .description("Include yourself in the split, listed before the other users")
+                .kind(ApplicationCommandOptionType::Boolean)
+                .required(false)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("channel")
+                .description("Where to post each bill, defaults to this channel")
+                .kind(ApplicationCommandOptionType::Channel)
+                .required(false)
+        });
+
+    for i in 0..10 {
+        option = option.create_sub_option(|option| {
+            option
+                .name(format!("user{}", i))
+                .description("User to bill")
+                .kind(ApplicationCommandOptionType::User)
+                .required(false)
+        })
+    }
+    option
+}
+
+fn id_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(ApplicationCommandOptionType::SubCommand)
+        .create_sub_option(|option| {
+            option
+                .name("id")
+                .description("Number of the recurring bill, as shown by /recurring list")
+                .kind(ApplicationCommandOptionType::Integer)
+                .required(true)
+        })
+}
+
+async fn create(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Result<CommandResult, HandleCommandError> {
+    let default_currency = get_default_currency(ctx, command.guild_id).await;
+
+    let mut amount = None;
+    let mut description = None;
+    let mut schedule = None;
+    let mut mode = None;
+    let mut values = None;
+    let mut include_self = false;
+    let mut channel_id = command.channel_id;
+    let mut users = Vec::new();
+
+    for option in options {
+        match option.name.as_ref() {
+            "amount" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                    amount = Some(parse_amount(value, default_currency));
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "description" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                    description = Some(value);
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "schedule" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                    schedule = Some(value);
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "split" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                    mode = Some(value.as_str());
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "values" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                    values = Some(value.as_str());
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "include_self" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                    include_self = *value;
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            "channel" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::Channel(channel)) => {
+                    channel_id = channel.id;
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            name => match (&option.resolved, name.strip_prefix("user")) {
+                (
+                    Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)),
+                    Some(index),
+                ) => {
+                    users.push((
+                        index.parse::<usize>().unwrap_or(usize::MAX),
+                        UserId::from(user.id),
+                    ));
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+        }
+    }
+
+    // values are matched to users in the order of their options, as with /bill
+    users.sort_by_key(|(index, _user)| *index);
+
+    let mut participants: Vec<UserId> = users.into_iter().map(|(_index, user)| user).collect();
+    if include_self {
+        participants.insert(0, command.user.id.into());
+    }
+    distinct_users(&participants, command.user.id.into(), include_self)?;
+
+    let (amount, currency) = match amount {
+        Some(Ok(amount)) => amount,
+        Some(Err(e)) => return Err(HandleCommandError::invalid("amount", e)),
+        None => return Err(HandleCommandError::MissingOption("amount")),
+    };
+    let description = match description {
+        Some(description) => description,
+        None => return Err(HandleCommandError::MissingOption("description")),
+    };
+    let schedule = match schedule.map(|schedule| Schedule::parse(schedule)) {
+        Some(Ok(schedule)) => schedule,
+        Some(Err(e)) => return Err(HandleCommandError::invalid("schedule", e)),
+        None => return Err(HandleCommandError::MissingOption("schedule")),
+    };
+
+    let mode = match SplitMode::parse(mode.unwrap_or("each"), values, currency) {
+        Ok(mode) => mode,
+        Err(e @ SplitError::UnknownMode(_)) => return Err(HandleCommandError::invalid("split", e)),
+        Err(e) => return Err(HandleCommandError::invalid("values", e)),
+    };
+
+    // check the split now rather than when it first runs
+    match bill_shares(command.user.id.into(), &participants, amount, &mode) {
+        Ok(shares) if shares.is_empty() => {
+            return Err(HandleCommandError::Rejected(
+                "Choose at least one user other than yourself to bill".to_string(),
+            ))
+        }
+        Ok(_shares) => {}
+        Err(e) => return Err(ledger_failed(e.into())),
+    }
+
+    let next_run = match schedule.next_after(Utc::now()) {
+        Some(next_run) => next_run,
+        None => {
+            return Err(HandleCommandError::invalid(
+                "schedule",
+                "this schedule never runs",
+            ))
+        }
+    };
+
+    let bill = RecurringBill {
+        guild_id: ledger_scope(command.guild_id),
+        channel_id,
+        initiator: command.user.id.into(),
+        amount,
+        currency,
+        description: description.to_string(),
+        mode,
+        participants,
+        schedule,
+        next_run,
+        paused: false,
+    };
+
+    let recurring = match get_recurring_lock(ctx).await {
+        Ok(recurring_lock) => recurring_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let mut recurring = recurring.lock().await;
+
+    let id = recurring.insert(bill.clone());
+    if let Err(e) = save_recurring(&recurring) {
+        recurring.remove(id);
+        return Err(HandleCommandError::internal(format!(
+            "could not save recurring bills: {}",
+            e
+        )));
+    }
+
+    Ok(CommandResult {
+        response: format!(
+            "{} created recurring bill #{}: {}\nFirst billed <t:{}:f> in {}",
+            command.user.tag(),
+            id,
+            describe_bill(&bill),
+            bill.next_run.timestamp(),
+            bill.channel_id.mention()
+        ),
+        ..Default::default()
+    })
+}
+
+async fn list(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<CommandResult, HandleCommandError> {
+    let recurring = match get_recurring_lock(ctx).await {
+        Ok(recurring_lock) => recurring_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let recurring = recurring.lock().await;
+
+    let bills = recurring.in_guild(ledger_scope(command.guild_id));
+    if bills.is_empty() {
+        return Ok(CommandResult {
+            response: "There are no recurring bills here".to_string(),
+            ..Default::default()
+        });
+    }
+
+    let mut response = String::new();
+    for (id, bill) in bills {
+        let next = if bill.paused {
+            "paused".to_string()
+        } else {
+            format!("next <t:{}:R>", bill.next_run.timestamp())
+        };
+        if let Err(e) = writeln!(
+            response,
+            "#{} {} by {} in {}, {}",
+            id,
+            describe_bill(bill),
+            bill.initiator.mention(),
+            bill.channel_id.mention(),
+            next
+        ) {
+            return Err(HandleCommandError::internal(e));
+        }
+    }
+
+    Ok(CommandResult {
+        response,
+        ..Default::default()
+    })
+}
+
+/// Pauses, resumes or deletes a recurring bill, which only its creator or an administrator can do.
+async fn change(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    subcommand: &ApplicationCommandInteractionDataOption,
+) -> Result<CommandResult, HandleCommandError> {
+    let mut id = None;
+
+    for option in &subcommand.options {
+        match option.name.as_ref() {
+            "id" => match &option.resolved {
+                Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                    id = Some(*value);
+                }
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            },
+            _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+        }
+    }
+
+    let id = match id {
+        Some(id) => id,
+        None => return Err(HandleCommandError::MissingOption("id")),
+    };
+
+    let recurring = match get_recurring_lock(ctx).await {
+        Ok(recurring_lock) => recurring_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let mut recurring = recurring.lock().await;
+    // changes are made to a copy, kept only once it has been saved
+    let mut updated = recurring.clone();
+
+    let bill = match u64::try_from(id)
+        .ok()
+        .and_then(|id| updated.get_mut(ledger_scope(command.guild_id), id))
+    {
+        Some(bill) => bill,
+        None => {
+            return Err(HandleCommandError::Rejected(format!(
+                "There is no recurring bill #{} here",
+                id
+            )))
+        }
+    };
+
+    if bill.initiator != UserId::from(command.user.id) && !is_admin(command.member.as_ref()) {
+        return Err(HandleCommandError::NotAllowed(format!(
+            "Only whoever created recurring bill #{} or an administrator can change it",
+            id
+        )));
+    }
+
+    let response = match subcommand.name.as_ref() {
+        "pause" => {
+            bill.pause();
+            format!("{} paused recurring bill #{}", command.user.tag(), id)
+        }
+        "resume" => {
+            if !bill.resume(Utc::now()) {
+                return Err(HandleCommandError::Rejected(format!(
+                    "Recurring bill #{} will never run again",
+                    id
+                )));
+            }
+            format!(
+                "{} resumed recurring bill #{}, next billed <t:{}:f>",
+                command.user.tag(),
+                id,
+                bill.next_run.timestamp()
+            )
+        }
+        _ => {
+            updated.remove(id as u64);
+            format!("{} deleted recurring bill #{}", command.user.tag(), id)
+        }
+    };
+
+    if let Err(e) = save_recurring(&updated) {
+        return Err(HandleCommandError::internal(format!(
+            "could not save recurring bills: {}",
+            e
+        )));
+    }
+    *recurring = updated;
+
+    Ok(CommandResult {
+        response,
+        ..Default::default()
+    })
+}
+
+fn describe_bill(bill: &RecurringBill) -> String {
+    format!(
+        "{} split {} between {} users for {}, on schedule `{}`",
+        format_money(bill.amount, bill.currency),
+        bill.mode.name(),
+        bill.participants.len(),
+        bill.description,
+        bill.schedule
+    )
+}
+
+/// Bills each of the runs of recurring bill `id` that came due, posting them to its channel.
+pub async fn run_recurring_bill(
+    http: &Http,
+    data: Arc<RwLock<TypeMap>>,
+    id: u64,
+    bill: &RecurringBill,
+    due: &DueRuns,
+) {
+    if due.skipped > 0 {
+        let response = format!(
+            "Skipped {} runs of recurring bill #{} for {} missed while the bot was offline",
+            due.skipped, id, bill.description
+        );
+        post(http, bill.channel_id, response, None).await;
+    }
+
+    for &run in &due.runs {
+        let (response, components) = match bill_run(data.clone(), id, bill, run).await {
+            Ok(result) => (result.response, result.components),
+            Err(e) => {
+                if let HandleCommandError::Internal(detail) = &e {
+                    println!("Error running recurring bill #{}: {}", id, detail);
+                }
+                (
+                    format!("Could not bill recurring bill #{}: {}", id, e),
+                    None,
+                )
+            }
+        };
+        post(http, bill.channel_id, response, components).await;
+    }
+}
+
+/// Bills one run that was due at `run`, or holds it for confirmation if the guild requires it.
+async fn bill_run(
+    data: Arc<RwLock<TypeMap>>,
+    id: u64,
+    bill: &RecurringBill,
+    run: DateTime<Utc>,
+) -> Result<CommandResult, HandleCommandError> {
+    if let Some(expires) = confirmation_expiry(data.clone(), Some(bill.guild_id)).await {
+        let shares = match bill_shares(bill.initiator, &bill.participants, bill.amount, &bill.mode)
+        {
+            Ok(shares) => shares,
+            Err(e) => return Err(ledger_failed(e.into())),
+        };
+        let pending_lock = match data.read().await.get::<Pending>() {
+            Some(pending) => pending.clone(),
+            None => return Err(HandleCommandError::internal("could not get lock")),
+        };
+        let mut pending = pending_lock.lock().await;
+
+        let request = PendingRequest::bill(
+            bill.guild_id,
+            bill.initiator,
+            bill.amount,
+            bill.currency,
+            &bill.description,
+            shares,
+            expires,
+        );
+        let mut result = hold_for_confirmation(&mut pending, BillCommand.name(), request)?;
+        result.response = format!("Recurring bill #{}{}: {}", id, late(run), result.response);
+        return Ok(result);
+    }
+
+    let result = transact(data.clone(), bill.guild_id, |book| {
+        book.bill(
+            bill.initiator,
+            &bill.participants,
+            bill.amount,
+            bill.currency,
+            &bill.mode,
+            &bill.description,
+        )
+    })
+    .await;
+    let (seq, transaction) = match result {
+        Ok(recorded) => recorded,
+        Err(e) => return Err(ledger_failed(e)),
+    };
+
+    let response = format!(
+        "Recurring bill #{}{}: {} billed {} to {} users for {} (#{})",
+        id,
+        late(run),
+        bill.initiator.mention(),
+        format_money(bill.amount, bill.currency),
+        transaction.recipients.len(),
+        bill.description,
+        seq
+    );
+
+    let origin = Origin {
+        guild_id: Some(bill.guild_id),
+        channel_id: bill.channel_id,
+        interaction_id: None,
+    };
+    if let Err(e) = log(data, origin, Some(seq), TransactionType::Bill(transaction)).await {
+        println!("Could not log transaction: {}", e);
+    }
+
+    Ok(CommandResult {
+        response,
+        id: Some(seq),
+        ..Default::default()
+    })
+}
+
+/// Notes when a run was due if it was billed well after, such as when catching up.
+fn late(run: DateTime<Utc>) -> String {
+    if Utc::now() - run > Duration::minutes(LATE_MINUTES) {
+        format!(" (due <t:{}:f>)", run.timestamp())
+    } else {
+        String::new()
+    }
+}
+
+async fn post(
+    http: &Http,
+    channel_id: ChannelId,
+    response: String,
+    components: Option<CreateComponents>,
+) {
+    let sent = channel_id
+        .send_message(http, |message| {
+            message.content(response);
+            if let Some(components) = components {
+                message.components(|c| {
+                    *c = components;
+                    c
+                });
+            }
+            message
+        })
+        .await;
+
+    if let Err(e) = sent {
+        println!("Cannot post to channel {}: {}", channel_id, e);
+    }
+}
@@ -0,0 +1,117 @@
+use super::audit::AuditCommand;
+use super::balance::BalanceCommand;
+use super::bill::BillCommand;
+use super::confirm::ConfirmationCommand;
+use super::convert::{ConvertCommand, RateCommand};
+use super::currency::CurrencyCommand;
+use super::embeds::EmbedsCommand;
+use super::group::GroupCommand;
+use super::history::HistoryCommand;
+use super::owe::OweCommand;
+use super::privacy::PrivacyCommand;
+use super::recurring::RecurringCommand;
+use super::reminders::{NotificationsCommand, RemindersCommand};
+use super::settle::SettleCommand;
+use super::simplify::SimplifyCommand;
+use super::void::{UndoCommand, VoidCommand};
+use super::{CommandResult, HandleCommandError, SlashCommand};
+
+use serenity::{
+    builder::CreateApplicationCommands,
+    client::Context,
+    model::interactions::{
+        application_command::ApplicationCommandInteraction,
+        message_component::MessageComponentInteraction,
+    },
+};
+
+/// Every slash command the bot offers, used both to register them and to dispatch interactions.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn SlashCommand>>,
+}
+
+impl CommandRegistry {
+    /// The registry of all commands, in the order they are registered with Discord.
+    pub fn new() -> CommandRegistry {
+        CommandRegistry::default()
+            .with(BalanceCommand)
+            .with(OweCommand)
+            .with(BillCommand)
+            .with(SettleCommand)
+            .with(VoidCommand)
+            .with(UndoCommand)
+            .with(SimplifyCommand)
+            .with(HistoryCommand)
+            .with(AuditCommand)
+            .with(CurrencyCommand)
+            .with(RateCommand)
+            .with(ConvertCommand)
+            .with(EmbedsCommand)
+            .with(PrivacyCommand)
+            .with(ConfirmationCommand)
+            .with(RecurringCommand)
+            .with(RemindersCommand)
+            .with(NotificationsCommand)
+            .with(GroupCommand)
+    }
+
+    pub fn with(mut self, command: impl SlashCommand + 'static) -> CommandRegistry {
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == name)
+            .map(|command| command.as_ref())
+    }
+
+    pub fn create_commands<'a>(
+        &self,
+        commands: &'a mut CreateApplicationCommands,
+    ) -> &'a mut CreateApplicationCommands {
+        for command in &self.commands {
+            commands.create_application_command(|builder| {
+                command.definition(builder.name(command.name()))
+            });
+        }
+        commands
+    }
+
+    pub async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        match self.get(&command.data.name) {
+            Some(handler) => handler.handle(ctx, command).await,
+            None => Err(HandleCommandError::internal(format!(
+                "unknown command {}",
+                command.data.name
+            ))),
+        }
+    }
+
+    /// Passes a component to the command named by the start of its custom id.
+    pub async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let name = component
+            .data
+            .custom_id
+            .split(':')
+            .next()
+            .unwrap_or_default();
+        match self.get(name) {
+            Some(handler) => handler.handle_component(ctx, component).await,
+            None => Err(HandleCommandError::internal(format!(
+                "unknown component {}",
+                component.data.custom_id
+            ))),
+        }
+    }
+}
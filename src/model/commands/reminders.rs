@@ -0,0 +1,530 @@
+use super::{CommandResult, HandleCommandError, SlashCommand, MAX_MESSAGE_LENGTH};
+
+use super::super::accounts::{ledger_scope, Accounts, Ledgers};
+use super::super::ids::{GuildId, UserId};
+use super::super::reminders::{debtors, outstanding, QuietHours};
+use super::super::schedule::Schedule;
+use super::super::utils::*;
+
+use crate::settings::{
+    exchange_rates, guild_settings, update_guild_settings, update_user_settings, user_settings,
+    GuildSettings, UserSettings,
+};
+
+use chrono::{DateTime, Utc};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    http::Http,
+    model::{
+        id::{self, ChannelId},
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            ApplicationCommandOptionType,
+        },
+        misc::Mentionable,
+    },
+    prelude::TypeMap,
+};
+use tokio::sync::RwLock;
+
+use std::sync::Arc;
+
+pub struct RemindersCommand;
+
+#[async_trait]
+impl SlashCommand for RemindersCommand {
+    fn name(&self) -> &'static str {
+        "reminders"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or set when the bot reminds users of their debts")
+            .create_option(|option| {
+                option
+                    .name("schedule")
+                    .description("When to message users in debt, as cron fields in UTC, or off")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("threshold")
+                    .description("Only remind users who owe more than this in total")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("digest_channel")
+                    .description("Where to post a digest of outstanding balances")
+                    .kind(ApplicationCommandOptionType::Channel)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("digest_schedule")
+                    .description("When to post the digest, as cron fields in UTC, or off")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let scope = ledger_scope(command.guild_id);
+        let currency = get_default_currency(ctx, command.guild_id).await;
+
+        // `Some(None)` turns the setting off
+        let mut schedule = None;
+        let mut threshold = None;
+        let mut digest_channel = None;
+        let mut digest_schedule = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "schedule" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        schedule = match parse_schedule(value) {
+                            Ok(value) => Some(value),
+                            Err(e) => return Err(HandleCommandError::invalid("schedule", e)),
+                        };
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "threshold" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        threshold = match parse_money(value, currency) {
+                            Ok(value) if !value.is_negative() => Some(value),
+                            Ok(_value) => {
+                                return Err(HandleCommandError::invalid(
+                                    "threshold",
+                                    "must not be negative",
+                                ))
+                            }
+                            Err(e) => return Err(HandleCommandError::invalid("threshold", e)),
+                        };
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "digest_channel" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Channel(channel)) => {
+                        digest_channel = Some(channel.id);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "digest_schedule" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        digest_schedule = match parse_schedule(value) {
+                            Ok(value) => Some(value),
+                            Err(e) => {
+                                return Err(HandleCommandError::invalid("digest_schedule", e))
+                            }
+                        };
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        if command.data.options.is_empty() {
+            let settings = guild_settings(ctx.data.clone(), scope).await;
+            return Ok(CommandResult {
+                response: describe_reminders(&settings),
+                ..Default::default()
+            });
+        }
+
+        if command.guild_id.is_none() {
+            return Err(HandleCommandError::NotAllowed(
+                "Reminders can only be set up in a server".to_string(),
+            ));
+        }
+        if !is_admin(command.member.as_ref()) {
+            return Err(HandleCommandError::admin_only("change reminders"));
+        }
+
+        if let Err(e) = update_guild_settings(ctx.data.clone(), scope, |settings| {
+            if let Some(schedule) = schedule {
+                settings.reminder_schedule = schedule;
+            }
+            if let Some(threshold) = threshold {
+                settings.reminder_threshold = Some(threshold);
+            }
+            if let Some(digest_channel) = digest_channel {
+                settings.digest_channel = Some(digest_channel);
+            }
+            match digest_schedule {
+                Some(None) => settings.digest_channel = None,
+                Some(schedule) => settings.digest_schedule = schedule,
+                None => {}
+            }
+        })
+        .await
+        {
+            return Err(HandleCommandError::internal(format!(
+                "could not save settings: {}",
+                e
+            )));
+        }
+
+        let settings = guild_settings(ctx.data.clone(), scope).await;
+        Ok(CommandResult {
+            response: format!(
+                "{} updated reminders: {}",
+                command.user.tag(),
+                describe_reminders(&settings)
+            ),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct NotificationsCommand;
+
+#[async_trait]
+impl SlashCommand for NotificationsCommand {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Show or choose which reminders you get and when")
+            .create_option(|option| {
+                option
+                    .name("reminders")
+                    .description("Get direct messages reminding you of what you owe")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("digest")
+                    .description("Include your debts in channel digests")
+                    .kind(ApplicationCommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("quiet_from")
+                    .description(
+                        "Hour in UTC from which reminders wait, the same as quiet_to for none",
+                    )
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("quiet_to")
+                    .description("Hour in UTC at which reminders are sent again")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut reminders = None;
+        let mut digest = None;
+        let mut quiet_from = None;
+        let mut quiet_to = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "reminders" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        reminders = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "digest" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Boolean(value)) => {
+                        digest = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "quiet_from" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        quiet_from = Some(parse_hour("quiet_from", *value)?);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "quiet_to" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        quiet_to = Some(parse_hour("quiet_to", *value)?);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let quiet_hours = match (quiet_from, quiet_to) {
+            (Some(start), Some(end)) if start == end => Some(None),
+            (Some(start), Some(end)) => Some(Some(QuietHours { start, end })),
+            (None, None) => None,
+            (None, Some(_end)) => return Err(HandleCommandError::MissingOption("quiet_from")),
+            (Some(_start), None) => return Err(HandleCommandError::MissingOption("quiet_to")),
+        };
+
+        if !command.data.options.is_empty() {
+            if let Err(e) =
+                update_user_settings(ctx.data.clone(), command.user.id.into(), |settings| {
+                    if let Some(reminders) = reminders {
+                        settings.no_reminders = !reminders;
+                    }
+                    if let Some(digest) = digest {
+                        settings.no_digest = !digest;
+                    }
+                    if let Some(quiet_hours) = quiet_hours {
+                        settings.quiet_hours = quiet_hours;
+                    }
+                })
+                .await
+            {
+                return Err(HandleCommandError::internal(format!(
+                    "could not save settings: {}",
+                    e
+                )));
+            }
+        }
+
+        let settings = user_settings(ctx.data.clone(), command.user.id.into()).await;
+
+        Ok(CommandResult {
+            response: describe_notifications(&settings),
+            ephemeral: true,
+            ..Default::default()
+        })
+    }
+}
+
+/// A schedule, or `None` to turn it off.
+fn parse_schedule(input: &str) -> Result<Option<Schedule>, String> {
+    if input.trim().eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    match Schedule::parse(input) {
+        Ok(schedule) => Ok(Some(schedule)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_hour(option: &'static str, value: i64) -> Result<u32, HandleCommandError> {
+    match value {
+        0..=23 => Ok(value as u32),
+        _ => Err(HandleCommandError::invalid(
+            option,
+            "must be between 0 and 23",
+        )),
+    }
+}
+
+fn describe_reminders(settings: &GuildSettings) -> String {
+    let reminders = match &settings.reminder_schedule {
+        Some(schedule) => format!(
+            "Users who owe more than {} are reminded on schedule `{}`",
+            format_money(settings.reminder_threshold(), settings.currency()),
+            schedule
+        ),
+        None => "Users aren't reminded of their debts".to_string(),
+    };
+    let digest = match settings.digest_channel {
+        Some(channel) => format!(
+            "a digest is posted to {} on schedule `{}`",
+            channel.mention(),
+            settings.digest_schedule()
+        ),
+        None => "no digest is posted".to_string(),
+    };
+    format!("{}, and {}", reminders, digest)
+}
+
+fn describe_notifications(settings: &UserSettings) -> String {
+    let reminders = if settings.no_reminders {
+        "You don't get reminders"
+    } else {
+        "You get reminders when servers send them"
+    };
+    let quiet = match settings.quiet_hours {
+        Some(quiet) if !settings.no_reminders => format!(
+            ", except from {:02}:00 to {:02}:00 UTC when they wait",
+            quiet.start, quiet.end
+        ),
+        _ => String::new(),
+    };
+    let digest = if settings.no_digest {
+        "your debts are left out of digests"
+    } else {
+        "your debts are included in digests"
+    };
+    format!("{}{}, and {}", reminders, quiet, digest)
+}
+
+/// A copy of the guild's ledgers, so no lock is held while messages are sent.
+async fn guild_ledgers(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> Ledgers {
+    let accounts_lock = match data.read().await.get::<Accounts>() {
+        Some(accounts) => accounts.clone(),
+        None => return Ledgers::default(),
+    };
+    let accounts = accounts_lock.read().await;
+    accounts.get(&guild_id).cloned().unwrap_or_default()
+}
+
+/// Messages users in `guild_id` who owe more than its threshold in any currency, or only those of
+/// `users` who still do.
+///
+/// Returns the users who were in their quiet hours, to be reminded once they're over.
+pub async fn remind_debtors(
+    http: &Http,
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: GuildId,
+    users: Option<&[UserId]>,
+    now: DateTime<Utc>,
+) -> Vec<UserId> {
+    let settings = guild_settings(data.clone(), guild_id).await;
+    let currency = settings.currency();
+
+    let ledgers = guild_ledgers(&data, guild_id).await;
+    let rates = exchange_rates(data.clone(), guild_id).await;
+    let debtors = match debtors(&ledgers, currency, settings.reminder_threshold(), &rates) {
+        Ok(debtors) => debtors,
+        Err(e) => {
+            println!("Cannot total debts in {}: {}", guild_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut guild_name = None;
+    let mut later = Vec::new();
+
+    for (user, debts) in debtors {
+        if users.is_some_and(|users| !users.contains(&user)) {
+            continue;
+        }
+
+        let notifications = user_settings(data.clone(), user).await;
+        if notifications.no_reminders {
+            continue;
+        }
+        if notifications
+            .quiet_hours
+            .is_some_and(|quiet| quiet.contains(now))
+        {
+            later.push(user);
+            continue;
+        }
+
+        if guild_name.is_none() {
+            guild_name = Some(
+                match id::GuildId::from(guild_id).to_partial_guild(http).await {
+                    Ok(guild) => guild.name,
+                    Err(_e) => "a server".to_string(),
+                },
+            );
+        }
+
+        let debts = debts
+            .into_iter()
+            .map(|(currency, debt)| format_money(debt, currency))
+            .collect::<Vec<_>>()
+            .join(" and ");
+        let message = format!(
+            "You owe {} in total in {}. Use /balance there to see who to pay, or /notifications to stop these reminders.",
+            debts,
+            guild_name.as_deref().unwrap_or_default()
+        );
+        let sent = match id::UserId::from(user).create_dm_channel(http).await {
+            Ok(channel) => channel.say(http, message).await.map(|_message| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            println!("Cannot remind {}: {}", user, e);
+        }
+    }
+
+    later
+}
+
+/// Posts every outstanding debt in `guild_id` to `channel_id`, except those involving users who
+/// left digests or keep their balances private.
+pub async fn post_digest(
+    http: &Http,
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) {
+    let ledgers = guild_ledgers(&data, guild_id).await;
+
+    let debts = outstanding(&ledgers);
+    let mut lines = Vec::new();
+    let mut hidden = 0;
+    for &(currency, debtor, creditor, amount) in &debts {
+        let (debtor_settings, creditor_settings) = (
+            user_settings(data.clone(), debtor).await,
+            user_settings(data.clone(), creditor).await,
+        );
+        // only debts between users who show their balances to everyone are listed
+        if !debtor_settings.public
+            || !creditor_settings.public
+            || debtor_settings.no_digest
+            || creditor_settings.no_digest
+        {
+            hidden += 1;
+            continue;
+        }
+        lines.push(format!(
+            "{} owes {} {}",
+            debtor.mention(),
+            creditor.mention(),
+            format_money(amount, currency)
+        ));
+    }
+
+    let mut response = if debts.is_empty() {
+        "Everyone is settled up".to_string()
+    } else if lines.is_empty() {
+        format!("{} outstanding balances, all of them private", hidden)
+    } else if hidden > 0 {
+        format!("Outstanding balances, besides {} private ones:", hidden)
+    } else {
+        "Outstanding balances:".to_string()
+    };
+    for (i, line) in lines.iter().enumerate() {
+        let more = format!("\n...and {} more", lines.len() - i);
+        if response.len() + 1 + line.len() + more.len() > MAX_MESSAGE_LENGTH {
+            response.push_str(&more);
+            break;
+        }
+        response.push('\n');
+        response.push_str(line);
+    }
+
+    // names are shown as mentions without notifying everyone listed
+    let sent = channel_id
+        .send_message(http, |message| {
+            message
+                .content(response)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await;
+    if let Err(e) = sent {
+        println!("Cannot post digest to channel {}: {}", channel_id, e);
+    }
+}
@@ -0,0 +1,174 @@
+use super::group::{find_group, in_group};
+use super::{currency_option, ledger_failed, CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::ids::UserId;
+use super::super::ledger::LedgerError;
+use super::super::transaction::TransactionType;
+use super::super::utils::*;
+
+use crate::journal::transact_in_group;
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+pub struct SettleCommand;
+
+#[async_trait]
+impl SlashCommand for SettleCommand {
+    fn name(&self) -> &'static str {
+        "settle"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Record a repayment with a user")
+            .create_option(|option| {
+                option
+                    .name("user")
+                    .description("User to settle with")
+                    .kind(ApplicationCommandOptionType::User)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("amount")
+                    .description("Amount, defaults to the full balance in the currency")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("currency")
+                    .description("Currency code to settle in, if not given with the amount")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("group")
+                    .description("Settle what is owed within this group")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut amount = None;
+        let mut currency = None;
+        let mut user_opt = None;
+        let mut group = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "amount" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        amount = Some(value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "currency" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        currency = Some(currency_option("currency", value)?);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "user" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::User(user, _member)) => {
+                        user_opt = Some(user);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                "group" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        group = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let other = match user_opt {
+            Some(user) => user,
+            None => return Err(HandleCommandError::MissingOption("user")),
+        };
+
+        let default_currency = match currency {
+            Some(currency) => currency,
+            None => get_default_currency(ctx, command.guild_id).await,
+        };
+        let (amount, currency) = match amount.map(|amount| parse_amount(amount, default_currency)) {
+            Some(Ok((_amount, parsed))) if currency.is_some_and(|currency| currency != parsed) => {
+                return Err(HandleCommandError::invalid(
+                    "amount",
+                    ParseMoneyError::WrongCurrency {
+                        expected: default_currency,
+                        found: parsed,
+                    },
+                ))
+            }
+            Some(Ok((amount, parsed))) => (Some(amount), parsed),
+            Some(Err(e)) => return Err(HandleCommandError::invalid("amount", e)),
+            None => (None, default_currency),
+        };
+
+        let group = match group {
+            Some(name) => Some(find_group(ctx, command, name).await?.0),
+            None => None,
+        };
+
+        let scope = ledger_scope(command.guild_id);
+        let result = transact_in_group(ctx.data.clone(), scope, group.as_deref(), |book| {
+            book.settle(command.user.id.into(), other.id.into(), amount, currency)
+        })
+        .await;
+        let (id, settle) = match result {
+            Ok(recorded) => recorded,
+            Err(LedgerError::NothingOutstanding) => {
+                return Err(HandleCommandError::Rejected(format!(
+                    "You have nothing to settle with {} in {}{}",
+                    other.tag(),
+                    currency,
+                    in_group(group.as_deref())
+                )))
+            }
+            Err(e) => return Err(ledger_failed(e)),
+        };
+
+        let (payer, payee) = if settle.payer == UserId::from(other.id) {
+            (other, &command.user)
+        } else {
+            (&command.user, other)
+        };
+
+        let response = format!(
+            "{} paid {} to {} (#{})",
+            payer.tag(),
+            format_money(settle.amount, currency),
+            payee.tag(),
+            id
+        );
+
+        Ok(CommandResult {
+            response,
+            transaction: TransactionType::Settle(settle),
+            id: Some(id),
+            ..Default::default()
+        })
+    }
+}
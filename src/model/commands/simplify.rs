@@ -0,0 +1,320 @@
+use super::group::{find_group, in_group};
+use super::{read_failed, CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::{ledger_scope, Ledgers};
+use super::super::currency::Currency;
+use super::super::ids::GuildId;
+use super::super::simplify::{rebalance, simplify, Payment};
+use super::super::transaction::{SimplifyTransaction, TransactionType};
+use super::super::utils::*;
+
+use crate::journal::transact_in_group;
+
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommand, CreateComponents},
+    client::Context,
+    model::{
+        id,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::{ButtonStyle, MessageComponentInteraction},
+        },
+        prelude::User,
+    },
+};
+
+use std::fmt::Write;
+
+pub struct SimplifyCommand;
+
+#[async_trait]
+impl SlashCommand for SimplifyCommand {
+    fn name(&self) -> &'static str {
+        "simplify"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Preview the fewest payments that would settle everyone's debts")
+            .create_option(|option| {
+                option
+                    .name("group")
+                    .description("Only simplify the debts within this group")
+                    .kind(ApplicationCommandOptionType::String)
+                    .required(false)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut group = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "group" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::String(value)) => {
+                        group = Some(value.as_str());
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        let group = match group {
+            Some(name) => Some(find_group(ctx, command, name).await?.0),
+            None => None,
+        };
+
+        let scope = ledger_scope(command.guild_id);
+        let (ledgers, seq) = scoped_ledgers(ctx, scope, group.as_deref()).await?;
+
+        let debts = ledgers
+            .values()
+            .flat_map(|ledger| ledger.values())
+            .flat_map(|account| account.values())
+            .filter(|amount| amount.is_positive())
+            .count();
+        let plan = match simplify_all(&ledgers) {
+            Ok(plan) => plan
+                .into_iter()
+                .flat_map(|(currency, plan)| {
+                    plan.into_iter().map(move |payment| (currency, payment))
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => return Err(simplify_failed(e)),
+        };
+
+        if plan.len() >= debts {
+            return Ok(CommandResult {
+                response: "Debts are already as simple as they can be".to_string(),
+                ..Default::default()
+            });
+        }
+
+        let mut response = format!(
+            "Simplifying {} debts{} into {} payments:\n",
+            debts,
+            in_group(group.as_deref()),
+            plan.len()
+        );
+        for (debtor, creditor, amount, currency) in resolve_payments(ctx, &plan).await? {
+            if let Err(e) = writeln!(
+                response,
+                "`{:<32}{:>16}` to {}",
+                debtor.tag(),
+                format_money(amount, currency),
+                creditor.tag()
+            ) {
+                return Err(HandleCommandError::internal(e));
+            }
+        }
+        response.push_str("An administrator can apply this plan.");
+
+        let mut components = CreateComponents::default();
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .style(ButtonStyle::Primary)
+                    .label("Apply")
+                    .custom_id(match &group {
+                        Some(name) => format!("simplify:{}:{}", seq, name),
+                        None => format!("simplify:{}", seq),
+                    })
+            })
+        });
+
+        Ok(CommandResult {
+            response,
+            components: Some(components),
+            ..Default::default()
+        })
+    }
+
+    /// Applies a previewed plan, provided nothing has been recorded in the guild since the preview.
+    ///
+    /// Plans for a group are recorded in the group, so they only rearrange debts within it.
+    async fn handle_component(
+        &self,
+        ctx: &Context,
+        component: &MessageComponentInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        if !is_admin(component.member.as_ref()) {
+            return Err(HandleCommandError::admin_only(
+                "apply a simplification plan",
+            ));
+        }
+
+        // buttons for a group's plan end with the group's name
+        let button = component
+            .data
+            .custom_id
+            .strip_prefix("simplify:")
+            .unwrap_or_default();
+        let (seq, group) = match button.split_once(':') {
+            Some((seq, group)) => (seq, Some(group)),
+            None => (button, None),
+        };
+        let previewed_seq = match seq.parse::<u64>() {
+            Ok(seq) => seq,
+            Err(_e) => {
+                return Err(HandleCommandError::internal(format!(
+                    "invalid simplify button {}",
+                    component.data.custom_id
+                )))
+            }
+        };
+
+        // check and plan against the balances as they are while the plan is recorded
+        let result = transact_in_group(
+            ctx.data.clone(),
+            ledger_scope(component.guild_id),
+            group,
+            |book| {
+                if book.last_seq()? != previewed_seq {
+                    return Err(HandleCommandError::Rejected(
+                        "Balances have changed since this plan was made, use /simplify again"
+                            .to_string(),
+                    ));
+                }
+                let ledgers = book.ledgers()?;
+                if ledgers.is_empty() {
+                    return Err(HandleCommandError::Rejected(
+                        "There are no debts to simplify".to_string(),
+                    ));
+                }
+
+                let mut plan = Vec::new();
+                let mut transfers = Vec::new();
+                let plans = match simplify_all(&ledgers) {
+                    Ok(plans) => plans,
+                    Err(e) => return Err(simplify_failed(e)),
+                };
+                for (currency, payments) in plans {
+                    transfers.extend(rebalance(&ledgers[&currency], currency, &payments));
+                    plan.extend(payments.into_iter().map(|payment| (currency, payment)));
+                }
+
+                let transaction = TransactionType::Simplify(SimplifyTransaction {
+                    initiator: component.user.id.into(),
+                    transfers,
+                });
+                let id = book.record(&transaction)?;
+                Ok((id, plan, transaction))
+            },
+        )
+        .await;
+        let (id, plan, transaction) = result?;
+
+        let payments = resolve_payments(ctx, &plan).await?;
+
+        let mut response = format!(
+            "{} simplified debts{} into {} payments (#{}):\n",
+            component.user.tag(),
+            in_group(group),
+            payments.len(),
+            id
+        );
+        for (debtor, creditor, amount, currency) in &payments {
+            if let Err(e) = writeln!(
+                response,
+                "`{:<32}{:>16}` to {}",
+                debtor.tag(),
+                format_money(*amount, *currency),
+                creditor.tag()
+            ) {
+                return Err(HandleCommandError::internal(e));
+            }
+        }
+
+        Ok(CommandResult {
+            response,
+            transaction,
+            id: Some(id),
+            ..Default::default()
+        })
+    }
+}
+
+/// The balances of `group`, or of the whole guild without a group, along with the sequence number
+/// of the guild's latest transaction that a plan for them is made as of.
+async fn scoped_ledgers(
+    ctx: &Context,
+    guild_id: GuildId,
+    group: Option<&str>,
+) -> Result<(Ledgers, u64), HandleCommandError> {
+    let accounts = match get_accounts_lock(ctx).await {
+        Ok(accounts_lock) => accounts_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+    let journal = match get_journal_lock(ctx).await {
+        Ok(journal_lock) => journal_lock,
+        Err(e) => return Err(HandleCommandError::internal(e)),
+    };
+
+    let accounts = accounts.read().await;
+    let mut journal = journal.lock().await;
+    let seq = match journal.last_seq_in(guild_id) {
+        Ok(seq) => seq,
+        Err(e) => return Err(read_failed(e)),
+    };
+    let ledgers = match group {
+        Some(group) => match journal.group_ledgers(guild_id, group) {
+            Ok(ledgers) => ledgers,
+            Err(e) => return Err(read_failed(e)),
+        },
+        None => accounts.get(&guild_id).cloned().unwrap_or_default(),
+    };
+
+    Ok((ledgers, seq))
+}
+
+/// Simplifies each currency's ledger separately, in order of currency code.
+fn simplify_all(ledgers: &Ledgers) -> Result<Vec<(Currency, Vec<Payment>)>, MoneyOverflowError> {
+    let mut currencies: Vec<Currency> = ledgers.keys().copied().collect();
+    currencies.sort();
+
+    currencies
+        .into_iter()
+        .map(|currency| Ok((currency, simplify(&ledgers[&currency])?)))
+        .collect()
+}
+
+fn simplify_failed(e: MoneyOverflowError) -> HandleCommandError {
+    HandleCommandError::Rejected(format!("Could not simplify debts: {}", e))
+}
+
+async fn resolve_payments(
+    ctx: &Context,
+    plan: &[(Currency, Payment)],
+) -> Result<Vec<(User, User, Money, Currency)>, HandleCommandError> {
+    let mut payments = Vec::new();
+    for (currency, payment) in plan {
+        match (
+            id::UserId::from(payment.debtor).to_user(ctx).await,
+            id::UserId::from(payment.creditor).to_user(ctx).await,
+        ) {
+            (Ok(debtor), Ok(creditor)) => {
+                payments.push((debtor, creditor, payment.amount, *currency))
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                return Err(HandleCommandError::internal(format!(
+                    "could not fetch user: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    Ok(payments)
+}
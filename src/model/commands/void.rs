@@ -0,0 +1,131 @@
+use super::{ledger_failed, CommandResult, HandleCommandError, SlashCommand};
+
+use super::super::accounts::ledger_scope;
+use super::super::ledger::LedgerError;
+use super::super::transaction::{TransactionType, VoidTransaction};
+use super::super::utils::*;
+
+use crate::journal::transact;
+
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommand,
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+pub struct VoidCommand;
+
+#[async_trait]
+impl SlashCommand for VoidCommand {
+    fn name(&self) -> &'static str {
+        "void"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .description("Void a transaction")
+            .create_option(|option| {
+                option
+                    .name("id")
+                    .description("Transaction number")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .required(true)
+            })
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let mut id = None;
+
+        for option in &command.data.options {
+            match option.name.as_ref() {
+                "id" => match &option.resolved {
+                    Some(ApplicationCommandInteractionDataOptionValue::Integer(value)) => {
+                        id = Some(*value);
+                    }
+                    _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+                },
+                _ => return Err(HandleCommandError::unexpected_option(&option.name)),
+            }
+        }
+
+        match id {
+            Some(id) if id > 0 => {
+                let is_admin = is_admin(command.member.as_ref());
+                let result = transact(ctx.data.clone(), ledger_scope(command.guild_id), |book| {
+                    book.void(command.user.id.into(), is_admin, id as u64)
+                })
+                .await;
+                voided(command, result)
+            }
+            Some(_id) => Err(HandleCommandError::invalid(
+                "id",
+                "transaction numbers start at 1",
+            )),
+            None => Err(HandleCommandError::MissingOption("id")),
+        }
+    }
+}
+
+pub struct UndoCommand;
+
+#[async_trait]
+impl SlashCommand for UndoCommand {
+    fn name(&self) -> &'static str {
+        "undo"
+    }
+
+    fn definition<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command.description("Void your most recent transaction")
+    }
+
+    async fn handle(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<CommandResult, HandleCommandError> {
+        let result = transact(ctx.data.clone(), ledger_scope(command.guild_id), |book| {
+            book.undo(command.user.id.into())
+        })
+        .await;
+        voided(command, result)
+    }
+}
+
+/// Reports the outcome of voiding a transaction.
+fn voided(
+    command: &ApplicationCommandInteraction,
+    result: Result<(u64, VoidTransaction), LedgerError>,
+) -> Result<CommandResult, HandleCommandError> {
+    let (id, void) = match result {
+        Ok(recorded) => recorded,
+        Err(e) => return Err(ledger_failed(e)),
+    };
+
+    let response = format!(
+        "{} voided transaction #{} (#{})",
+        command.user.tag(),
+        void.voided,
+        id
+    );
+
+    Ok(CommandResult {
+        response,
+        transaction: TransactionType::Void(void),
+        id: Some(id),
+        ..Default::default()
+    })
+}
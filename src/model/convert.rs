@@ -0,0 +1,354 @@
+use super::accounts::{Ledgers, Transfer};
+use super::currency::Currency;
+use super::ids::UserId;
+use super::utils::Money;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// Rates are kept in millionths, so `1000000` converts one unit into one unit.
+const RATE_EXPONENT: u32 = 6;
+const RATE_SCALE: i128 = 10_i128.pow(RATE_EXPONENT);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConvertError {
+    MissingRate(Currency, Currency),
+    Overflow,
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ConvertError::MissingRate(from, to) => {
+                write!(f, "no exchange rate from {} to {}", from, to)
+            }
+            ConvertError::Overflow => write!(f, "converted amount is too large"),
+        }
+    }
+}
+
+impl Error for ConvertError {}
+
+/// How many units of one currency a unit of another is worth, to six decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate(i64);
+
+impl Rate {
+    /// Parses a positive decimal with at most six decimal places.
+    pub fn parse(input: &str) -> Option<Rate> {
+        let (whole, fraction) = match input.trim().split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (input.trim(), ""),
+        };
+
+        if fraction.len() > RATE_EXPONENT as usize || !fraction.chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        let whole = i64::from(whole.parse::<u32>().ok()?);
+        let fraction = format!("{:0<width$}", fraction, width = RATE_EXPONENT as usize)
+            .parse::<i64>()
+            .ok()?;
+
+        let rate = whole * RATE_SCALE as i64 + fraction;
+        if rate > 0 {
+            Some(Rate(rate))
+        } else {
+            None
+        }
+    }
+
+    fn inverse(&self) -> Rate {
+        Rate(round_div(RATE_SCALE * RATE_SCALE, i128::from(self.0)).max(1) as i64)
+    }
+}
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let scale = RATE_SCALE as i64;
+        let fraction = format!(
+            "{:0>width$}",
+            self.0 % scale,
+            width = RATE_EXPONENT as usize
+        );
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}", self.0 / scale)
+        } else {
+            write!(f, "{}.{}", self.0 / scale, fraction)
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rate = String::deserialize(deserializer)?;
+        Rate::parse(&rate).ok_or_else(|| de::Error::custom(format!("invalid rate {}", rate)))
+    }
+}
+
+/// Exchange rates, keyed by the currency converted from and then the currency converted to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateTable(BTreeMap<Currency, BTreeMap<Currency, Rate>>);
+
+impl RateTable {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Sets the rate from `from` to `to`, replacing any rate in the opposite direction.
+    pub fn set(&mut self, from: Currency, to: Currency, rate: Rate) {
+        if let Some(rates) = self.0.get_mut(&to) {
+            rates.remove(&from);
+        }
+        self.0.entry(from).or_default().insert(to, rate);
+    }
+
+    /// The rate from `from` to `to`, using the inverse of the opposite rate if only it is known.
+    pub fn get(&self, from: Currency, to: Currency) -> Option<Rate> {
+        if from == to {
+            return Some(Rate(RATE_SCALE as i64));
+        }
+
+        let direct = self.0.get(&from).and_then(|rates| rates.get(&to));
+        let inverse = self.0.get(&to).and_then(|rates| rates.get(&from));
+        match (direct, inverse) {
+            (Some(&rate), _) => Some(rate),
+            (None, Some(rate)) => Some(rate.inverse()),
+            (None, None) => None,
+        }
+    }
+
+    /// Adds every rate in `other`, replacing rates already in this table.
+    pub fn extend(&mut self, other: &RateTable) {
+        for (&from, rates) in &other.0 {
+            for (&to, &rate) in rates {
+                self.set(from, to, rate);
+            }
+        }
+    }
+}
+
+/// Converts `amount` of `from` into `to`, rounding half a minor unit away from zero.
+pub fn convert_amount(
+    amount: Money,
+    from: Currency,
+    to: Currency,
+    rate: Rate,
+) -> Result<Money, ConvertError> {
+    let numerator = i128::from(amount.minor())
+        .checked_mul(i128::from(rate.0))
+        .and_then(|value| value.checked_mul(10_i128.pow(to.exponent())))
+        .ok_or(ConvertError::Overflow)?;
+    let denominator = RATE_SCALE * 10_i128.pow(from.exponent());
+
+    i64::try_from(round_div(numerator, denominator))
+        .ok()
+        .and_then(Money::new)
+        .ok_or(ConvertError::Overflow)
+}
+
+fn round_div(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    // compared without doubling the remainder, which could overflow
+    if remainder.abs() >= denominator - remainder.abs() {
+        quotient + numerator.signum()
+    } else {
+        quotient
+    }
+}
+
+/// The transfers that move every debt in a currency other than `target` into `target`, limited
+/// to debts involving every one of `users`.
+///
+/// Without any users every debt is moved, and with two only the debts between them.
+///
+/// Each debt is cancelled in its own currency and recorded again in `target`.
+pub fn conversion(
+    ledgers: &Ledgers,
+    users: &[UserId],
+    target: Currency,
+    rates: &RateTable,
+) -> Result<Vec<Transfer>, ConvertError> {
+    let mut currencies: Vec<Currency> = ledgers
+        .keys()
+        .copied()
+        .filter(|&currency| currency != target)
+        .collect();
+    currencies.sort();
+
+    let mut transfers = Vec::new();
+    for currency in currencies {
+        let mut debts = debts_between(ledgers, currency, users);
+        debts.sort();
+
+        if debts.is_empty() {
+            continue;
+        }
+
+        let rate = rates
+            .get(currency, target)
+            .ok_or(ConvertError::MissingRate(currency, target))?;
+
+        for (creditor, debtor, amount) in debts {
+            transfers.push(Transfer {
+                creditor,
+                debtor,
+                amount: -amount,
+                currency,
+            });
+            transfers.push(Transfer {
+                creditor,
+                debtor,
+                amount: convert_amount(amount, currency, target, rate)?,
+                currency: target,
+            });
+        }
+    }
+
+    Ok(transfers)
+}
+
+/// Whether `ledgers` hold any debt in a currency other than `target` involving every one of
+/// `users`, which [`conversion`] would move.
+pub fn has_debts_to_convert(ledgers: &Ledgers, users: &[UserId], target: Currency) -> bool {
+    ledgers
+        .keys()
+        .any(|&currency| currency != target && !debts_between(ledgers, currency, users).is_empty())
+}
+
+/// The positive debts in `currency` as (creditor, debtor, amount), involving every one of `users`.
+fn debts_between(
+    ledgers: &Ledgers,
+    currency: Currency,
+    users: &[UserId],
+) -> Vec<(UserId, UserId, Money)> {
+    ledgers[&currency]
+        .iter()
+        .flat_map(|(&creditor, account)| {
+            account
+                .iter()
+                .filter(|(_debtor, amount)| amount.is_positive())
+                .map(move |(&debtor, &amount)| (creditor, debtor, amount))
+        })
+        .filter(|&(creditor, debtor, _amount)| {
+            users.iter().all(|&user| creditor == user || debtor == user)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::accounts::apply_transfer;
+    use super::super::currency::{EUR, JPY, USD};
+    use super::*;
+
+    #[test]
+    fn test_rate_parse() {
+        assert_eq!(Some(Rate(1_082_500)), Rate::parse("1.0825"));
+        assert_eq!(Some(Rate(150_000_000)), Rate::parse("150"));
+        assert_eq!("1.0825", Rate(1_082_500).to_string());
+        assert!(Rate::parse("0").is_none());
+        assert!(Rate::parse("1.0000001").is_none());
+        assert!(Rate::parse("-1").is_none());
+    }
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    #[test]
+    fn test_convert_amount_rounding() {
+        let rate = Rate::parse("1.5").unwrap();
+        assert_eq!(Ok(money(150)), convert_amount(money(100), EUR, USD, rate));
+        assert_eq!(Ok(money(2)), convert_amount(money(1), EUR, USD, rate));
+        assert_eq!(Ok(money(-2)), convert_amount(money(-1), EUR, USD, rate));
+
+        // 12.34 USD at 150 JPY per dollar is 1851 yen
+        let rate = Rate::parse("150").unwrap();
+        assert_eq!(Ok(money(1851)), convert_amount(money(1234), USD, JPY, rate));
+
+        assert_eq!(
+            Err(ConvertError::Overflow),
+            convert_amount(money(i64::MAX), EUR, USD, rate)
+        );
+    }
+
+    #[test]
+    fn test_rate_inverse() {
+        let mut rates = RateTable::default();
+        rates.set(USD, JPY, Rate::parse("150").unwrap());
+        assert_eq!(Some(Rate(6_667)), rates.get(JPY, USD));
+        assert!(rates.get(EUR, USD).is_none());
+    }
+
+    #[test]
+    fn test_conversion_preserves_debts() {
+        let mut ledgers = Ledgers::new();
+        for (creditor, debtor, amount, currency) in
+            [(1, 2, 1000, EUR), (2, 3, 500, EUR), (1, 2, 300, USD)]
+        {
+            apply_transfer(
+                ledgers.entry(currency).or_default(),
+                &Transfer {
+                    creditor: UserId(creditor),
+                    debtor: UserId(debtor),
+                    amount: money(amount),
+                    currency,
+                },
+            )
+            .unwrap();
+        }
+
+        let mut rates = RateTable::default();
+        rates.set(EUR, USD, Rate::parse("1.1").unwrap());
+
+        let transfers = conversion(&ledgers, &[UserId(1)], USD, &rates).unwrap();
+        assert_eq!(2, transfers.len());
+        for transfer in &transfers {
+            apply_transfer(ledgers.entry(transfer.currency).or_default(), transfer).unwrap();
+        }
+
+        assert_eq!(money(1400), ledgers[&USD][&UserId(1)][&UserId(2)]);
+        assert_eq!(Money::ZERO, ledgers[&EUR][&UserId(1)][&UserId(2)]);
+        assert_eq!(money(500), ledgers[&EUR][&UserId(2)][&UserId(3)]);
+
+        assert!(conversion(&ledgers, &[UserId(1), UserId(3)], USD, &rates)
+            .unwrap()
+            .is_empty());
+        assert!(!has_debts_to_convert(
+            &ledgers,
+            &[UserId(1), UserId(3)],
+            USD
+        ));
+        assert!(!has_debts_to_convert(&ledgers, &[UserId(1)], USD));
+        assert!(has_debts_to_convert(&ledgers, &[UserId(3)], USD));
+        assert!(has_debts_to_convert(&ledgers, &[UserId(1)], EUR));
+        let between = conversion(&ledgers, &[UserId(3), UserId(2)], USD, &rates).unwrap();
+        assert_eq!(
+            vec![(UserId(2), UserId(3)), (UserId(2), UserId(3))],
+            between
+                .iter()
+                .map(|transfer| (transfer.creditor, transfer.debtor))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            Some(ConvertError::MissingRate(EUR, JPY)),
+            conversion(&ledgers, &[], JPY, &rates).err()
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::fmt::{self, Display, Formatter};
+
+/// A currency identified by its ISO 4217 code, with amounts kept in its minor unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency {
+    code: &'static str,
+    /// How many decimal places the minor unit is, e.g. 2 for cents and 0 for yen.
+    exponent: u32,
+    /// Symbol written before amounts, only for currencies it identifies unambiguously.
+    symbol: Option<&'static str>,
+}
+
+const fn currency(code: &'static str, exponent: u32, symbol: Option<&'static str>) -> Currency {
+    Currency {
+        code,
+        exponent,
+        symbol,
+    }
+}
+
+pub const USD: Currency = currency("USD", 2, Some("$"));
+pub const EUR: Currency = currency("EUR", 2, Some("€"));
+pub const GBP: Currency = currency("GBP", 2, Some("£"));
+pub const JPY: Currency = currency("JPY", 0, Some("¥"));
+
+/// Currencies amounts can be entered in.
+const CURRENCIES: &[Currency] = &[
+    USD,
+    EUR,
+    GBP,
+    JPY,
+    currency("AUD", 2, None),
+    currency("BHD", 3, None),
+    currency("BRL", 2, None),
+    currency("CAD", 2, None),
+    currency("CHF", 2, None),
+    currency("CNY", 2, None),
+    currency("CZK", 2, None),
+    currency("DKK", 2, None),
+    currency("HKD", 2, None),
+    currency("HUF", 2, None),
+    currency("IDR", 2, None),
+    currency("ILS", 2, Some("₪")),
+    currency("INR", 2, Some("₹")),
+    currency("ISK", 0, None),
+    currency("KRW", 0, Some("₩")),
+    currency("KWD", 3, None),
+    currency("MXN", 2, None),
+    currency("NOK", 2, None),
+    currency("NZD", 2, None),
+    currency("PHP", 2, Some("₱")),
+    currency("PLN", 2, None),
+    currency("SEK", 2, None),
+    currency("SGD", 2, None),
+    currency("THB", 2, Some("฿")),
+    currency("TRY", 2, Some("₺")),
+    currency("TWD", 2, None),
+    currency("VND", 0, Some("₫")),
+    currency("ZAR", 2, None),
+];
+
+impl Currency {
+    /// Looks up a currency by its ISO 4217 code, ignoring case.
+    pub fn from_code(code: &str) -> Option<Currency> {
+        CURRENCIES
+            .iter()
+            .find(|currency| currency.code.eq_ignore_ascii_case(code))
+            .copied()
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Currency> {
+        CURRENCIES
+            .iter()
+            .find(|currency| currency.symbol == Some(symbol))
+            .copied()
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn exponent(&self) -> u32 {
+        self.exponent
+    }
+
+    pub fn symbol(&self) -> Option<&'static str> {
+        self.symbol
+    }
+}
+
+impl Default for Currency {
+    /// Amounts recorded before currencies were tracked were all in dollars.
+    fn default() -> Self {
+        USD
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Currency::from_code(&code)
+            .ok_or_else(|| de::Error::custom(format!("unknown currency {}", code)))
+    }
+}
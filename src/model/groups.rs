@@ -0,0 +1,151 @@
+use super::ids::{GuildId, UserId};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fmt,
+};
+
+const MAX_NAME_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupError {
+    InvalidName(String),
+    AlreadyExists(String),
+    NotFound(String),
+}
+
+impl fmt::Display for GroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupError::InvalidName(name) => write!(
+                f,
+                "{} is not a valid group name, which must be 1 to {} letters, digits, - or _",
+                name, MAX_NAME_LENGTH
+            ),
+            GroupError::AlreadyExists(name) => write!(f, "there is already a group named {}", name),
+            GroupError::NotFound(name) => write!(f, "there is no group named {}", name),
+        }
+    }
+}
+
+impl Error for GroupError {}
+
+/// A saved set of users that can be billed together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    /// Who created the group, who can change it along with administrators.
+    pub owner: UserId,
+    pub members: BTreeSet<UserId>,
+}
+
+/// Every guild's groups, by name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedGroups {
+    guilds: HashMap<GuildId, BTreeMap<String, Group>>,
+}
+
+/// The name a group is stored under, ignoring case and surrounding spaces.
+pub fn group_name(name: &str) -> Result<String, GroupError> {
+    let name = name.trim().to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(GroupError::InvalidName(name))
+    }
+}
+
+impl SavedGroups {
+    pub fn create(
+        &mut self,
+        guild_id: GuildId,
+        name: &str,
+        owner: UserId,
+        members: impl IntoIterator<Item = UserId>,
+    ) -> Result<String, GroupError> {
+        let name = group_name(name)?;
+        let groups = self.guilds.entry(guild_id).or_default();
+        if groups.contains_key(&name) {
+            return Err(GroupError::AlreadyExists(name));
+        }
+        groups.insert(
+            name.clone(),
+            Group {
+                owner,
+                members: members.into_iter().collect(),
+            },
+        );
+        Ok(name)
+    }
+
+    pub fn get(&self, guild_id: GuildId, name: &str) -> Result<&Group, GroupError> {
+        let name = group_name(name)?;
+        match self
+            .guilds
+            .get(&guild_id)
+            .and_then(|groups| groups.get(&name))
+        {
+            Some(group) => Ok(group),
+            None => Err(GroupError::NotFound(name)),
+        }
+    }
+
+    pub fn get_mut(&mut self, guild_id: GuildId, name: &str) -> Result<&mut Group, GroupError> {
+        let name = group_name(name)?;
+        match self
+            .guilds
+            .get_mut(&guild_id)
+            .and_then(|groups| groups.get_mut(&name))
+        {
+            Some(group) => Ok(group),
+            None => Err(GroupError::NotFound(name)),
+        }
+    }
+
+    /// The groups in `guild_id`, by name.
+    pub fn in_guild(&self, guild_id: GuildId) -> Vec<(&str, &Group)> {
+        self.guilds
+            .get(&guild_id)
+            .into_iter()
+            .flatten()
+            .map(|(name, group)| (name.as_str(), group))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_ignore_case() {
+        let mut groups = SavedGroups::default();
+        let guild = GuildId(1);
+
+        assert_eq!(
+            groups.create(guild, " Flat-3 ", UserId(10), [UserId(10), UserId(20)]),
+            Ok("flat-3".to_string())
+        );
+        assert_eq!(
+            groups.create(guild, "FLAT-3", UserId(20), []),
+            Err(GroupError::AlreadyExists("flat-3".to_string()))
+        );
+        assert!(groups.create(guild, "flat 3", UserId(10), []).is_err());
+
+        groups
+            .get_mut(guild, "flat-3")
+            .unwrap()
+            .members
+            .insert(UserId(30));
+        assert_eq!(groups.get(guild, "Flat-3").unwrap().members.len(), 3);
+        assert!(groups.get(GuildId(2), "flat-3").is_err());
+        assert_eq!(groups.in_guild(guild).len(), 1);
+    }
+}
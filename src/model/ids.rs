@@ -0,0 +1,89 @@
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use std::fmt::{self, Display, Formatter};
+
+/// A user with balances, identified by the same number as on Discord.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct UserId(pub u64);
+
+/// A guild, whose balances are kept apart from every other guild's, identified by the same number
+/// as on Discord.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct GuildId(pub u64);
+
+impl Display for UserId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for GuildId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// ids are read from numbers or, as map keys are, from strings of them
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor).map(UserId)
+    }
+}
+
+impl<'de> Deserialize<'de> for GuildId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor).map(GuildId)
+    }
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "an id as a number or a string")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+        u64::try_from(value).map_err(|_e| E::custom(format!("{} is not an id", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_id_serde() {
+        assert_eq!(serde_json::to_string(&UserId(10)).unwrap(), "10");
+        assert_eq!(serde_json::from_str::<UserId>("10").unwrap(), UserId(10));
+        assert_eq!(
+            serde_json::from_str::<GuildId>("\"20\"").unwrap(),
+            GuildId(20)
+        );
+
+        let mut accounts = HashMap::new();
+        accounts.insert(UserId(10), GuildId(20));
+        let json = serde_json::to_string(&accounts).unwrap();
+        assert_eq!(json, "{\"10\":20}");
+        assert_eq!(
+            serde_json::from_str::<HashMap<UserId, GuildId>>(&json).unwrap(),
+            accounts
+        );
+        assert!(serde_json::from_str::<UserId>("-1").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use super::accounts::{apply_transfer, Ledgers, Transfer};
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+use super::transaction::{TransactionKind, TransactionType};
+use super::utils::{Money, MoneyOverflowError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, io,
+};
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    Overflow(MoneyOverflowError),
+    Invalid(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "{}", e),
+            StorageError::Json(e) => write!(f, "{}", e),
+            StorageError::Sqlite(e) => write!(f, "{}", e),
+            StorageError::Overflow(e) => write!(f, "{}", e),
+            StorageError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(e: serde_json::Error) -> Self {
+        StorageError::Json(e)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl From<MoneyOverflowError> for StorageError {
+    fn from(e: MoneyOverflowError) -> Self {
+        StorageError::Overflow(e)
+    }
+}
+
+/// A transaction as recorded in the journal, with everything needed to replay it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    /// When the entry was recorded, missing for entries written before timestamps were kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    pub guild_id: GuildId,
+    /// The group whose sub-ledger the entry was recorded in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub kind: TransactionKind,
+    pub initiator: UserId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voids: Option<u64>,
+    pub transfers: Vec<Transfer>,
+}
+
+impl JournalEntry {
+    /// Builds the entry recording `transaction` as number `seq`.
+    pub fn new(
+        seq: u64,
+        guild_id: GuildId,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<JournalEntry, StorageError> {
+        match (transaction.kind(), transaction.initiator()) {
+            (Some(kind), Some(initiator)) => Ok(JournalEntry {
+                seq,
+                timestamp: Some(Utc::now()),
+                guild_id,
+                group: group.map(str::to_string),
+                kind,
+                initiator,
+                description: transaction.description().map(str::to_string),
+                voids: transaction.voids(),
+                transfers: transaction.transfers(),
+            }),
+            _ => Err(StorageError::Invalid(
+                "transaction has no effect".to_string(),
+            )),
+        }
+    }
+
+    /// How much this entry changed what `counterparty` owes `user`, or what everyone owes `user`,
+    /// in each currency it involved.
+    pub fn net_for(
+        &self,
+        user: UserId,
+        counterparty: Option<UserId>,
+    ) -> Result<BTreeMap<Currency, Money>, MoneyOverflowError> {
+        let mut net = BTreeMap::new();
+        for transfer in &self.transfers {
+            let amount = if transfer.creditor == user
+                && counterparty.is_none_or(|other| transfer.debtor == other)
+            {
+                transfer.amount
+            } else if transfer.debtor == user
+                && counterparty.is_none_or(|other| transfer.creditor == other)
+            {
+                -transfer.amount
+            } else {
+                Money::ZERO
+            };
+            let total: &mut Money = net.entry(transfer.currency).or_default();
+            *total = total.checked_add(amount).ok_or(MoneyOverflowError)?;
+        }
+        Ok(net)
+    }
+
+    /// Whether `user` took part in this entry, either by recording it or by being affected by it.
+    pub fn involves(&self, user: UserId) -> bool {
+        self.initiator == user
+            || self
+                .transfers
+                .iter()
+                .any(|transfer| transfer.creditor == user || transfer.debtor == user)
+    }
+
+    /// Whether this entry moved money between `user` and `other` in either direction.
+    pub fn between(&self, user: UserId, other: UserId) -> bool {
+        self.transfers.iter().any(|transfer| {
+            (transfer.creditor == user && transfer.debtor == other)
+                || (transfer.creditor == other && transfer.debtor == user)
+        })
+    }
+
+    pub fn replay(
+        &self,
+        accounts: &mut HashMap<GuildId, Ledgers>,
+    ) -> Result<(), MoneyOverflowError> {
+        let ledgers = accounts.entry(self.guild_id).or_default();
+        for transfer in &self.transfers {
+            apply_transfer(ledgers.entry(transfer.currency).or_default(), transfer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which journal entries a query returns.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    pub guild_id: Option<GuildId>,
+    /// Only entries recorded in this group's sub-ledger.
+    pub group: Option<String>,
+    /// Only entries this user recorded or was affected by.
+    pub involving: Option<UserId>,
+    /// Only entries moving money between `involving` and this user.
+    pub counterparty: Option<UserId>,
+    pub initiator: Option<UserId>,
+    pub since: Option<DateTime<Utc>>,
+    pub upto: Option<u64>,
+    /// Only entries that could still be voided.
+    pub voidable: bool,
+}
+
+impl EntryFilter {
+    pub fn matches(&self, entry: &JournalEntry, is_voided: impl Fn(u64) -> bool) -> bool {
+        self.guild_id
+            .is_none_or(|guild_id| entry.guild_id == guild_id)
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|group| entry.group.as_ref() == Some(group))
+            && self.involving.is_none_or(|user| entry.involves(user))
+            && match (self.involving, self.counterparty) {
+                (Some(user), Some(other)) => entry.between(user, other),
+                _ => true,
+            }
+            && self
+                .initiator
+                .is_none_or(|initiator| entry.initiator == initiator)
+            && self
+                .since
+                .is_none_or(|since| entry.timestamp.is_some_and(|timestamp| timestamp >= since))
+            && self.upto.is_none_or(|upto| entry.seq <= upto)
+            && (!self.voidable || (entry.kind != TransactionKind::Void && !is_voided(entry.seq)))
+    }
+}
+
+/// Where balances and the transaction journal are kept.
+pub trait Storage: Send {
+    /// Loads the balances of every guild as of the most recent transaction.
+    fn load_balances(&mut self) -> Result<HashMap<GuildId, Ledgers>, StorageError>;
+
+    /// Saves the balances of every guild, so they can be loaded without replaying the journal.
+    fn save_balances(&mut self, accounts: &HashMap<GuildId, Ledgers>) -> Result<(), StorageError>;
+
+    /// Durably appends `transaction` to the journal, in `group`'s sub-ledger if there is one.
+    fn append(
+        &mut self,
+        guild_id: GuildId,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<JournalEntry, StorageError>;
+
+    fn get(&self, seq: u64) -> Result<Option<JournalEntry>, StorageError>;
+
+    fn is_voided(&self, seq: u64) -> Result<bool, StorageError>;
+
+    fn last_seq(&self) -> Result<u64, StorageError>;
+
+    /// The sequence number of the most recent entry recorded in `guild_id`.
+    fn last_seq_in(&self, guild_id: GuildId) -> Result<u64, StorageError>;
+
+    /// Entries matching `filter`, newest first, skipping `offset` and returning at most `limit`.
+    fn query(
+        &self,
+        filter: &EntryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<JournalEntry>, StorageError>;
+
+    fn count(&self, filter: &EntryFilter) -> Result<usize, StorageError>;
+
+    /// The balances in `group`'s sub-ledger, replayed from the entries recorded in it.
+    fn group_ledgers(&mut self, guild_id: GuildId, group: &str) -> Result<Ledgers, StorageError> {
+        let filter = EntryFilter {
+            guild_id: Some(guild_id),
+            group: Some(group.to_string()),
+            ..Default::default()
+        };
+
+        let mut ledgers = Ledgers::new();
+        for entry in self.query(&filter, 0, usize::MAX)?.iter().rev() {
+            for transfer in &entry.transfers {
+                apply_transfer(ledgers.entry(transfer.currency).or_default(), transfer)?;
+            }
+        }
+        Ok(ledgers)
+    }
+}
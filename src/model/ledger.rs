@@ -0,0 +1,692 @@
+use super::accounts::{apply_transfer, balance, Ledgers, Transfer};
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+use super::journal::{EntryFilter, JournalEntry, Storage, StorageError};
+use super::split::{split, SplitError, SplitMode};
+use super::transaction::{
+    BillTransaction, OweTransaction, SettleTransaction, TransactionKind, TransactionType,
+    VoidTransaction,
+};
+use super::utils::{format_money, Money, MoneyOverflowError};
+
+use std::{collections::HashMap, error::Error, fmt};
+
+/// Why a ledger operation was not carried out.
+#[derive(Debug)]
+pub enum LedgerError {
+    Storage(StorageError),
+    Split(SplitError),
+    /// Neither user owes the other anything in the currency.
+    NothingOutstanding,
+    /// A repayment of zero or less.
+    NotPositive,
+    /// A repayment larger than the amount owed, which is given.
+    MoreThanOutstanding(Money, Currency),
+    NotFound(u64),
+    /// The transaction is itself a void.
+    VoidsAnother(u64),
+    AlreadyVoided(u64),
+    /// Someone other than the initiator or an administrator tried to void the transaction.
+    NotInitiator(u64),
+    NothingToUndo,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::Storage(e) => write!(f, "{}", e),
+            LedgerError::Split(e) => write!(f, "{}", e),
+            LedgerError::NothingOutstanding => write!(f, "nothing is outstanding"),
+            LedgerError::NotPositive => write!(f, "the amount repaid must be more than zero"),
+            LedgerError::MoreThanOutstanding(outstanding, currency) => write!(
+                f,
+                "only {} is outstanding",
+                format_money(*outstanding, *currency)
+            ),
+            LedgerError::NotFound(seq) => write!(f, "there is no transaction #{}", seq),
+            LedgerError::VoidsAnother(seq) => write!(
+                f,
+                "#{} voids another transaction and can't be voided itself",
+                seq
+            ),
+            LedgerError::AlreadyVoided(seq) => write!(f, "transaction #{} was already voided", seq),
+            LedgerError::NotInitiator(seq) => write!(
+                f,
+                "only whoever recorded #{} or an administrator can void it",
+                seq
+            ),
+            LedgerError::NothingToUndo => write!(f, "there are no transactions left to undo"),
+        }
+    }
+}
+
+impl Error for LedgerError {}
+
+impl From<StorageError> for LedgerError {
+    fn from(e: StorageError) -> Self {
+        LedgerError::Storage(e)
+    }
+}
+
+impl From<MoneyOverflowError> for LedgerError {
+    fn from(e: MoneyOverflowError) -> Self {
+        LedgerError::Storage(StorageError::Overflow(e))
+    }
+}
+
+impl From<SplitError> for LedgerError {
+    fn from(e: SplitError) -> Self {
+        LedgerError::Split(e)
+    }
+}
+
+/// A guild's balances together with the journal they are kept from, which every change is
+/// recorded in before it is applied.
+///
+/// A book for a group records changes in the group's sub-ledger as well as the guild's balances,
+/// so the guild's balances are always the total of every group and everything recorded outside
+/// of them.
+pub struct Book<'a> {
+    guild_id: GuildId,
+    group: Option<String>,
+    accounts: &'a mut HashMap<GuildId, Ledgers>,
+    journal: &'a mut dyn Storage,
+}
+
+impl<'a> Book<'a> {
+    pub fn new(
+        guild_id: GuildId,
+        accounts: &'a mut HashMap<GuildId, Ledgers>,
+        journal: &'a mut dyn Storage,
+    ) -> Book<'a> {
+        Book {
+            guild_id,
+            group: None,
+            accounts,
+            journal,
+        }
+    }
+
+    /// Scopes the book to `group`'s sub-ledger, or to the whole guild without a group.
+    pub fn in_group(mut self, group: Option<&str>) -> Book<'a> {
+        self.group = group.map(str::to_string);
+        self
+    }
+
+    /// Durably records `transaction` in the journal, then applies it to the guild's ledger.
+    ///
+    /// Nothing is recorded if any balance it changes would overflow. Returns the sequence number
+    /// the transaction was recorded under.
+    pub fn record(&mut self, transaction: &TransactionType) -> Result<u64, StorageError> {
+        let group = self.group.clone();
+        self.record_in(group.as_deref(), transaction)
+    }
+
+    fn record_in(
+        &mut self,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<u64, StorageError> {
+        // apply the transfers to copies of the ledgers they change first, so a transaction that
+        // can't be applied isn't recorded
+        let current = self.accounts.get(&self.guild_id);
+        let mut changed = Ledgers::new();
+        for transfer in &transaction.transfers() {
+            let ledger = changed.entry(transfer.currency).or_insert_with(|| {
+                current
+                    .and_then(|ledgers| ledgers.get(&transfer.currency))
+                    .cloned()
+                    .unwrap_or_default()
+            });
+            apply_transfer(ledger, transfer)?;
+        }
+
+        let entry = self.journal.append(self.guild_id, group, transaction)?;
+        self.accounts
+            .entry(self.guild_id)
+            .or_default()
+            .extend(changed);
+
+        Ok(entry.seq)
+    }
+
+    /// The balances in the book's group, or in the whole guild without a group.
+    pub fn ledgers(&mut self) -> Result<Ledgers, StorageError> {
+        match &self.group {
+            Some(group) => self.journal.group_ledgers(self.guild_id, group),
+            None => Ok(self
+                .accounts
+                .get(&self.guild_id)
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+
+    /// The balances in another of the guild's groups than the one the book is scoped to.
+    pub fn group_ledgers(&mut self, group: &str) -> Result<Ledgers, StorageError> {
+        self.journal.group_ledgers(self.guild_id, group)
+    }
+
+    /// The sequence number of the guild's latest transaction.
+    pub fn last_seq(&self) -> Result<u64, StorageError> {
+        self.journal.last_seq_in(self.guild_id)
+    }
+
+    /// Records that `initiator` owes `recipient` the given amount.
+    pub fn owe(
+        &mut self,
+        initiator: UserId,
+        recipient: UserId,
+        amount: Money,
+        currency: Currency,
+        description: &str,
+    ) -> Result<(u64, OweTransaction), LedgerError> {
+        let transaction = OweTransaction {
+            initiator,
+            amount,
+            recipient,
+            description: description.to_string(),
+            currency,
+        };
+
+        let seq = self.record(&TransactionType::Owe(transaction.clone()))?;
+        Ok((seq, transaction))
+    }
+
+    /// Bills each participant their part of `amount` as divided by `mode`, in the order given.
+    ///
+    /// The initiator may be one of the participants, in which case their part isn't billed.
+    pub fn bill(
+        &mut self,
+        initiator: UserId,
+        participants: &[UserId],
+        amount: Money,
+        currency: Currency,
+        mode: &SplitMode,
+        description: &str,
+    ) -> Result<(u64, BillTransaction), LedgerError> {
+        let transaction = BillTransaction {
+            initiator,
+            amount,
+            recipients: bill_shares(initiator, participants, amount, mode)?,
+            description: description.to_string(),
+            currency,
+        };
+
+        let seq = self.record(&TransactionType::Bill(transaction.clone()))?;
+        Ok((seq, transaction))
+    }
+
+    /// Records a repayment between `initiator` and `other` in whichever direction is owed, of
+    /// `amount` or otherwise everything outstanding in `currency`.
+    ///
+    /// In a group, no more is outstanding than is owed in the guild as a whole, so debts already
+    /// repaid outside the group can't be repaid again.
+    pub fn settle(
+        &mut self,
+        initiator: UserId,
+        other: UserId,
+        amount: Option<Money>,
+        currency: Currency,
+    ) -> Result<(u64, SettleTransaction), LedgerError> {
+        // positive when the other user owes the initiator
+        let total = self
+            .accounts
+            .get(&self.guild_id)
+            .and_then(|ledgers| ledgers.get(&currency))
+            .map_or(Money::ZERO, |ledger| balance(ledger, initiator, other));
+        let outstanding = match &self.group {
+            Some(group) => {
+                let in_group = self
+                    .journal
+                    .group_ledgers(self.guild_id, group)?
+                    .get(&currency)
+                    .map_or(Money::ZERO, |ledger| balance(ledger, initiator, other));
+                // whatever was repaid outside the group is no longer owed in it either
+                if in_group.is_positive() && total.is_positive() {
+                    in_group.min(total)
+                } else if in_group.is_negative() && total.is_negative() {
+                    in_group.max(total)
+                } else {
+                    Money::ZERO
+                }
+            }
+            None => total,
+        };
+
+        let amount = match amount {
+            _ if outstanding == Money::ZERO => return Err(LedgerError::NothingOutstanding),
+            Some(amount) if !amount.is_positive() => return Err(LedgerError::NotPositive),
+            Some(amount) if amount > outstanding.abs() => {
+                return Err(LedgerError::MoreThanOutstanding(
+                    outstanding.abs(),
+                    currency,
+                ))
+            }
+            Some(amount) => amount,
+            None => outstanding.abs(),
+        };
+
+        let (payer, payee) = if outstanding.is_positive() {
+            (other, initiator)
+        } else {
+            (initiator, other)
+        };
+
+        let transaction = SettleTransaction {
+            initiator,
+            payer,
+            amount,
+            payee,
+            currency,
+        };
+
+        let seq = self.record(&TransactionType::Settle(transaction.clone()))?;
+        Ok((seq, transaction))
+    }
+
+    /// Reverses the effect of transaction `voided`, if `user` initiated it or is an administrator.
+    ///
+    /// The void is recorded in the same group as the transaction, whichever group the book is for.
+    pub fn void(
+        &mut self,
+        user: UserId,
+        is_admin: bool,
+        voided: u64,
+    ) -> Result<(u64, VoidTransaction), LedgerError> {
+        let entry = match self.journal.get(voided)? {
+            Some(entry) if entry.guild_id == self.guild_id => entry,
+            _ => return Err(LedgerError::NotFound(voided)),
+        };
+
+        if entry.kind == TransactionKind::Void {
+            return Err(LedgerError::VoidsAnother(voided));
+        }
+        if entry.initiator != user && !is_admin {
+            return Err(LedgerError::NotInitiator(voided));
+        }
+        if self.journal.is_voided(voided)? {
+            return Err(LedgerError::AlreadyVoided(voided));
+        }
+
+        let transaction = VoidTransaction {
+            initiator: user,
+            voided,
+            transfers: entry
+                .transfers
+                .iter()
+                .map(|transfer| Transfer {
+                    amount: -transfer.amount,
+                    ..transfer.clone()
+                })
+                .collect(),
+        };
+
+        let seq = self.record_in(
+            entry.group.as_deref(),
+            &TransactionType::Void(transaction.clone()),
+        )?;
+        Ok((seq, transaction))
+    }
+
+    /// Voids the most recent transaction `user` recorded that can still be voided.
+    pub fn undo(&mut self, user: UserId) -> Result<(u64, VoidTransaction), LedgerError> {
+        let filter = EntryFilter {
+            guild_id: Some(self.guild_id),
+            initiator: Some(user),
+            voidable: true,
+            ..Default::default()
+        };
+
+        match self.journal.query(&filter, 0, 1)?.first() {
+            Some(entry) => self.void(user, false, entry.seq),
+            None => Err(LedgerError::NothingToUndo),
+        }
+    }
+}
+
+/// What each participant other than the initiator is billed when `amount` is divided by `mode`.
+pub fn bill_shares(
+    initiator: UserId,
+    participants: &[UserId],
+    amount: Money,
+    mode: &SplitMode,
+) -> Result<Vec<(UserId, Money)>, SplitError> {
+    let shares = split(amount, participants.len(), mode)?;
+    Ok(participants
+        .iter()
+        .copied()
+        .zip(shares)
+        .filter(|&(user, _share)| user != initiator)
+        .collect())
+}
+
+/// What everyone owes `user` in `guild_id`, negative where `user` owes them, ordered by currency
+/// and then by user.
+pub fn balances(
+    accounts: &HashMap<GuildId, Ledgers>,
+    guild_id: GuildId,
+    user: UserId,
+) -> Vec<(Currency, UserId, Money)> {
+    let mut balances: Vec<(Currency, UserId, Money)> = accounts
+        .get(&guild_id)
+        .into_iter()
+        .flatten()
+        .filter_map(|(&currency, ledger)| Some((currency, ledger.get(&user)?)))
+        .flat_map(|(currency, account)| {
+            account
+                .iter()
+                .map(move |(&other, &amount)| (currency, other, amount))
+        })
+        .collect();
+    balances.sort_by_key(|&(currency, other, _amount)| (currency, other));
+    balances
+}
+
+/// A user's non-zero balances in one currency, split by who owes whom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceSummary {
+    pub currency: Currency,
+    /// Users who owe the user, and how much.
+    pub owed: Vec<(UserId, Money)>,
+    /// Users the user owes, and how much.
+    pub owing: Vec<(UserId, Money)>,
+    /// How much the user is owed in total, negative when they owe more than they are owed.
+    pub net: Money,
+}
+
+/// Groups balances as returned by `balances` into a summary for each currency, in the order the
+/// currencies first appear.
+pub fn summarize(
+    balances: &[(Currency, UserId, Money)],
+) -> Result<Vec<BalanceSummary>, MoneyOverflowError> {
+    let mut summaries: Vec<BalanceSummary> = Vec::new();
+    for &(currency, other, amount) in balances {
+        if amount == Money::ZERO {
+            continue;
+        }
+
+        let index = match summaries
+            .iter()
+            .position(|summary| summary.currency == currency)
+        {
+            Some(index) => index,
+            None => {
+                summaries.push(BalanceSummary {
+                    currency,
+                    owed: Vec::new(),
+                    owing: Vec::new(),
+                    net: Money::ZERO,
+                });
+                summaries.len() - 1
+            }
+        };
+        let summary = &mut summaries[index];
+
+        if amount.is_positive() {
+            summary.owed.push((other, amount));
+        } else {
+            summary.owing.push((other, amount.abs()));
+        }
+        summary.net = summary.net.checked_add(amount).ok_or(MoneyOverflowError)?;
+    }
+    Ok(summaries)
+}
+
+/// One page of the journal entries matching a filter, newest first.
+pub struct HistoryPage {
+    /// Which page this is, counting from 0.
+    pub page: usize,
+    /// How many pages there are, at least 1 even if there are no entries.
+    pub pages: usize,
+    /// Each entry along with whether it has been voided.
+    pub entries: Vec<(JournalEntry, bool)>,
+}
+
+/// Page `page` of the entries matching `filter`, or the last page if there aren't that many.
+pub fn history(
+    journal: &dyn Storage,
+    filter: &EntryFilter,
+    page: usize,
+    limit: usize,
+) -> Result<HistoryPage, StorageError> {
+    let limit = limit.max(1);
+    let pages = journal.count(filter)?.div_ceil(limit).max(1);
+    let page = page.min(pages - 1);
+
+    let mut entries = Vec::new();
+    for entry in journal.query(filter, page * limit, limit)? {
+        let voided = journal.is_voided(entry.seq)?;
+        entries.push((entry, voided));
+    }
+
+    Ok(HistoryPage {
+        page,
+        pages,
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::currency::USD;
+    use crate::storage::sqlite::SqliteStorage;
+
+    const GUILD: GuildId = GuildId(1);
+    const ALICE: UserId = UserId(10);
+    const BOB: UserId = UserId(20);
+    const CAROL: UserId = UserId(30);
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    fn owed(accounts: &HashMap<GuildId, Ledgers>, user: UserId, other: UserId) -> Money {
+        accounts
+            .get(&GUILD)
+            .and_then(|ledgers| ledgers.get(&USD))
+            .map_or(Money::ZERO, |ledger| balance(ledger, user, other))
+    }
+
+    #[test]
+    fn test_owe_and_settle() {
+        let mut accounts = HashMap::new();
+        let mut journal = SqliteStorage::open(":memory:").unwrap();
+        let mut book = Book::new(GUILD, &mut accounts, &mut journal);
+
+        let (seq, _owe) = book.owe(ALICE, BOB, money(1250), USD, "lunch").unwrap();
+        assert_eq!(seq, 1);
+
+        assert!(matches!(
+            book.settle(BOB, ALICE, Some(money(2000)), USD),
+            Err(LedgerError::MoreThanOutstanding(amount, currency)) if amount == money(1250) && currency == USD
+        ));
+        assert!(matches!(
+            book.settle(BOB, ALICE, Some(Money::ZERO), USD),
+            Err(LedgerError::NotPositive)
+        ));
+
+        let (_seq, settle) = book.settle(BOB, ALICE, None, USD).unwrap();
+        assert_eq!((settle.payer, settle.payee), (ALICE, BOB));
+        assert_eq!(settle.amount, money(1250));
+
+        assert!(matches!(
+            book.settle(BOB, ALICE, None, USD),
+            Err(LedgerError::NothingOutstanding)
+        ));
+        assert_eq!(owed(&accounts, BOB, ALICE), Money::ZERO);
+    }
+
+    #[test]
+    fn test_bill_skips_initiator() {
+        let mut accounts = HashMap::new();
+        let mut journal = SqliteStorage::open(":memory:").unwrap();
+        let mut book = Book::new(GUILD, &mut accounts, &mut journal);
+
+        let (_seq, bill) = book
+            .bill(
+                ALICE,
+                &[ALICE, BOB, CAROL],
+                money(1000),
+                USD,
+                &SplitMode::Equal,
+                "pizza",
+            )
+            .unwrap();
+        assert_eq!(
+            bill.recipients,
+            vec![(BOB, money(333)), (CAROL, money(333))]
+        );
+
+        assert!(matches!(
+            book.bill(ALICE, &[], money(1000), USD, &SplitMode::Equal, "pizza"),
+            Err(LedgerError::Split(SplitError::NoParticipants))
+        ));
+
+        assert_eq!(
+            balances(&accounts, GUILD, ALICE),
+            vec![(USD, BOB, money(333)), (USD, CAROL, money(333))]
+        );
+    }
+
+    #[test]
+    fn test_summarize_by_currency() {
+        let eur = Currency::from_code("EUR").unwrap();
+        let balances = [
+            (eur, BOB, money(-500)),
+            (USD, BOB, money(1200)),
+            (USD, CAROL, Money::ZERO),
+            (USD, CAROL, money(-200)),
+        ];
+
+        assert_eq!(
+            summarize(&balances).unwrap(),
+            vec![
+                BalanceSummary {
+                    currency: eur,
+                    owed: Vec::new(),
+                    owing: vec![(BOB, money(500))],
+                    net: money(-500),
+                },
+                BalanceSummary {
+                    currency: USD,
+                    owed: vec![(BOB, money(1200))],
+                    owing: vec![(CAROL, money(200))],
+                    net: money(1000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_void_and_undo() {
+        let mut accounts = HashMap::new();
+        let mut journal = SqliteStorage::open(":memory:").unwrap();
+        let mut book = Book::new(GUILD, &mut accounts, &mut journal);
+
+        let (owe, _owe) = book.owe(ALICE, BOB, money(500), USD, "coffee").unwrap();
+
+        assert!(matches!(
+            book.void(CAROL, false, owe),
+            Err(LedgerError::NotInitiator(_))
+        ));
+        assert!(matches!(
+            book.void(ALICE, false, 99),
+            Err(LedgerError::NotFound(99))
+        ));
+
+        let (void, _void) = book.undo(ALICE).unwrap();
+        assert!(matches!(
+            book.void(ALICE, false, owe),
+            Err(LedgerError::AlreadyVoided(_))
+        ));
+        assert!(matches!(
+            book.void(ALICE, true, void),
+            Err(LedgerError::VoidsAnother(_))
+        ));
+        assert!(matches!(book.undo(ALICE), Err(LedgerError::NothingToUndo)));
+
+        assert_eq!(owed(&accounts, ALICE, BOB), Money::ZERO);
+
+        let page = history(&journal, &EntryFilter::default(), 5, 1).unwrap();
+        assert_eq!((page.page, page.pages), (1, 2));
+        assert_eq!(page.entries[0].0.seq, owe);
+        assert!(page.entries[0].1);
+    }
+
+    #[test]
+    fn test_group_sub_ledgers() {
+        let mut accounts = HashMap::new();
+        let mut journal = SqliteStorage::open(":memory:").unwrap();
+
+        let (rent, _bill) = Book::new(GUILD, &mut accounts, &mut journal)
+            .in_group(Some("flat"))
+            .bill(
+                ALICE,
+                &[ALICE, BOB],
+                money(1000),
+                USD,
+                &SplitMode::Equal,
+                "rent",
+            )
+            .unwrap();
+        Book::new(GUILD, &mut accounts, &mut journal)
+            .owe(BOB, ALICE, money(300), USD, "coffee")
+            .unwrap();
+
+        // the guild's balances include both, but the group's only the bill made in it
+        assert_eq!(owed(&accounts, ALICE, BOB), money(800));
+        let flat = journal.group_ledgers(GUILD, "flat").unwrap();
+        assert_eq!(balance(&flat[&USD], ALICE, BOB), money(500));
+
+        assert!(matches!(
+            Book::new(GUILD, &mut accounts, &mut journal)
+                .in_group(Some("flat"))
+                .settle(BOB, ALICE, Some(money(600)), USD),
+            Err(LedgerError::MoreThanOutstanding(amount, _currency)) if amount == money(500)
+        ));
+
+        // voiding the bill from outside the group still takes it out of the group
+        Book::new(GUILD, &mut accounts, &mut journal)
+            .void(ALICE, false, rent)
+            .unwrap();
+        let flat = journal.group_ledgers(GUILD, "flat").unwrap();
+        assert_eq!(balance(&flat[&USD], ALICE, BOB), Money::ZERO);
+        assert_eq!(owed(&accounts, ALICE, BOB), money(300));
+    }
+
+    #[test]
+    fn test_group_settle_after_settling_outside() {
+        let mut accounts = HashMap::new();
+        let mut journal = SqliteStorage::open(":memory:").unwrap();
+
+        Book::new(GUILD, &mut accounts, &mut journal)
+            .in_group(Some("flat"))
+            .owe(ALICE, BOB, money(1000), USD, "groceries")
+            .unwrap();
+        Book::new(GUILD, &mut accounts, &mut journal)
+            .settle(ALICE, BOB, None, USD)
+            .unwrap();
+
+        assert!(matches!(
+            Book::new(GUILD, &mut accounts, &mut journal)
+                .in_group(Some("flat"))
+                .settle(ALICE, BOB, None, USD),
+            Err(LedgerError::NothingOutstanding)
+        ));
+        assert_eq!(owed(&accounts, BOB, ALICE), Money::ZERO);
+
+        // only the part still owed overall can be settled in the group
+        Book::new(GUILD, &mut accounts, &mut journal)
+            .owe(ALICE, BOB, money(300), USD, "coffee")
+            .unwrap();
+        let (_seq, settle) = Book::new(GUILD, &mut accounts, &mut journal)
+            .in_group(Some("flat"))
+            .settle(ALICE, BOB, None, USD)
+            .unwrap();
+        assert_eq!(settle.amount, money(300));
+        assert_eq!(owed(&accounts, BOB, ALICE), Money::ZERO);
+    }
+}
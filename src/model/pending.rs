@@ -0,0 +1,353 @@
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+use super::transaction::{
+    BillTransaction, ConvertTransaction, OweTransaction, TransactionKind, TransactionType,
+};
+use super::utils::Money;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use std::{collections::BTreeMap, error::Error, fmt};
+
+/// How a party to a pending request has answered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confirmation {
+    Pending,
+    /// Accepted, and recorded under the given journal sequence number.
+    Accepted(u64),
+    Disputed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingError {
+    /// The user wasn't asked to confirm the request.
+    NotAParty,
+    AlreadyAnswered,
+    Expired,
+}
+
+impl fmt::Display for PendingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PendingError::NotAParty => write!(f, "you weren't asked to confirm this"),
+            PendingError::AlreadyAnswered => write!(f, "you already answered this"),
+            PendingError::Expired => write!(f, "this request has expired"),
+        }
+    }
+}
+
+impl Error for PendingError {}
+
+/// An `/owe`, `/bill` or `/convert` held back until the other users involved confirm it.
+///
+/// Each party's part is recorded as its own transaction when they accept it, and nothing is
+/// recorded for parties who dispute it or don't answer before it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingRequest {
+    pub guild_id: GuildId,
+    /// The group whose sub-ledger the request is recorded in once accepted, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub kind: TransactionKind,
+    pub initiator: UserId,
+    /// Amount entered for the transaction, or zero for a conversion.
+    pub amount: Money,
+    /// The currency of the amounts, which a conversion converts debts into.
+    pub currency: Currency,
+    pub description: String,
+    pub expires: DateTime<Utc>,
+    /// Everyone asked to confirm, with the amount involving them and their answer so far.
+    pub parties: Vec<(UserId, Money, Confirmation)>,
+}
+
+impl PendingRequest {
+    /// A bill waiting for each recipient to accept their share.
+    pub fn bill(
+        guild_id: GuildId,
+        initiator: UserId,
+        amount: Money,
+        currency: Currency,
+        description: &str,
+        shares: Vec<(UserId, Money)>,
+        expires: DateTime<Utc>,
+    ) -> PendingRequest {
+        PendingRequest {
+            guild_id,
+            group: None,
+            kind: TransactionKind::Bill,
+            initiator,
+            amount,
+            currency,
+            description: description.to_string(),
+            expires,
+            parties: shares
+                .into_iter()
+                .map(|(user, share)| (user, share, Confirmation::Pending))
+                .collect(),
+        }
+    }
+
+    /// A conversion of the debts between the initiator and each party into `currency`, waiting for
+    /// each party to agree to theirs.
+    ///
+    /// Each party's amount is what they would owe the initiator once converted.
+    pub fn conversion(
+        guild_id: GuildId,
+        initiator: UserId,
+        currency: Currency,
+        parties: Vec<(UserId, Money)>,
+        expires: DateTime<Utc>,
+    ) -> PendingRequest {
+        PendingRequest {
+            guild_id,
+            group: None,
+            kind: TransactionKind::Convert,
+            initiator,
+            amount: Money::ZERO,
+            currency,
+            description: format!("converting debts into {}", currency),
+            expires,
+            parties: parties
+                .into_iter()
+                .map(|(user, amount)| (user, amount, Confirmation::Pending))
+                .collect(),
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires
+    }
+
+    /// Whether every party has answered.
+    pub fn is_answered(&self) -> bool {
+        self.parties
+            .iter()
+            .all(|&(_party, _share, confirmation)| confirmation != Confirmation::Pending)
+    }
+
+    /// The transaction recording `party`'s part of the request.
+    ///
+    /// A conversion has no transfers yet, as which debts it converts is only known once it's
+    /// recorded against the balances at the time.
+    fn transaction(&self, party: UserId, share: Money) -> TransactionType {
+        match self.kind {
+            TransactionKind::Convert => TransactionType::Convert(ConvertTransaction {
+                initiator: self.initiator,
+                currency: self.currency,
+                transfers: Vec::new(),
+            }),
+            TransactionKind::Owe => TransactionType::Owe(OweTransaction {
+                initiator: self.initiator,
+                amount: share,
+                recipient: party,
+                description: self.description.clone(),
+                currency: self.currency,
+            }),
+            _ => TransactionType::Bill(BillTransaction {
+                initiator: self.initiator,
+                amount: self.amount,
+                recipients: vec![(party, share)],
+                description: self.description.clone(),
+                currency: self.currency,
+            }),
+        }
+    }
+
+    /// The transaction to record once `user` accepts their part, if they still can.
+    pub fn accept(
+        &self,
+        user: UserId,
+        now: DateTime<Utc>,
+    ) -> Result<TransactionType, PendingError> {
+        let share = self.pending_share(user, now)?;
+        Ok(self.transaction(user, share))
+    }
+
+    /// Checks that `user` can still dispute their part.
+    pub fn dispute(&self, user: UserId, now: DateTime<Utc>) -> Result<(), PendingError> {
+        self.pending_share(user, now).map(|_share| ())
+    }
+
+    fn pending_share(&self, user: UserId, now: DateTime<Utc>) -> Result<Money, PendingError> {
+        if self.is_expired(now) {
+            return Err(PendingError::Expired);
+        }
+        match self
+            .parties
+            .iter()
+            .find(|(party, _share, _confirmation)| *party == user)
+        {
+            Some(&(_party, share, Confirmation::Pending)) => Ok(share),
+            Some(_party) => Err(PendingError::AlreadyAnswered),
+            None => Err(PendingError::NotAParty),
+        }
+    }
+
+    /// Records how `user` answered.
+    pub fn answer(&mut self, user: UserId, confirmation: Confirmation) {
+        for (party, _share, answer) in &mut self.parties {
+            if *party == user {
+                *answer = confirmation;
+            }
+        }
+    }
+
+    /// What the unanswered parts would change between `user` and each other user, positive
+    /// when the other user would owe `user`.
+    pub fn pending_for(&self, user: UserId) -> Vec<(UserId, Money)> {
+        let mut pending = Vec::new();
+        for &(party, share, confirmation) in &self.parties {
+            if confirmation != Confirmation::Pending {
+                continue;
+            }
+            for transfer in self.transaction(party, share).transfers() {
+                if transfer.creditor == user {
+                    pending.push((transfer.debtor, transfer.amount));
+                } else if transfer.debtor == user {
+                    pending.push((transfer.creditor, -transfer.amount));
+                }
+            }
+        }
+        pending
+    }
+}
+
+/// Every request still waiting for an answer, by id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PendingRequests {
+    next_id: u64,
+    requests: BTreeMap<u64, PendingRequest>,
+}
+
+impl PendingRequests {
+    /// Adds `request`, returning the id it can be looked up by.
+    pub fn insert(&mut self, request: PendingRequest) -> u64 {
+        self.next_id += 1;
+        self.requests.insert(self.next_id, request);
+        self.next_id
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut PendingRequest> {
+        self.requests.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<PendingRequest> {
+        self.requests.remove(&id)
+    }
+
+    /// Removes expired requests, returning how many there were.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.requests.len();
+        self.requests
+            .retain(|_id, request| !request.is_expired(now));
+        before - self.requests.len()
+    }
+
+    /// What the unanswered requests in `guild_id` would change between `user` and each other
+    /// user, positive when the other user would owe `user`.
+    ///
+    /// Only requests in `group` are included if one is given.
+    pub fn pending_for(
+        &self,
+        guild_id: GuildId,
+        group: Option<&str>,
+        user: UserId,
+    ) -> Vec<(Currency, UserId, Money)> {
+        self.requests
+            .values()
+            .filter(|request| request.guild_id == guild_id)
+            .filter(|request| group.is_none_or(|group| request.group.as_deref() == Some(group)))
+            .flat_map(|request| {
+                request
+                    .pending_for(user)
+                    .into_iter()
+                    .map(move |(other, amount)| (request.currency, other, amount))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::currency::USD;
+
+    use chrono::Duration;
+
+    const ALICE: UserId = UserId(10);
+    const BOB: UserId = UserId(20);
+    const CAROL: UserId = UserId(30);
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    fn bill(expires: DateTime<Utc>) -> PendingRequest {
+        PendingRequest {
+            guild_id: GuildId(1),
+            group: None,
+            kind: TransactionKind::Bill,
+            initiator: ALICE,
+            amount: money(1000),
+            currency: USD,
+            description: "pizza".to_string(),
+            expires,
+            parties: vec![
+                (BOB, money(500), Confirmation::Pending),
+                (CAROL, money(500), Confirmation::Pending),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_answer_each_part() {
+        let now = Utc::now();
+        let mut request = bill(now + Duration::hours(1));
+
+        assert_eq!(
+            request.accept(ALICE, now).err(),
+            Some(PendingError::NotAParty)
+        );
+
+        let transfers = request.accept(BOB, now).unwrap().transfers();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].creditor, transfers[0].debtor), (ALICE, BOB));
+        assert_eq!(transfers[0].amount, money(500));
+
+        request.answer(BOB, Confirmation::Accepted(1));
+        assert_eq!(
+            request.dispute(BOB, now),
+            Err(PendingError::AlreadyAnswered)
+        );
+        assert_eq!(request.pending_for(ALICE), vec![(CAROL, money(500))]);
+        assert_eq!(request.pending_for(CAROL), vec![(ALICE, money(-500))]);
+        assert!(!request.is_answered());
+
+        request.answer(CAROL, Confirmation::Disputed);
+        assert!(request.is_answered());
+        assert!(request.pending_for(ALICE).is_empty());
+    }
+
+    #[test]
+    fn test_expire_requests() {
+        let now = Utc::now();
+        let mut requests = PendingRequests::default();
+        let expired = requests.insert(bill(now - Duration::minutes(1)));
+        let current = requests.insert(bill(now + Duration::minutes(1)));
+
+        assert_eq!(
+            requests.get_mut(expired).unwrap().dispute(BOB, now),
+            Err(PendingError::Expired)
+        );
+        assert_eq!(requests.expire(now), 1);
+        assert!(requests.get_mut(expired).is_none());
+        assert_eq!(
+            requests.pending_for(GuildId(1), None, BOB),
+            vec![(USD, ALICE, money(-500))]
+        );
+        assert!(requests.get_mut(current).is_some());
+    }
+}
@@ -0,0 +1,191 @@
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+use super::schedule::Schedule;
+use super::split::SplitMode;
+use super::utils::Money;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::ChannelId;
+
+use std::collections::BTreeMap;
+
+/// Most runs billed at once for a schedule that was missed while the bot was down, the rest
+/// being skipped.
+pub const MAX_CATCH_UP: usize = 12;
+
+/// A bill made again each time its schedule comes round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecurringBill {
+    pub guild_id: GuildId,
+    /// Where each run is posted.
+    pub channel_id: ChannelId,
+    pub initiator: UserId,
+    pub amount: Money,
+    pub currency: Currency,
+    pub description: String,
+    pub mode: SplitMode,
+    pub participants: Vec<UserId>,
+    pub schedule: Schedule,
+    pub next_run: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+}
+
+/// The runs of a bill that came due, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DueRuns {
+    pub runs: Vec<DateTime<Utc>>,
+    /// Runs missed for so long that they were skipped rather than billed.
+    pub skipped: usize,
+}
+
+impl RecurringBill {
+    /// Moves `next_run` past `now`, returning the runs passed over.
+    ///
+    /// Only the latest `MAX_CATCH_UP` runs are returned. A schedule that can't run again is paused.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> DueRuns {
+        let mut runs = Vec::new();
+        while self.next_run <= now {
+            runs.push(self.next_run);
+            match self.schedule.next_after(self.next_run) {
+                Some(next_run) => self.next_run = next_run,
+                None => {
+                    self.paused = true;
+                    break;
+                }
+            }
+        }
+
+        let skipped = runs.len().saturating_sub(MAX_CATCH_UP);
+        DueRuns {
+            runs: runs.split_off(skipped),
+            skipped,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Unpauses the bill from its next run after `now`, without billing the runs it was paused for.
+    pub fn resume(&mut self, now: DateTime<Utc>) -> bool {
+        match self.schedule.next_after(now) {
+            Some(next_run) => {
+                self.next_run = next_run;
+                self.paused = false;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Every recurring bill, by id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecurringBills {
+    next_id: u64,
+    bills: BTreeMap<u64, RecurringBill>,
+}
+
+impl RecurringBills {
+    /// Adds `bill`, returning the id it can be looked up by.
+    pub fn insert(&mut self, bill: RecurringBill) -> u64 {
+        self.next_id += 1;
+        self.bills.insert(self.next_id, bill);
+        self.next_id
+    }
+
+    /// The bill numbered `id` if it belongs to `guild_id`.
+    pub fn get_mut(&mut self, guild_id: GuildId, id: u64) -> Option<&mut RecurringBill> {
+        self.bills
+            .get_mut(&id)
+            .filter(|bill| bill.guild_id == guild_id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<RecurringBill> {
+        self.bills.remove(&id)
+    }
+
+    /// The bills in `guild_id`, by id.
+    pub fn in_guild(&self, guild_id: GuildId) -> Vec<(u64, &RecurringBill)> {
+        self.bills
+            .iter()
+            .filter(|(_id, bill)| bill.guild_id == guild_id)
+            .map(|(&id, bill)| (id, bill))
+            .collect()
+    }
+
+    /// Takes the due runs of every unpaused bill, with a copy of the bill to make them from.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<(u64, RecurringBill, DueRuns)> {
+        self.bills
+            .iter_mut()
+            .filter(|(_id, bill)| !bill.paused && bill.next_run <= now)
+            .map(|(&id, bill)| {
+                let due = bill.take_due(now);
+                (id, bill.clone(), due)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::currency::USD;
+
+    use chrono::{Duration, TimeZone};
+
+    fn daily(next_run: DateTime<Utc>) -> RecurringBill {
+        RecurringBill {
+            guild_id: GuildId(1),
+            channel_id: ChannelId(2),
+            initiator: UserId(10),
+            amount: Money::new(1000).unwrap(),
+            currency: USD,
+            description: "internet".to_string(),
+            mode: SplitMode::Equal,
+            participants: vec![UserId(10), UserId(20)],
+            schedule: Schedule::parse("@daily").unwrap(),
+            next_run,
+            paused: false,
+        }
+    }
+
+    #[test]
+    fn test_catch_up_missed_runs() {
+        let start = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let mut bill = daily(start);
+
+        let due = bill.take_due(start + Duration::hours(36));
+        assert_eq!(due.runs, vec![start, start + Duration::days(1)]);
+        assert_eq!(due.skipped, 0);
+        assert_eq!(bill.next_run, start + Duration::days(2));
+
+        let due = bill.take_due(start + Duration::days(30));
+        assert_eq!(due.runs.len(), MAX_CATCH_UP);
+        assert_eq!(due.skipped, 29 - MAX_CATCH_UP);
+        assert_eq!(due.runs.last(), Some(&(start + Duration::days(30))));
+        assert_eq!(bill.next_run, start + Duration::days(31));
+    }
+
+    #[test]
+    fn test_paused_bills_are_not_due() {
+        let start = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let mut bills = RecurringBills::default();
+        let paused = bills.insert(daily(start));
+        let active = bills.insert(daily(start));
+        bills.get_mut(GuildId(1), paused).unwrap().pause();
+        assert!(bills.get_mut(GuildId(2), active).is_none());
+
+        let due = bills.take_due(start + Duration::hours(1));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, active);
+
+        let now = start + Duration::days(5) + Duration::hours(1);
+        let bill = bills.get_mut(GuildId(1), paused).unwrap();
+        assert!(bill.resume(now));
+        assert_eq!(bill.next_run, start + Duration::days(6));
+    }
+}
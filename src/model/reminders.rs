@@ -0,0 +1,217 @@
+use super::accounts::Ledgers;
+use super::convert::{convert_amount, RateTable};
+use super::currency::Currency;
+use super::ids::UserId;
+use super::utils::{Money, MoneyOverflowError};
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Hours of the day in UTC when a user doesn't want to be messaged, from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl QuietHours {
+    /// Whether `now` is within the quiet hours, which may run past midnight.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let hour = now.hour();
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+/// What a user owes in total in each currency they owe too much in.
+pub type Debts = Vec<(Currency, Money)>;
+
+/// Everyone whose total debt in any currency is more than `threshold`, with how much they owe in
+/// each currency that is over it.
+///
+/// The threshold is given in `currency` and converted into other currencies at `rates`. In a
+/// currency without a rate it counts as the same number of whole units.
+pub fn debtors(
+    ledgers: &Ledgers,
+    currency: Currency,
+    threshold: Money,
+    rates: &RateTable,
+) -> Result<Vec<(UserId, Debts)>, MoneyOverflowError> {
+    let mut currencies: Vec<Currency> = ledgers.keys().copied().collect();
+    currencies.sort();
+
+    let mut debtors: Vec<(UserId, Debts)> = Vec::new();
+    for debt_currency in currencies {
+        let threshold = match rates.get(currency, debt_currency) {
+            Some(rate) => convert_amount(threshold, currency, debt_currency, rate)
+                .map_err(|_e| MoneyOverflowError)?,
+            None => rescale(threshold, currency, debt_currency)?,
+        };
+        for (&user, account) in &ledgers[&debt_currency] {
+            // positive when the user is owed on the whole
+            let net = match Money::checked_sum(account.values().copied()) {
+                Some(net) => net,
+                None => return Err(MoneyOverflowError),
+            };
+            if !(net.is_negative() && -net > threshold) {
+                continue;
+            }
+            match debtors.iter_mut().find(|(debtor, _debts)| *debtor == user) {
+                Some((_debtor, debts)) => debts.push((debt_currency, -net)),
+                None => debtors.push((user, vec![(debt_currency, -net)])),
+            }
+        }
+    }
+    debtors.sort_by_key(|&(user, _)| user);
+    Ok(debtors)
+}
+
+/// `amount` in `from` as the same number of whole units in `to`, whose minor unit may differ.
+fn rescale(amount: Money, from: Currency, to: Currency) -> Result<Money, MoneyOverflowError> {
+    let rescaled = if to.exponent() >= from.exponent() {
+        10i64
+            .checked_pow(to.exponent() - from.exponent())
+            .and_then(|factor| amount.checked_mul(factor))
+    } else {
+        10i64
+            .checked_pow(from.exponent() - to.exponent())
+            .and_then(|factor| Money::new(amount.minor() / factor))
+    };
+    rescaled.ok_or(MoneyOverflowError)
+}
+
+/// Every debt between two users, as the currency, debtor, creditor and amount, in that order.
+pub fn outstanding(ledgers: &Ledgers) -> Vec<(Currency, UserId, UserId, Money)> {
+    let mut outstanding: Vec<(Currency, UserId, UserId, Money)> = ledgers
+        .iter()
+        .flat_map(|(&currency, ledger)| {
+            ledger.iter().flat_map(move |(&creditor, account)| {
+                account
+                    .iter()
+                    .filter(|(_debtor, amount)| amount.is_positive())
+                    .map(move |(&debtor, &amount)| (currency, debtor, creditor, amount))
+            })
+        })
+        .collect();
+    outstanding.sort();
+    outstanding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::accounts::{apply_transfer, Ledger, Transfer};
+    use crate::model::convert::Rate;
+    use crate::model::currency::{JPY, USD};
+
+    use chrono::TimeZone;
+
+    const ALICE: UserId = UserId(10);
+    const BOB: UserId = UserId(20);
+    const CAROL: UserId = UserId(30);
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    fn ledgers(debts: &[(UserId, UserId, i64)]) -> Ledgers {
+        let mut ledger = Ledger::new();
+        for &(debtor, creditor, amount) in debts {
+            let transfer = Transfer {
+                creditor,
+                debtor,
+                amount: money(amount),
+                currency: USD,
+            };
+            apply_transfer(&mut ledger, &transfer).unwrap();
+        }
+        let mut ledgers = Ledgers::new();
+        ledgers.insert(USD, ledger);
+        ledgers
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let at = |hour| Utc.with_ymd_and_hms(2021, 1, 1, hour, 30, 0).unwrap();
+
+        let night = QuietHours { start: 22, end: 7 };
+        assert!(night.contains(at(23)));
+        assert!(night.contains(at(6)));
+        assert!(!night.contains(at(7)));
+        assert!(!night.contains(at(12)));
+
+        let afternoon = QuietHours { start: 12, end: 14 };
+        assert!(afternoon.contains(at(13)));
+        assert!(!afternoon.contains(at(14)));
+    }
+
+    #[test]
+    fn test_debtors_over_threshold() {
+        // bob owes 30 in total, and carol owes 15 but is owed 10
+        let ledgers = ledgers(&[(BOB, ALICE, 2000), (BOB, CAROL, 1000), (CAROL, ALICE, 1500)]);
+
+        assert_eq!(
+            debtors(&ledgers, USD, money(500), &RateTable::default()),
+            Ok(vec![(BOB, vec![(USD, money(3000))])])
+        );
+        assert_eq!(
+            debtors(&ledgers, USD, Money::ZERO, &RateTable::default()),
+            Ok(vec![
+                (BOB, vec![(USD, money(3000))]),
+                (CAROL, vec![(USD, money(500))])
+            ])
+        );
+        assert_eq!(
+            outstanding(&ledgers),
+            vec![
+                (USD, BOB, ALICE, money(2000)),
+                (USD, BOB, CAROL, money(1000)),
+                (USD, CAROL, ALICE, money(1500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_debtors_over_threshold_in_each_currency() {
+        // carol owes 40 dollars, and 5 yen which is under the threshold of 10 whole units
+        let mut ledgers = ledgers(&[(CAROL, ALICE, 4000)]);
+        let mut yen = Ledger::new();
+        for (debtor, amount) in [(BOB, 2000), (CAROL, 5)] {
+            let transfer = Transfer {
+                creditor: ALICE,
+                debtor,
+                amount: money(amount),
+                currency: JPY,
+            };
+            apply_transfer(&mut yen, &transfer).unwrap();
+        }
+        ledgers.insert(JPY, yen);
+
+        assert_eq!(
+            debtors(&ledgers, USD, money(1000), &RateTable::default()),
+            Ok(vec![
+                (BOB, vec![(JPY, money(2000))]),
+                (CAROL, vec![(USD, money(4000))])
+            ])
+        );
+
+        // with a rate, 10 dollars is 1500 yen
+        let mut rates = RateTable::default();
+        rates.set(USD, JPY, Rate::parse("150").unwrap());
+        assert_eq!(
+            debtors(&ledgers, USD, money(1000), &rates),
+            Ok(vec![
+                (BOB, vec![(JPY, money(2000))]),
+                (CAROL, vec![(USD, money(4000))])
+            ])
+        );
+        assert_eq!(
+            debtors(&ledgers, USD, money(2000), &rates),
+            Ok(vec![(CAROL, vec![(USD, money(4000))])])
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// How far ahead to look for the next run, long enough to reach the 29th of February.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// The expression didn't have five fields.
+    FieldCount(usize),
+    InvalidField(String),
+    /// The expression is valid but names a date that never comes, such as the 30th of February.
+    NeverRuns,
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::FieldCount(count) => write!(
+                f,
+                "expected 5 fields (minute hour day month weekday) but found {}",
+                count
+            ),
+            ScheduleError::InvalidField(field) => write!(f, "{} is not a valid field", field),
+            ScheduleError::NeverRuns => write!(f, "this schedule never runs"),
+        }
+    }
+}
+
+impl Error for ScheduleError {}
+
+/// A cron schedule of `minute hour day month weekday` in UTC.
+///
+/// Fields may be `*`, numbers, ranges such as `1-5`, lists such as `1,15` and steps such as
+/// `*/15`. Weekdays count from Sunday as 0, and 7 is also Sunday. As in cron, when both the day
+/// and weekday are restricted, a date matching either runs. `@hourly`, `@daily`, `@weekly`,
+/// `@monthly` and `@yearly` are accepted as shorthands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    expression: String,
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Schedule, ScheduleError> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let (minute, hour, day, month, weekday) = match fields[..] {
+            [minute, hour, day, month, weekday] => (minute, hour, day, month, weekday),
+            _ => return Err(ScheduleError::FieldCount(fields.len())),
+        };
+
+        let mut weekdays: Vec<u32> = parse_field(weekday, 0, 7)?
+            .into_iter()
+            .map(|weekday| weekday % 7)
+            .collect();
+        weekdays.sort_unstable();
+        weekdays.dedup();
+
+        let schedule = Schedule {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        };
+
+        match schedule.next_after(Utc::now()) {
+            Some(_next) => Ok(schedule),
+            None => Err(ScheduleError::NeverRuns),
+        }
+    }
+
+    /// The first time strictly after `after` that the schedule runs.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.naive_utc().date();
+        for offset in 0..SEARCH_DAYS {
+            let date = start + Duration::days(offset);
+            if !self.months.contains(&date.month()) || !self.matches_day(date) {
+                continue;
+            }
+            for &hour in &self.hours {
+                for &minute in &self.minutes {
+                    let time = match date.and_hms_opt(hour, minute, 0) {
+                        Some(time) => Utc.from_utc_datetime(&time),
+                        None => continue,
+                    };
+                    if time > after {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether the schedule runs after `after` and no later than `until`.
+    pub fn runs_between(&self, after: DateTime<Utc>, until: DateTime<Utc>) -> bool {
+        match self.next_after(after) {
+            Some(next) => next <= until,
+            None => false,
+        }
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = ScheduleError;
+
+    fn try_from(expression: String) -> Result<Schedule, ScheduleError> {
+        Schedule::parse(&expression)
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> String {
+        schedule.expression
+    }
+}
+
+/// The sorted values from `min` to `max` that a cron field matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, ScheduleError> {
+    let invalid = || ScheduleError::InvalidField(field.to_string());
+    let number = |value: &str| match value.parse::<u32>() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(invalid()),
+    };
+
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<usize>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(invalid()),
+            },
+            None => (part, 1),
+        };
+
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // a single value with a step runs from that value to the end
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(invalid());
+        }
+
+        values.extend((first..=last).step_by(step));
+    }
+
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(parse_field("*/15", 0, 59), Ok(vec![0, 15, 30, 45]));
+        assert_eq!(parse_field("1-3,10", 1, 31), Ok(vec![1, 2, 3, 10]));
+        assert_eq!(parse_field("5/20", 0, 59), Ok(vec![5, 25, 45]));
+        assert!(parse_field("60", 0, 59).is_err());
+        assert!(parse_field("5-1", 0, 59).is_err());
+        assert!(parse_field("*/0", 0, 59).is_err());
+
+        assert_eq!(
+            Schedule::parse("0 0 1 *"),
+            Err(ScheduleError::FieldCount(4))
+        );
+        assert_eq!(Schedule::parse("0 0 30 2 *"), Err(ScheduleError::NeverRuns));
+    }
+
+    #[test]
+    fn test_next_run() {
+        let monthly = Schedule::parse("0 9 1 * *").unwrap();
+        let after = Utc.with_ymd_and_hms(2021, 1, 31, 12, 0, 0).unwrap();
+        assert_eq!(
+            monthly.next_after(after),
+            Some(Utc.with_ymd_and_hms(2021, 2, 1, 9, 0, 0).unwrap())
+        );
+
+        // runs are strictly after the given time
+        let run = Utc.with_ymd_and_hms(2021, 2, 1, 9, 0, 0).unwrap();
+        assert_eq!(
+            monthly.next_after(run),
+            Some(Utc.with_ymd_and_hms(2021, 3, 1, 9, 0, 0).unwrap())
+        );
+
+        // the 15th or any Monday, 2021-03-08 being a Monday
+        let either = Schedule::parse("30 18 15 * 1").unwrap();
+        assert_eq!(
+            either.next_after(Utc.with_ymd_and_hms(2021, 3, 2, 0, 0, 0).unwrap()),
+            Some(Utc.with_ymd_and_hms(2021, 3, 8, 18, 30, 0).unwrap())
+        );
+
+        assert!(monthly.runs_between(after, run));
+        assert!(!monthly.runs_between(run, run + Duration::days(27)));
+
+        let leap = Schedule::parse("0 0 29 2 *").unwrap();
+        assert_eq!(
+            leap.next_after(after),
+            Some(Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use super::accounts::{Ledger, Transfer};
+use super::currency::Currency;
+use super::ids::UserId;
+use super::utils::{Money, MoneyOverflowError};
+
+use std::collections::HashMap;
+
+/// A payment that `debtor` should make to `creditor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub debtor: UserId,
+    pub creditor: UserId,
+    pub amount: Money,
+}
+
+/// Each user's net position in `ledger`, positive when they are owed money overall.
+pub fn net_positions(ledger: &Ledger) -> Result<HashMap<UserId, Money>, MoneyOverflowError> {
+    ledger
+        .iter()
+        .map(|(&user, account)| {
+            Money::checked_sum(account.values().copied())
+                .map(|net| (user, net))
+                .ok_or(MoneyOverflowError)
+        })
+        .collect()
+}
+
+/// Proposes payments that leave every user in `ledger` with the same net position.
+///
+/// The largest remaining debtor repeatedly pays the largest remaining creditor, which needs at
+/// most one fewer payment than there are users with a non-zero position. Ties are broken by user
+/// id so the same ledger always produces the same plan.
+pub fn simplify(ledger: &Ledger) -> Result<Vec<Payment>, MoneyOverflowError> {
+    let mut creditors = Vec::new();
+    let mut debtors = Vec::new();
+
+    for (user, net) in net_positions(ledger)? {
+        if net.is_positive() {
+            creditors.push((net, user));
+        } else if net.is_negative() {
+            debtors.push((-net, user));
+        }
+    }
+
+    let mut payments = Vec::new();
+
+    loop {
+        creditors.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        debtors.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        let (credit, creditor) = match creditors.first_mut() {
+            Some(entry) => entry,
+            None => break,
+        };
+        let (debt, debtor) = match debtors.first_mut() {
+            Some(entry) => entry,
+            None => break,
+        };
+
+        let amount = (*credit).min(*debt);
+        payments.push(Payment {
+            debtor: *debtor,
+            creditor: *creditor,
+            amount,
+        });
+
+        *credit = credit.checked_sub(amount).ok_or(MoneyOverflowError)?;
+        *debt = debt.checked_sub(amount).ok_or(MoneyOverflowError)?;
+
+        creditors.retain(|(credit, _user)| credit.is_positive());
+        debtors.retain(|(debt, _user)| debt.is_positive());
+    }
+
+    Ok(payments)
+}
+
+/// The transfers that replace every debt in `ledger`, kept in `currency`, with the debts in `plan`.
+pub fn rebalance(ledger: &Ledger, currency: Currency, plan: &[Payment]) -> Vec<Transfer> {
+    let mut transfers: Vec<Transfer> = ledger
+        .iter()
+        .flat_map(|(&creditor, account)| {
+            account
+                .iter()
+                .filter(|(_debtor, amount)| amount.is_positive())
+                .map(move |(&debtor, &amount)| Transfer {
+                    creditor,
+                    debtor,
+                    amount: -amount,
+                    currency,
+                })
+        })
+        .collect();
+
+    transfers.sort_by_key(|transfer| (transfer.creditor, transfer.debtor));
+
+    transfers.extend(plan.iter().map(|payment| Transfer {
+        creditor: payment.creditor,
+        debtor: payment.debtor,
+        amount: payment.amount,
+        currency,
+    }));
+
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::accounts::apply_transfer;
+    use super::super::currency::USD;
+    use super::*;
+
+    fn ledger(transfers: &[(u64, u64, i64)]) -> Ledger {
+        let mut ledger = Ledger::new();
+        for &(creditor, debtor, amount) in transfers {
+            apply_transfer(
+                &mut ledger,
+                &Transfer {
+                    creditor: UserId(creditor),
+                    debtor: UserId(debtor),
+                    amount: Money::new(amount).unwrap(),
+                    currency: USD,
+                },
+            )
+            .unwrap();
+        }
+        ledger
+    }
+
+    #[test]
+    fn test_simplify_cycle() {
+        // 2 owes 1, 3 owes 2 and 1 owes 3 the same amount, so nobody needs to pay
+        let ledger = ledger(&[(1, 2, 500), (2, 3, 500), (3, 1, 500)]);
+        assert!(simplify(&ledger).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_simplify_chain() {
+        // 3 owes 2 and 2 owes 1, so 3 can pay 1 directly
+        let ledger = ledger(&[(1, 2, 1000), (2, 3, 1000)]);
+        assert_eq!(
+            vec![Payment {
+                debtor: UserId(3),
+                creditor: UserId(1),
+                amount: Money::new(1000).unwrap(),
+            }],
+            simplify(&ledger).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_simplify_preserves_net_positions() {
+        let before = ledger(&[
+            (1, 2, 1200),
+            (1, 3, 300),
+            (2, 3, 450),
+            (4, 1, 700),
+            (3, 4, 25),
+            (5, 2, 999),
+        ]);
+        let plan = simplify(&before).unwrap();
+        assert!(plan.len() < 5);
+
+        let mut after = before.clone();
+        for transfer in rebalance(&before, USD, &plan) {
+            apply_transfer(&mut after, &transfer).unwrap();
+        }
+
+        let mut expected = net_positions(&before).unwrap();
+        let mut actual = net_positions(&after).unwrap();
+        expected.retain(|_user, net| *net != Money::ZERO);
+        actual.retain(|_user, net| *net != Money::ZERO);
+        assert_eq!(expected, actual);
+
+        let pairs = after
+            .values()
+            .flat_map(|account| account.values())
+            .filter(|amount| amount.is_positive())
+            .count();
+        assert_eq!(plan.len(), pairs);
+    }
+}
@@ -0,0 +1,311 @@
+use super::currency::Currency;
+use super::utils::{format_decimal, parse_decimal, parse_money, Money};
+
+use serde::{Deserialize, Serialize};
+
+use std::{error::Error, fmt};
+
+/// Shares and percentages are parsed with two decimal places, so 100% is `10000` hundredths of a
+/// percent.
+const VALUE_EXPONENT: u32 = 2;
+const WHOLE_PERCENT: i64 = 10000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    UnknownMode(String),
+    /// Values were given for a mode that doesn't take any.
+    UnexpectedValues(String),
+    InvalidValue(String),
+    NoParticipants,
+    /// The number of values didn't match the number of participants.
+    ValueCount {
+        expected: usize,
+        found: usize,
+    },
+    NegativeValue,
+    NoShares,
+    /// Percentages added up to this many hundredths of a percent instead of 100%.
+    PercentTotal(Option<i64>),
+    ExactTotal,
+    TooLarge,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitError::UnknownMode(mode) => write!(f, "there is no {} split", mode),
+            SplitError::UnexpectedValues(mode) => write!(f, "{} splits don't take values", mode),
+            SplitError::InvalidValue(reason) => write!(f, "{}", reason),
+            SplitError::NoParticipants => write!(f, "there is no one to split between"),
+            SplitError::ValueCount { expected, found } => write!(
+                f,
+                "expected {} values, one for each user, but got {}",
+                expected, found
+            ),
+            SplitError::NegativeValue => write!(f, "values can't be negative"),
+            SplitError::NoShares => write!(f, "shares can't all be zero"),
+            SplitError::PercentTotal(Some(total)) => write!(
+                f,
+                "percentages add up to {}% instead of 100%",
+                format_decimal(*total, VALUE_EXPONENT)
+            ),
+            SplitError::PercentTotal(None) => write!(f, "percentages don't add up to 100%"),
+            SplitError::ExactTotal => write!(f, "amounts don't add up to the total"),
+            SplitError::TooLarge => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl Error for SplitError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitMode {
+    /// Every participant is charged the full amount.
+    Each,
+    /// The amount is divided evenly between participants.
+    Equal,
+    /// The amount is divided in proportion to each participant's weight.
+    Shares(Vec<i64>),
+    /// Each participant pays a percentage of the amount, in hundredths of a percent.
+    Percent(Vec<i64>),
+    /// Each participant pays an explicit amount, which must add up to the total.
+    Exact(Vec<Money>),
+}
+
+impl SplitMode {
+    /// Parses a split mode name and its space separated per-participant values, with exact
+    /// amounts in `currency`.
+    pub fn parse(
+        mode: &str,
+        values: Option<&str>,
+        currency: Currency,
+    ) -> Result<SplitMode, SplitError> {
+        let values: Vec<&str> = values
+            .map(|values| values.split_whitespace().collect())
+            .unwrap_or_default();
+        let weights = || {
+            values
+                .iter()
+                .map(|value| {
+                    parse_decimal(value, VALUE_EXPONENT).map_err(|_e| {
+                        SplitError::InvalidValue(format!(
+                            "{} is not a number with at most {} decimal places",
+                            value, VALUE_EXPONENT
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<i64>, SplitError>>()
+        };
+
+        match mode {
+            "each" | "equal" if !values.is_empty() => {
+                Err(SplitError::UnexpectedValues(mode.to_string()))
+            }
+            "each" => Ok(SplitMode::Each),
+            "equal" => Ok(SplitMode::Equal),
+            "shares" => Ok(SplitMode::Shares(weights()?)),
+            "percent" => Ok(SplitMode::Percent(weights()?)),
+            "exact" => Ok(SplitMode::Exact(
+                values
+                    .iter()
+                    .map(|value| {
+                        parse_money(value, currency)
+                            .map_err(|e| SplitError::InvalidValue(format!("{}: {}", value, e)))
+                    })
+                    .collect::<Result<Vec<Money>, SplitError>>()?,
+            )),
+            _ => Err(SplitError::UnknownMode(mode.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SplitMode::Each => "each",
+            SplitMode::Equal => "equal",
+            SplitMode::Shares(_) => "shares",
+            SplitMode::Percent(_) => "percent",
+            SplitMode::Exact(_) => "exact",
+        }
+    }
+}
+
+/// Divides `total` between `participants` according to `mode`.
+///
+/// Except for [`SplitMode::Each`], the returned amounts always add up to `total`. Cents left
+/// over after rounding down go one at a time to the participants with the largest remainders,
+/// with ties going to whoever is listed first.
+pub fn split(
+    total: Money,
+    participants: usize,
+    mode: &SplitMode,
+) -> Result<Vec<Money>, SplitError> {
+    if participants == 0 {
+        return Err(SplitError::NoParticipants);
+    }
+
+    let check_count = |found: usize| {
+        if found == participants {
+            Ok(())
+        } else {
+            Err(SplitError::ValueCount {
+                expected: participants,
+                found,
+            })
+        }
+    };
+
+    match mode {
+        SplitMode::Each => {
+            // the initiator is owed the full amount by everyone, which has to fit in total
+            match i64::try_from(participants)
+                .ok()
+                .and_then(|participants| total.checked_mul(participants))
+            {
+                Some(_billed) => Ok(vec![total; participants]),
+                None => Err(SplitError::TooLarge),
+            }
+        }
+        SplitMode::Equal => distribute(total, &vec![1; participants]),
+        SplitMode::Shares(shares) => {
+            check_count(shares.len())?;
+            if shares.iter().any(|&share| share < 0) {
+                return Err(SplitError::NegativeValue);
+            }
+            if shares.iter().all(|&share| share == 0) {
+                return Err(SplitError::NoShares);
+            }
+            distribute(total, shares)
+        }
+        SplitMode::Percent(percentages) => {
+            check_count(percentages.len())?;
+            if percentages.iter().any(|&percentage| percentage < 0) {
+                return Err(SplitError::NegativeValue);
+            }
+            let sum = percentages
+                .iter()
+                .try_fold(0_i64, |sum, &percentage| sum.checked_add(percentage));
+            if sum != Some(WHOLE_PERCENT) {
+                return Err(SplitError::PercentTotal(sum));
+            }
+            distribute(total, percentages)
+        }
+        SplitMode::Exact(amounts) => {
+            check_count(amounts.len())?;
+            if Money::checked_sum(amounts.iter().copied()) != Some(total) {
+                return Err(SplitError::ExactTotal);
+            }
+            Ok(amounts.clone())
+        }
+    }
+}
+
+fn distribute(total: Money, weights: &[i64]) -> Result<Vec<Money>, SplitError> {
+    total.checked_split(weights).ok_or(SplitError::TooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::currency::USD;
+    use super::*;
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    fn amounts(minor: &[i64]) -> Vec<Money> {
+        minor.iter().map(|&minor| money(minor)).collect()
+    }
+
+    #[test]
+    fn test_split_each() {
+        assert_eq!(
+            amounts(&[500, 500, 500]),
+            split(money(500), 3, &SplitMode::Each).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_equal_leftover() {
+        assert_eq!(
+            amounts(&[34, 33, 33]),
+            split(money(100), 3, &SplitMode::Equal).unwrap()
+        );
+        assert_eq!(
+            amounts(&[-34, -33, -33]),
+            split(money(-100), 3, &SplitMode::Equal).unwrap()
+        );
+        assert_eq!(
+            amounts(&[1, 1, 0, 0]),
+            split(money(2), 4, &SplitMode::Equal).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_shares() {
+        let mode = SplitMode::Shares(vec![200, 100, 100]);
+        assert_eq!(
+            amounts(&[5000, 2500, 2500]),
+            split(money(10000), 3, &mode).unwrap()
+        );
+
+        let mode = SplitMode::Shares(vec![100, 200]);
+        assert_eq!(amounts(&[333, 667]), split(money(1000), 2, &mode).unwrap());
+    }
+
+    #[test]
+    fn test_split_percent() {
+        let mode = SplitMode::parse("percent", Some("33.34 33.33 33.33"), USD).unwrap();
+        assert_eq!(
+            amounts(&[3334, 3333, 3333]),
+            split(money(10000), 3, &mode).unwrap()
+        );
+
+        let mode = SplitMode::parse("percent", Some("50 25"), USD).unwrap();
+        assert!(split(money(10000), 2, &mode).is_err());
+
+        // fewer decimal places than allowed, or no whole part, are still read as numbers
+        assert_eq!(
+            SplitMode::Percent(vec![3350, 6600, 50]),
+            SplitMode::parse("percent", Some("33.5 66 .5"), USD).unwrap()
+        );
+        assert_eq!(
+            SplitMode::Shares(vec![150, 100]),
+            SplitMode::parse("shares", Some("1.5 1"), USD).unwrap()
+        );
+        for value in ["1.234", "1.", ".", "1.2.3", "-1", "1,5"] {
+            assert!(SplitMode::parse("shares", Some(value), USD).is_err());
+        }
+    }
+
+    #[test]
+    fn test_split_exact() {
+        let mode = SplitMode::parse("exact", Some("12.50 7.50"), USD).unwrap();
+        assert_eq!(amounts(&[1250, 750]), split(money(2000), 2, &mode).unwrap());
+        assert!(split(money(2001), 2, &mode).is_err());
+    }
+
+    #[test]
+    fn test_split_sum_matches_total() {
+        for total in [1, 7, 99, 1001, 123457] {
+            for mode in [
+                SplitMode::Equal,
+                SplitMode::Shares(vec![300, 100, 700, 0]),
+                SplitMode::Percent(vec![1000, 2500, 6499, 1]),
+            ] {
+                let split_amounts = split(money(total), 4, &mode).unwrap();
+                assert_eq!(Some(money(total)), Money::checked_sum(split_amounts));
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_error() {
+        assert!(split(money(100), 0, &SplitMode::Equal).is_err());
+        assert!(split(money(100), 2, &SplitMode::Shares(vec![100])).is_err());
+        assert!(split(money(100), 2, &SplitMode::Shares(vec![0, 0])).is_err());
+        assert!(SplitMode::parse("equal", Some("1 2"), USD).is_err());
+        assert!(SplitMode::parse("thirds", None, USD).is_err());
+        assert!(split(money(i64::MAX / 2), 3, &SplitMode::Each).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use super::accounts::Transfer;
+use super::currency::Currency;
+use super::ids::UserId;
+use super::utils::Money;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone)]
+pub struct OweTransaction {
+    pub initiator: UserId,
+    pub amount: Money,
+    pub recipient: UserId,
+    pub description: String,
+    pub currency: Currency,
+}
+
+#[derive(Clone)]
+pub struct BillTransaction {
+    pub initiator: UserId,
+    pub amount: Money,
+    pub recipients: Vec<(UserId, Money)>,
+    pub description: String,
+    pub currency: Currency,
+}
+
+#[derive(Clone)]
+pub struct SettleTransaction {
+    pub initiator: UserId,
+    pub payer: UserId,
+    pub amount: Money,
+    pub payee: UserId,
+    pub currency: Currency,
+}
+
+#[derive(Clone)]
+pub struct VoidTransaction {
+    pub initiator: UserId,
+    pub voided: u64,
+    pub transfers: Vec<Transfer>,
+}
+
+#[derive(Clone)]
+pub struct SimplifyTransaction {
+    pub initiator: UserId,
+    pub transfers: Vec<Transfer>,
+}
+
+#[derive(Clone)]
+pub struct ConvertTransaction {
+    pub initiator: UserId,
+    pub currency: Currency,
+    pub transfers: Vec<Transfer>,
+}
+
+#[derive(Default)]
+pub enum TransactionType {
+    Owe(OweTransaction),
+    Bill(BillTransaction),
+    Settle(SettleTransaction),
+    Void(VoidTransaction),
+    Simplify(SimplifyTransaction),
+    Convert(ConvertTransaction),
+    #[default]
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Owe,
+    Bill,
+    Settle,
+    Void,
+    Simplify,
+    Convert,
+}
+
+impl TransactionType {
+    pub fn kind(&self) -> Option<TransactionKind> {
+        match self {
+            TransactionType::Owe(_) => Some(TransactionKind::Owe),
+            TransactionType::Bill(_) => Some(TransactionKind::Bill),
+            TransactionType::Settle(_) => Some(TransactionKind::Settle),
+            TransactionType::Void(_) => Some(TransactionKind::Void),
+            TransactionType::Simplify(_) => Some(TransactionKind::Simplify),
+            TransactionType::Convert(_) => Some(TransactionKind::Convert),
+            TransactionType::None => None,
+        }
+    }
+
+    pub fn initiator(&self) -> Option<UserId> {
+        match self {
+            TransactionType::Owe(x) => Some(x.initiator),
+            TransactionType::Bill(x) => Some(x.initiator),
+            TransactionType::Settle(x) => Some(x.initiator),
+            TransactionType::Void(x) => Some(x.initiator),
+            TransactionType::Simplify(x) => Some(x.initiator),
+            TransactionType::Convert(x) => Some(x.initiator),
+            TransactionType::None => None,
+        }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            TransactionType::Owe(x) => Some(&x.description),
+            TransactionType::Bill(x) => Some(&x.description),
+            _ => None,
+        }
+    }
+
+    /// The amount entered for the transaction, if it was entered as a single amount.
+    pub fn amount(&self) -> Option<Money> {
+        match self {
+            TransactionType::Owe(x) => Some(x.amount),
+            TransactionType::Bill(x) => Some(x.amount),
+            TransactionType::Settle(x) => Some(x.amount),
+            _ => None,
+        }
+    }
+
+    /// The currency `amount` is in, or that balances were converted into.
+    pub fn currency(&self) -> Option<Currency> {
+        match self {
+            TransactionType::Owe(x) => Some(x.currency),
+            TransactionType::Bill(x) => Some(x.currency),
+            TransactionType::Settle(x) => Some(x.currency),
+            TransactionType::Convert(x) => Some(x.currency),
+            _ => None,
+        }
+    }
+
+    /// The journal sequence number of the transaction this one reverses.
+    pub fn voids(&self) -> Option<u64> {
+        match self {
+            TransactionType::Void(x) => Some(x.voided),
+            _ => None,
+        }
+    }
+
+    /// The changes this transaction makes to the ledger it is recorded in.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        match self {
+            TransactionType::Owe(x) => vec![Transfer {
+                creditor: x.recipient,
+                debtor: x.initiator,
+                amount: x.amount,
+                currency: x.currency,
+            }],
+            TransactionType::Bill(x) => x
+                .recipients
+                .iter()
+                .map(|(recipient, amount)| Transfer {
+                    creditor: x.initiator,
+                    debtor: *recipient,
+                    amount: *amount,
+                    currency: x.currency,
+                })
+                .collect(),
+            TransactionType::Settle(x) => vec![Transfer {
+                creditor: x.payer,
+                debtor: x.payee,
+                amount: x.amount,
+                currency: x.currency,
+            }],
+            TransactionType::Void(x) => x.transfers.clone(),
+            TransactionType::Simplify(x) => x.transfers.clone(),
+            TransactionType::Convert(x) => x.transfers.clone(),
+            TransactionType::None => Vec::new(),
+        }
+    }
+}
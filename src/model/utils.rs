@@ -1,8 +1,56 @@
-use super::accounts::{Accounts, AccountsType};
+use super::accounts::{ledger_scope, Accounts, AccountsType};
+use super::amount;
+use super::currency::Currency;
+use super::ids::{GuildId, UserId};
+
+use crate::groups::{Groups, GroupsType};
+use crate::journal::{Journal, JournalType};
+use crate::pending::{Pending, PendingType};
+use crate::recurring::{Recurring, RecurringType};
+use crate::settings::guild_settings;
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::{
+        guild::Member,
+        id,
+        misc::{Mention, Mentionable},
+    },
+};
+
+use std::{error::Error, fmt, ops::Neg};
+
+// the ledger keeps its own ids, which are the same numbers as Discord's
+impl From<id::UserId> for UserId {
+    fn from(user: id::UserId) -> Self {
+        UserId(user.0)
+    }
+}
+
+impl From<UserId> for id::UserId {
+    fn from(user: UserId) -> Self {
+        id::UserId(user.0)
+    }
+}
 
-use serenity::client::Context;
+impl From<id::GuildId> for GuildId {
+    fn from(guild: id::GuildId) -> Self {
+        GuildId(guild.0)
+    }
+}
+
+impl From<GuildId> for id::GuildId {
+    fn from(guild: GuildId) -> Self {
+        id::GuildId(guild.0)
+    }
+}
 
-use std::{error::Error, fmt};
+impl Mentionable for UserId {
+    fn mention(&self) -> Mention {
+        id::UserId::from(*self).mention()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct GetLockError;
@@ -15,17 +63,186 @@ impl fmt::Display for GetLockError {
 
 impl Error for GetLockError {}
 
-#[derive(Debug, Clone)]
-pub struct ParseMoneyError;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseMoneyError {
+    Empty,
+    UnexpectedCharacter(char),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnknownCurrency(String),
+    MixedCurrencies(Currency, Currency),
+    WrongCurrency { expected: Currency, found: Currency },
+    InvalidNumber(String),
+    InvalidGrouping(String),
+    TooManyDecimals(Currency),
+    DivisionByZero,
+    TooLarge,
+}
 
 impl fmt::Display for ParseMoneyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "could not parse money")
+        match self {
+            ParseMoneyError::Empty => write!(f, "no amount was given"),
+            ParseMoneyError::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            ParseMoneyError::UnexpectedToken(token) => write!(f, "unexpected '{}'", token),
+            ParseMoneyError::UnexpectedEnd => write!(f, "amount ends unexpectedly"),
+            ParseMoneyError::UnknownCurrency(code) => write!(f, "unknown currency {}", code),
+            ParseMoneyError::MixedCurrencies(first, second) => {
+                write!(f, "amount mixes {} and {}", first, second)
+            }
+            ParseMoneyError::WrongCurrency { expected, found } => {
+                write!(f, "amount must be in {}, not {}", expected, found)
+            }
+            ParseMoneyError::InvalidNumber(number) => write!(f, "{} is not a number", number),
+            ParseMoneyError::InvalidGrouping(number) => write!(
+                f,
+                "{} is not grouped correctly, digits go in groups of three like 1,234.50",
+                number
+            ),
+            ParseMoneyError::TooManyDecimals(currency) if currency.exponent() == 0 => {
+                write!(f, "{} amounts can't have decimal places", currency)
+            }
+            ParseMoneyError::TooManyDecimals(currency) => write!(
+                f,
+                "{} amounts have at most {} decimal places",
+                currency,
+                currency.exponent()
+            ),
+            ParseMoneyError::DivisionByZero => write!(f, "can't divide by zero"),
+            ParseMoneyError::TooLarge => write!(f, "amount is too large"),
+        }
     }
 }
 
 impl Error for ParseMoneyError {}
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoneyOverflowError;
+
+impl fmt::Display for MoneyOverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "amount is too large")
+    }
+}
+
+impl Error for MoneyOverflowError {}
+
+/// An amount of money in the minor unit of its currency, such as cents.
+///
+/// Arithmetic is checked, so amounts that don't fit are reported rather than wrapping around.
+/// `i64::MIN` is never produced, so every amount can be negated.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct Money(i64);
+
+impl TryFrom<i64> for Money {
+    type Error = MoneyOverflowError;
+
+    fn try_from(minor: i64) -> Result<Self, Self::Error> {
+        Money::new(minor).ok_or(MoneyOverflowError)
+    }
+}
+
+impl From<Money> for i64 {
+    fn from(money: Money) -> Self {
+        money.0
+    }
+}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub fn new(minor: i64) -> Option<Money> {
+        if minor == i64::MIN {
+            None
+        } else {
+            Some(Money(minor))
+        }
+    }
+
+    /// The amount in minor units.
+    pub fn minor(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).and_then(Money::new)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).and_then(Money::new)
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Option<Money> {
+        self.0.checked_mul(factor).and_then(Money::new)
+    }
+
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Money>) -> Option<Money> {
+        amounts
+            .into_iter()
+            .try_fold(Money::ZERO, |sum, amount| sum.checked_add(amount))
+    }
+
+    /// Divides this amount in proportion to `weights`, so that the parts add up to it exactly.
+    ///
+    /// Minor units left over after rounding down go one at a time to the parts with the largest
+    /// remainders, with ties going to the earliest part.
+    pub fn checked_split(self, weights: &[i64]) -> Option<Vec<Money>> {
+        if weights.iter().any(|&weight| weight < 0) {
+            return None;
+        }
+
+        let weight_sum: i128 = weights.iter().map(|&weight| i128::from(weight)).sum();
+        if weight_sum <= 0 {
+            return None;
+        }
+
+        let magnitude = i128::from(self.0).abs();
+        let sign = if self.0 < 0 { -1 } else { 1 };
+
+        let mut amounts = Vec::with_capacity(weights.len());
+        let mut remainders = Vec::with_capacity(weights.len());
+        for (index, &weight) in weights.iter().enumerate() {
+            let exact = magnitude * i128::from(weight);
+            amounts.push(exact / weight_sum);
+            remainders.push((exact % weight_sum, index));
+        }
+
+        let leftover = magnitude - amounts.iter().sum::<i128>();
+        remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        for &(_remainder, index) in remainders.iter().take(leftover as usize) {
+            amounts[index] += 1;
+        }
+
+        amounts
+            .into_iter()
+            .map(|amount| i64::try_from(sign * amount).ok().and_then(Money::new))
+            .collect()
+    }
+
+    pub fn abs(self) -> Money {
+        Money(self.0.abs())
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
 pub async fn get_accounts_lock(ctx: &Context) -> Result<AccountsType, GetLockError> {
     let accounts_lock = {
         let data_read = ctx.data.read().await;
@@ -38,93 +255,195 @@ pub async fn get_accounts_lock(ctx: &Context) -> Result<AccountsType, GetLockErr
     Ok(accounts_lock)
 }
 
-pub fn format_money(money: i64) -> String {
-    let mut string;
-    if money >= 0 {
-        string = format!("${:0>3}", money);
-    } else {
-        string = format!("-${:0>3}", -money);
-    }
-    string.insert(string.len() - 2, '.');
-    string
+pub async fn get_journal_lock(ctx: &Context) -> Result<JournalType, GetLockError> {
+    let journal_lock = {
+        let data_read = ctx.data.read().await;
+        match data_read.get::<Journal>() {
+            Some(data) => data.clone(),
+            None => return Err(GetLockError),
+        }
+    };
+
+    Ok(journal_lock)
 }
 
-pub fn parse_money(mut input: &str) -> Result<i64, ParseMoneyError> {
-    let mut negative = false;
+pub async fn get_pending_lock(ctx: &Context) -> Result<PendingType, GetLockError> {
+    let pending_lock = {
+        let data_read = ctx.data.read().await;
+        match data_read.get::<Pending>() {
+            Some(data) => data.clone(),
+            None => return Err(GetLockError),
+        }
+    };
 
-    if input.starts_with('-') {
-        negative = true;
-        input = &((*input)[1..]);
-    }
+    Ok(pending_lock)
+}
 
-    if input.starts_with('$') {
-        input = &((*input)[1..]);
-    }
+pub async fn get_recurring_lock(ctx: &Context) -> Result<RecurringType, GetLockError> {
+    let recurring_lock = {
+        let data_read = ctx.data.read().await;
+        match data_read.get::<Recurring>() {
+            Some(data) => data.clone(),
+            None => return Err(GetLockError),
+        }
+    };
 
-    let mut split = (*input).split('.');
+    Ok(recurring_lock)
+}
 
-    let mut money = match split.next() {
-        Some(dollars) => match dollars.parse::<u32>() {
-            Ok(dollars) => dollars * 100,
-            Err(_e) => return Err(ParseMoneyError),
-        },
-        None => return Err(ParseMoneyError),
+pub async fn get_groups_lock(ctx: &Context) -> Result<GroupsType, GetLockError> {
+    let groups_lock = {
+        let data_read = ctx.data.read().await;
+        match data_read.get::<Groups>() {
+            Some(data) => data.clone(),
+            None => return Err(GetLockError),
+        }
     };
 
-    if let Some(next) = split.next() {
-        if next.len() != 2 {
-            return Err(ParseMoneyError);
+    Ok(groups_lock)
+}
+
+/// The currency of amounts entered without a symbol or code in the given guild.
+pub async fn get_default_currency(ctx: &Context, guild_id: Option<id::GuildId>) -> Currency {
+    guild_settings(ctx.data.clone(), ledger_scope(guild_id))
+        .await
+        .currency()
+}
+
+/// Whether the member who triggered an interaction is an administrator of its guild.
+pub fn is_admin(member: Option<&Member>) -> bool {
+    member
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator())
+}
+
+/// Adds the members of a role, voice channel or group to the users to bill, skipping anyone
+/// already listed and the caller, who is only billed with `include_self`.
+pub fn add_members(
+    participants: &mut Vec<UserId>,
+    members: impl IntoIterator<Item = UserId>,
+    caller: UserId,
+    include_self: bool,
+) {
+    for member in members {
+        if (member != caller || include_self) && !participants.contains(&member) {
+            participants.push(member);
         }
+    }
+}
 
-        match next.parse::<u32>() {
-            Ok(cents) => money += cents,
-            Err(_e) => return Err(ParseMoneyError),
-        };
+pub fn format_money(money: Money, currency: Currency) -> String {
+    let string = format_decimal(money.abs().minor(), currency.exponent());
+    let sign = if money.is_negative() { "-" } else { "" };
+    match currency.symbol() {
+        Some(symbol) => format!("{}{}{}", sign, symbol, string),
+        None => format!("{}{} {}", sign, string, currency.code()),
     }
+}
 
-    if let Some(_next) = split.next() {
-        return Err(ParseMoneyError);
+/// Formats a non-negative count of a unit with `exponent` decimal places, such as `1234` cents as
+/// `12.34`.
+pub fn format_decimal(value: i64, exponent: u32) -> String {
+    let exponent = exponent as usize;
+    let mut string = format!("{:0>width$}", value, width = exponent + 1);
+    if exponent > 0 {
+        string.insert(string.len() - exponent, '.');
     }
+    string
+}
 
-    if negative {
-        Ok(-(i64::from(money)))
-    } else {
-        Ok(money.into())
+/// Parses an amount in `currency`, which may be written with its symbol or code.
+pub fn parse_money(input: &str, currency: Currency) -> Result<Money, ParseMoneyError> {
+    match parse_amount(input, currency) {
+        Ok((money, parsed)) if parsed == currency => Ok(money),
+        Ok((_money, found)) => Err(ParseMoneyError::WrongCurrency {
+            expected: currency,
+            found,
+        }),
+        Err(e) => Err(e),
     }
 }
 
+/// Parses an amount and the currency it is in, which is `default` unless a symbol or code is
+/// written with it.
+///
+/// See [`amount::evaluate`] for what amounts can be written as.
+pub fn parse_amount(input: &str, default: Currency) -> Result<(Money, Currency), ParseMoneyError> {
+    amount::evaluate(input, default)
+}
+
+/// Parses an unsigned number with at most `exponent` decimal places, as in `1.5`, `33.25` or
+/// `.5`, into an integer count of its smallest unit.
+pub fn parse_decimal(input: &str, exponent: u32) -> Result<i64, ParseMoneyError> {
+    let (whole, fraction) = match input.split_once('.') {
+        Some((whole, fraction)) if !fraction.is_empty() => (whole, fraction),
+        Some(_parts) => return Err(ParseMoneyError::InvalidNumber(input.to_string())),
+        None => (input, ""),
+    };
+
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > exponent as usize
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(ParseMoneyError::InvalidNumber(input.to_string()));
+    }
+
+    let whole = match whole {
+        "" => 0,
+        whole => whole
+            .parse::<i64>()
+            .map_err(|_e| ParseMoneyError::TooLarge)?,
+    };
+    // "5" in two decimal places is 50 hundredths
+    let fraction = match exponent {
+        0 => 0,
+        _ => format!("{:0<width$}", fraction, width = exponent as usize)
+            .parse::<i64>()
+            .map_err(|_e| ParseMoneyError::InvalidNumber(input.to_string()))?,
+    };
+
+    whole
+        .checked_mul(10_i64.pow(exponent))
+        .and_then(|whole| whole.checked_add(fraction))
+        .ok_or(ParseMoneyError::TooLarge)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::super::currency::{EUR, JPY, USD};
     use super::*;
 
     #[test]
     fn test_format_money_zero() {
-        assert_eq!("$0.00", format_money(0));
+        assert_eq!("$0.00", format_money(Money(0), USD));
     }
 
     #[test]
     fn test_format_money_positive() {
-        assert_eq!("$0.01", format_money(1));
-        assert_eq!("$0.12", format_money(12));
-        assert_eq!("$1.23", format_money(123));
-        assert_eq!("$12.34", format_money(1234));
+        assert_eq!("$0.01", format_money(Money(1), USD));
+        assert_eq!("$0.12", format_money(Money(12), USD));
+        assert_eq!("$1.23", format_money(Money(123), USD));
+        assert_eq!("$12.34", format_money(Money(1234), USD));
     }
 
     #[test]
     fn test_format_money_negative() {
-        assert_eq!("-$0.01", format_money(-1));
-        assert_eq!("-$0.12", format_money(-12));
-        assert_eq!("-$1.23", format_money(-123));
-        assert_eq!("-$12.34", format_money(-1234));
+        assert_eq!("-$0.01", format_money(Money(-1), USD));
+        assert_eq!("-$0.12", format_money(Money(-12), USD));
+        assert_eq!("-$1.23", format_money(Money(-123), USD));
+        assert_eq!("-$12.34", format_money(Money(-1234), USD));
     }
 
     #[test]
     fn test_parse_money_zero() -> Result<(), String> {
-        let expected = 0;
-        match parse_money("0") {
+        let expected = Money(0);
+        match parse_money("0", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
@@ -135,31 +454,31 @@ mod tests {
 
     #[test]
     fn test_parse_money_positive() -> Result<(), String> {
-        let expected = 1;
-        match parse_money("0.01") {
+        let expected = Money(1);
+        match parse_money("0.01", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
         }
 
-        let expected = 1200;
-        match parse_money("12") {
+        let expected = Money(1200);
+        match parse_money("12", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
         }
 
-        let expected = 1234;
-        match parse_money("$12.34") {
+        let expected = Money(1234);
+        match parse_money("$12.34", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
@@ -170,31 +489,31 @@ mod tests {
 
     #[test]
     fn test_parse_money_negative() -> Result<(), String> {
-        let expected = -1;
-        match parse_money("-0.01") {
+        let expected = Money(-1);
+        match parse_money("-0.01", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
         }
 
-        let expected = -1200;
-        match parse_money("-12") {
+        let expected = Money(-1200);
+        match parse_money("-12", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
         }
 
-        let expected = -1234;
-        match parse_money("-$12.34") {
+        let expected = Money(-1234);
+        match parse_money("-$12.34", USD) {
             Ok(actual) => {
                 if expected != actual {
-                    return Err(format!("Expected {}, got {}", expected, actual));
+                    return Err(format!("Expected {:?}, got {:?}", expected, actual));
                 }
             }
             Err(e) => return Err(e.to_string()),
@@ -205,21 +524,126 @@ mod tests {
 
     #[test]
     fn test_parse_money_error() -> Result<(), String> {
-        match parse_money("a") {
-            Ok(actual) => return Err(format!("Expected error, got {}", actual)),
+        match parse_money("a", USD) {
+            Ok(actual) => return Err(format!("Expected error, got {:?}", actual)),
             Err(_e) => {}
         }
 
-        match parse_money("-0.0.1") {
-            Ok(actual) => return Err(format!("Expected error, got {}", actual)),
+        match parse_money("-0.0.1", USD) {
+            Ok(actual) => return Err(format!("Expected error, got {:?}", actual)),
             Err(_e) => {}
         }
 
-        match parse_money("-0.-1") {
-            Ok(actual) => return Err(format!("Expected error, got {}", actual)),
+        match parse_money("-0.-1", USD) {
+            Ok(actual) => return Err(format!("Expected error, got {:?}", actual)),
             Err(_e) => {}
         }
 
         Ok(())
     }
+
+    #[test]
+    fn test_format_money_currencies() {
+        assert_eq!("€12.34", format_money(Money(1234), EUR));
+        assert_eq!("¥1234", format_money(Money(1234), JPY));
+        assert_eq!("-¥5", format_money(Money(-5), JPY));
+        assert_eq!(
+            "12.345 BHD",
+            format_money(Money(12345), Currency::from_code("BHD").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_amount_currencies() {
+        assert_eq!(
+            Ok((Money(1234), USD)),
+            parse_amount("12.34", USD).map_err(|_e| ())
+        );
+        assert_eq!(
+            Ok((Money(1234), EUR)),
+            parse_amount("€12.34", USD).map_err(|_e| ())
+        );
+        assert_eq!(
+            Ok((Money(1234), EUR)),
+            parse_amount("12.34 eur", USD).map_err(|_e| ())
+        );
+        assert_eq!(
+            Ok((Money(-1234), EUR)),
+            parse_amount("-EUR 12.34", USD).map_err(|_e| ())
+        );
+        assert_eq!(
+            Ok((Money(500), JPY)),
+            parse_amount("500", JPY).map_err(|_e| ())
+        );
+        assert!(parse_amount("5.00", JPY).is_err());
+        assert!(parse_amount("12.34 XYZ", USD).is_err());
+        assert!(parse_money("€12.34", USD).is_err());
+    }
+
+    #[test]
+    fn test_money_checked_arithmetic() {
+        let max = Money(i64::MAX);
+        assert_eq!(Some(Money(3)), Money(1).checked_add(Money(2)));
+        assert_eq!(None, max.checked_add(Money(1)));
+        assert_eq!(None, (-max).checked_sub(Money(1)));
+        assert_eq!(Some(Money(-6)), Money(-2).checked_mul(3));
+        assert_eq!(None, max.checked_mul(2));
+        assert_eq!(None, Money::checked_sum([max, Money(1)]));
+        assert_eq!(None, Money::new(i64::MIN));
+    }
+
+    #[test]
+    fn test_money_checked_split() {
+        assert_eq!(
+            Some(vec![Money(34), Money(33), Money(33)]),
+            Money(100).checked_split(&[1, 1, 1])
+        );
+        assert_eq!(
+            Some(vec![Money(i64::MAX), Money(0)]),
+            Money(i64::MAX).checked_split(&[i64::MAX, 0])
+        );
+        assert_eq!(None, Money(100).checked_split(&[0, 0]));
+        assert_eq!(None, Money(100).checked_split(&[1, -1]));
+    }
+
+    #[test]
+    fn test_money_serde() {
+        assert_eq!("-1234", serde_json::to_string(&Money(-1234)).unwrap());
+        assert_eq!(Money(1234), serde_json::from_str::<Money>("1234").unwrap());
+        assert!(serde_json::from_str::<Money>(&i64::MIN.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_parse_money_too_large() {
+        assert_eq!(
+            Err(ParseMoneyError::TooLarge),
+            parse_money("92233720368547758.08", USD)
+        );
+        assert_eq!(
+            Err(ParseMoneyError::TooLarge),
+            parse_money("100000000000000000000", USD)
+        );
+        assert_eq!(
+            Ok(Money(i64::MAX)),
+            parse_money("92233720368547758.07", USD)
+        );
+        assert_eq!(
+            Ok(Money(-i64::MAX)),
+            parse_money("-92233720368547758.07", USD)
+        );
+    }
+
+    #[test]
+    fn test_add_members() {
+        let caller = UserId(1);
+        let members = [UserId(3), UserId(1), UserId(2)];
+
+        let mut participants = vec![UserId(2)];
+        add_members(&mut participants, members, caller, false);
+        assert_eq!(vec![UserId(2), UserId(3)], participants);
+
+        let mut participants = vec![caller, UserId(2)];
+        add_members(&mut participants, members, caller, true);
+        assert_eq!(vec![caller, UserId(2), UserId(3)], participants);
+    }
 }
@@ -0,0 +1,72 @@
+use crate::model::journal::StorageError;
+use crate::model::pending::PendingRequests;
+
+use chrono::Utc;
+use serenity::prelude::TypeMap;
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::{self, Duration},
+};
+use typemap_rev::TypeMapKey;
+
+use std::{
+    fs::{self, File},
+    sync::Arc,
+};
+
+const PENDING_FILE: &str = "data/pending.json";
+const TEMP_FILE: &str = "data/pending.json.tmp";
+const EXPIRY_INTERVAL: u64 = 60;
+
+pub type PendingType = Arc<Mutex<PendingRequests>>;
+
+pub struct Pending;
+
+impl TypeMapKey for Pending {
+    type Value = PendingType;
+}
+
+pub async fn restore_pending(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let pending: PendingRequests = match File::open(PENDING_FILE) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(_e) => PendingRequests::default(),
+    };
+
+    data.write()
+        .await
+        .insert::<Pending>(PendingType::new(Mutex::new(pending)));
+    Ok(())
+}
+
+/// Writes every pending request to disk, replacing the previous file only once the new one is
+/// complete.
+pub fn save_pending(pending: &PendingRequests) -> Result<(), StorageError> {
+    let file = File::create(TEMP_FILE)?;
+    serde_json::to_writer_pretty(&file, pending)?;
+    file.sync_all()?;
+    fs::rename(TEMP_FILE, PENDING_FILE)?;
+    Ok(())
+}
+
+/// Drops requests nobody answered in time, checking every `EXPIRY_INTERVAL` seconds.
+pub async fn expire_pending(data: Arc<RwLock<TypeMap>>) {
+    let mut timer = time::interval(Duration::from_secs(EXPIRY_INTERVAL));
+
+    loop {
+        timer.tick().await;
+
+        let pending_lock = match data.read().await.get::<Pending>() {
+            Some(pending) => pending.clone(),
+            None => continue,
+        };
+
+        let mut pending = pending_lock.lock().await;
+        if pending.expire(Utc::now()) == 0 {
+            continue;
+        }
+
+        if let Err(e) = save_pending(&pending) {
+            println!("Could not save pending requests: {}", e);
+        }
+    }
+}
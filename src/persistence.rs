@@ -1,38 +1,114 @@
+use crate::journal::{Journal, JournalType};
 use crate::model::accounts::{Accounts, AccountsType};
+use crate::model::journal::StorageError;
+use crate::storage::open_storage;
 
 use serenity::prelude::TypeMap;
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{Mutex, Notify, RwLock},
+    time::{self, Duration},
+};
+use typemap_rev::TypeMapKey;
 
-use std::{fs::File, sync::Arc};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
-const DATA_FILE: &str = "data/balances.json";
+const DEFAULT_AUTOSAVE_INTERVAL: u64 = 300;
+const DEFAULT_AUTOSAVE_AFTER: usize = 50;
 
-pub async fn write_accounts_file(data: Arc<RwLock<TypeMap>>) {
-    let lock = get_lock(data).await;
-    let accounts = lock.read().await;
+/// Tracks how many transactions were recorded since balances were last saved.
+pub struct AutosaveState {
+    unsaved: AtomicUsize,
+    /// Save as soon as this many transactions are unsaved, or only on the interval if 0.
+    threshold: usize,
+    notify: Notify,
+}
 
-    if let Ok(file) = File::create(DATA_FILE) {
-        serde_json::to_writer_pretty(file, &*accounts).unwrap();
+impl AutosaveState {
+    pub fn mark_dirty(&self) {
+        let unsaved = self.unsaved.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.threshold > 0 && unsaved >= self.threshold {
+            self.notify.notify_one();
+        }
     }
 }
 
-pub async fn read_accounts_file(data: Arc<RwLock<TypeMap>>) {
-    let lock = get_lock(data).await;
-    let mut accounts = lock.write().await;
+pub struct Autosave;
+
+impl TypeMapKey for Autosave {
+    type Value = Arc<AutosaveState>;
+}
+
+pub async fn write_accounts_file(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let (accounts_lock, journal_lock) = get_locks(data).await;
+    let accounts = accounts_lock.read().await;
+
+    let mut journal = journal_lock.lock().await;
+    journal.save_balances(&accounts)
+}
+
+/// Loads every ledger from the configured storage backend.
+pub async fn restore_accounts(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let mut storage = open_storage()?;
+    let accounts = storage.load_balances()?;
+
+    let mut data = data.write().await;
+    data.insert::<Accounts>(AccountsType::new(RwLock::new(accounts)));
+    data.insert::<Journal>(JournalType::new(Mutex::new(storage)));
+    Ok(())
+}
+
+/// Saves balances every `AUTOSAVE_INTERVAL` seconds, or once `AUTOSAVE_AFTER` transactions have
+/// been recorded, skipping the save when nothing changed.
+pub async fn autosave(data: Arc<RwLock<TypeMap>>) {
+    let interval = std::env::var("AUTOSAVE_INTERVAL")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .filter(|&interval| interval > 0)
+        .unwrap_or(DEFAULT_AUTOSAVE_INTERVAL);
+    let threshold = std::env::var("AUTOSAVE_AFTER")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_AUTOSAVE_AFTER);
+
+    let state = Arc::new(AutosaveState {
+        unsaved: AtomicUsize::new(0),
+        threshold,
+        notify: Notify::new(),
+    });
+    data.write().await.insert::<Autosave>(state.clone());
 
-    if let Ok(file) = File::open(DATA_FILE) {
-        *accounts = serde_json::from_reader(file).unwrap();
+    let mut timer = time::interval(Duration::from_secs(interval));
+    timer.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {}
+            _ = state.notify.notified() => {}
+        }
+
+        let unsaved = state.unsaved.swap(0, Ordering::SeqCst);
+        if unsaved == 0 {
+            continue;
+        }
+
+        if let Err(e) = write_accounts_file(data.clone()).await {
+            println!("Could not save balances: {}", e);
+            state.unsaved.fetch_add(unsaved, Ordering::SeqCst);
+        }
     }
 }
 
-async fn get_lock(data: Arc<RwLock<TypeMap>>) -> AccountsType {
-    let accounts_lock = {
+async fn get_locks(data: Arc<RwLock<TypeMap>>) -> (AccountsType, JournalType) {
+    let locks = {
         let data_read = data.read().await;
-        match data_read.get::<Accounts>() {
-            Some(data) => data.clone(),
-            None => panic!("Could not get lock"),
+        match (data_read.get::<Accounts>(), data_read.get::<Journal>()) {
+            (Some(accounts), Some(journal)) => (accounts.clone(), journal.clone()),
+            _ => panic!("Could not get lock"),
         }
     };
 
-    accounts_lock
+    locks
 }
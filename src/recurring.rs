@@ -0,0 +1,86 @@
+use crate::model::commands::recurring::run_recurring_bill;
+use crate::model::journal::StorageError;
+use crate::model::recurring::RecurringBills;
+
+use chrono::Utc;
+use serenity::{http::Http, prelude::TypeMap};
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::{self, Duration},
+};
+use typemap_rev::TypeMapKey;
+
+use std::{
+    fs::{self, File},
+    sync::Arc,
+};
+
+const RECURRING_FILE: &str = "data/recurring.json";
+const TEMP_FILE: &str = "data/recurring.json.tmp";
+const SCHEDULER_INTERVAL: u64 = 60;
+
+pub type RecurringType = Arc<Mutex<RecurringBills>>;
+
+pub struct Recurring;
+
+impl TypeMapKey for Recurring {
+    type Value = RecurringType;
+}
+
+pub async fn restore_recurring(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let recurring: RecurringBills = match File::open(RECURRING_FILE) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(_e) => RecurringBills::default(),
+    };
+
+    data.write()
+        .await
+        .insert::<Recurring>(RecurringType::new(Mutex::new(recurring)));
+    Ok(())
+}
+
+/// Writes every recurring bill to disk, replacing the previous file only once the new one is
+/// complete.
+pub fn save_recurring(recurring: &RecurringBills) -> Result<(), StorageError> {
+    let file = File::create(TEMP_FILE)?;
+    serde_json::to_writer_pretty(&file, recurring)?;
+    file.sync_all()?;
+    fs::rename(TEMP_FILE, RECURRING_FILE)?;
+    Ok(())
+}
+
+/// Bills recurring bills as they come due, checking every `SCHEDULER_INTERVAL` seconds.
+///
+/// The first check happens straight away, so runs missed while the bot was down are caught up on
+/// startup.
+pub async fn schedule_recurring(http: Arc<Http>, data: Arc<RwLock<TypeMap>>) {
+    let mut timer = time::interval(Duration::from_secs(SCHEDULER_INTERVAL));
+
+    loop {
+        timer.tick().await;
+
+        let recurring_lock = match data.read().await.get::<Recurring>() {
+            Some(recurring) => recurring.clone(),
+            None => continue,
+        };
+
+        // the new run times are saved before billing, so a crash can skip a run but never
+        // bill one twice
+        let due = {
+            let mut recurring = recurring_lock.lock().await;
+            let due = recurring.take_due(Utc::now());
+            if due.is_empty() {
+                continue;
+            }
+            if let Err(e) = save_recurring(&recurring) {
+                println!("Could not save recurring bills: {}", e);
+                continue;
+            }
+            due
+        };
+
+        for (id, bill, due) in due {
+            run_recurring_bill(&http, data.clone(), id, &bill, &due).await;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::model::commands::reminders::{post_digest, remind_debtors};
+use crate::model::ids::GuildId;
+use crate::settings::all_guild_settings;
+
+use chrono::Utc;
+use serenity::{http::Http, prelude::TypeMap};
+use tokio::{
+    sync::RwLock,
+    time::{self, Duration},
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+const REMINDER_INTERVAL: u64 = 60;
+
+/// Sends reminders and digests as their guilds' schedules come round, checking every
+/// `REMINDER_INTERVAL` seconds.
+///
+/// Runs missed while the bot was down are skipped, and reminders held back by a user's quiet hours
+/// are only kept until the bot stops.
+pub async fn send_reminders(http: Arc<Http>, data: Arc<RwLock<TypeMap>>) {
+    let mut timer = time::interval(Duration::from_secs(REMINDER_INTERVAL));
+    let mut last = Utc::now();
+    let mut waiting: HashMap<GuildId, Vec<_>> = HashMap::new();
+
+    loop {
+        timer.tick().await;
+        let now = Utc::now();
+
+        for (guild_id, users) in std::mem::take(&mut waiting) {
+            let later = remind_debtors(&http, data.clone(), guild_id, Some(&users), now).await;
+            if !later.is_empty() {
+                waiting.insert(guild_id, later);
+            }
+        }
+
+        for (guild_id, settings) in all_guild_settings(data.clone()).await {
+            if let Some(schedule) = &settings.reminder_schedule {
+                if schedule.runs_between(last, now) {
+                    let later = remind_debtors(&http, data.clone(), guild_id, None, now).await;
+                    let users = waiting.entry(guild_id).or_default();
+                    for user in later {
+                        if !users.contains(&user) {
+                            users.push(user);
+                        }
+                    }
+                }
+            }
+            if let Some(channel_id) = settings.digest_channel {
+                if settings.digest_schedule().runs_between(last, now) {
+                    post_digest(&http, data.clone(), guild_id, channel_id).await;
+                }
+            }
+        }
+
+        last = now;
+    }
+}
@@ -0,0 +1,252 @@
+use crate::model::{
+    convert::RateTable,
+    currency::Currency,
+    ids::{GuildId, UserId},
+    journal::StorageError,
+    reminders::QuietHours,
+    schedule::Schedule,
+    utils::Money,
+};
+
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serenity::{model::id::ChannelId, prelude::TypeMap};
+use tokio::sync::RwLock;
+use typemap_rev::TypeMapKey;
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    sync::Arc,
+};
+
+const SETTINGS_FILE: &str = "data/settings.json";
+const TEMP_FILE: &str = "data/settings.json.tmp";
+const RATES_FILE: &str = "data/rates.json";
+const DEFAULT_CONFIRMATION_HOURS: u32 = 48;
+/// Mondays at 09:00 UTC.
+const DEFAULT_DIGEST_SCHEDULE: &str = "0 9 * * 1";
+
+/// Options an administrator can change for their guild.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildSettings {
+    /// Currency of amounts entered without a symbol or code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Exchange rates set with `/rate`, used instead of those in the rates file.
+    #[serde(default, skip_serializing_if = "RateTable::is_empty")]
+    pub rates: RateTable,
+    /// Respond with plain text instead of embeds, for servers where embeds are disabled.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plain_text: bool,
+    /// Hold `/owe` and `/bill` until the other users involved accept them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_confirmation: bool,
+    /// How long requests wait for confirmation before expiring, if not the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation_hours: Option<u32>,
+    /// When to message users who owe more than `reminder_threshold`, if at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_schedule: Option<Schedule>,
+    /// Total debt in the guild's currency that users must owe more than to be reminded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_threshold: Option<Money>,
+    /// Where to post the digest of outstanding balances, if anywhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_channel: Option<ChannelId>,
+    /// When to post the digest, if not the default of once a week.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_schedule: Option<Schedule>,
+}
+
+impl GuildSettings {
+    pub fn currency(&self) -> Currency {
+        self.currency.unwrap_or_else(default_currency)
+    }
+
+    pub fn confirmation_hours(&self) -> u32 {
+        self.confirmation_hours
+            .unwrap_or(DEFAULT_CONFIRMATION_HOURS)
+    }
+
+    /// How long requests wait for confirmation, if the guild requires it.
+    pub fn confirmation_window(&self) -> Option<Duration> {
+        if self.require_confirmation {
+            Some(Duration::hours(self.confirmation_hours().into()))
+        } else {
+            None
+        }
+    }
+
+    pub fn reminder_threshold(&self) -> Money {
+        self.reminder_threshold.unwrap_or_default()
+    }
+
+    pub fn digest_schedule(&self) -> Schedule {
+        match &self.digest_schedule {
+            Some(schedule) => schedule.clone(),
+            None => {
+                Schedule::parse(DEFAULT_DIGEST_SCHEDULE).expect("invalid default digest schedule")
+            }
+        }
+    }
+}
+
+/// Options each user chooses for themselves, shared by every guild.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// Show their balance and history to the whole channel instead of only to them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+    /// Let other users look up their balance.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub queryable: bool,
+    /// Don't send them reminders of what they owe.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_reminders: bool,
+    /// Leave their debts out of channel digests.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_digest: bool,
+    /// When reminders wait rather than being sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SettingsData {
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, GuildSettings>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub users: HashMap<UserId, UserSettings>,
+    /// Exchange rates from the rates file, shared by every guild.
+    #[serde(skip)]
+    pub rates: RateTable,
+}
+
+pub type SettingsType = Arc<RwLock<SettingsData>>;
+
+pub struct Settings;
+
+impl TypeMapKey for Settings {
+    type Value = SettingsType;
+}
+
+/// The currency used by guilds that haven't chosen one, from `DEFAULT_CURRENCY` or dollars.
+pub fn default_currency() -> Currency {
+    std::env::var("DEFAULT_CURRENCY")
+        .ok()
+        .and_then(|code| Currency::from_code(&code))
+        .unwrap_or_default()
+}
+
+pub async fn restore_settings(data: Arc<RwLock<TypeMap>>) -> Result<(), StorageError> {
+    let mut settings: SettingsData = match File::open(SETTINGS_FILE) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(_e) => SettingsData::default(),
+    };
+
+    if let Ok(file) = File::open(RATES_FILE) {
+        settings.rates = serde_json::from_reader(file)?;
+    }
+
+    data.write()
+        .await
+        .insert::<Settings>(SettingsType::new(RwLock::new(settings)));
+    Ok(())
+}
+
+pub async fn guild_settings(data: Arc<RwLock<TypeMap>>, guild_id: GuildId) -> GuildSettings {
+    let settings_lock = match data.read().await.get::<Settings>() {
+        Some(settings) => settings.clone(),
+        None => return GuildSettings::default(),
+    };
+
+    let settings = settings_lock.read().await;
+    settings.guilds.get(&guild_id).cloned().unwrap_or_default()
+}
+
+/// Every guild that has changed its settings.
+pub async fn all_guild_settings(data: Arc<RwLock<TypeMap>>) -> Vec<(GuildId, GuildSettings)> {
+    let settings_lock = match data.read().await.get::<Settings>() {
+        Some(settings) => settings.clone(),
+        None => return Vec::new(),
+    };
+
+    let settings = settings_lock.read().await;
+    settings
+        .guilds
+        .iter()
+        .map(|(&guild_id, settings)| (guild_id, settings.clone()))
+        .collect()
+}
+
+pub async fn user_settings(data: Arc<RwLock<TypeMap>>, user_id: UserId) -> UserSettings {
+    let settings_lock = match data.read().await.get::<Settings>() {
+        Some(settings) => settings.clone(),
+        None => return UserSettings::default(),
+    };
+
+    let settings = settings_lock.read().await;
+    settings.users.get(&user_id).copied().unwrap_or_default()
+}
+
+/// The exchange rates a guild converts with, from the rates file and its own `/rate` settings.
+pub async fn exchange_rates(data: Arc<RwLock<TypeMap>>, guild_id: GuildId) -> RateTable {
+    let settings_lock = match data.read().await.get::<Settings>() {
+        Some(settings) => settings.clone(),
+        None => return RateTable::default(),
+    };
+
+    let settings = settings_lock.read().await;
+    let mut rates = settings.rates.clone();
+    if let Some(guild) = settings.guilds.get(&guild_id) {
+        rates.extend(&guild.rates);
+    }
+    rates
+}
+
+/// Changes a guild's settings and saves everyone's settings to disk.
+pub async fn update_guild_settings(
+    data: Arc<RwLock<TypeMap>>,
+    guild_id: GuildId,
+    update: impl FnOnce(&mut GuildSettings),
+) -> Result<(), StorageError> {
+    update_settings(data, |settings| {
+        update(settings.guilds.entry(guild_id).or_default())
+    })
+    .await
+}
+
+/// Changes a user's settings and saves everyone's settings to disk.
+pub async fn update_user_settings(
+    data: Arc<RwLock<TypeMap>>,
+    user_id: UserId,
+    update: impl FnOnce(&mut UserSettings),
+) -> Result<(), StorageError> {
+    update_settings(data, |settings| {
+        update(settings.users.entry(user_id).or_default())
+    })
+    .await
+}
+
+async fn update_settings(
+    data: Arc<RwLock<TypeMap>>,
+    update: impl FnOnce(&mut SettingsData),
+) -> Result<(), StorageError> {
+    let settings_lock = match data.read().await.get::<Settings>() {
+        Some(settings) => settings.clone(),
+        None => return Err(StorageError::Invalid("could not get lock".to_string())),
+    };
+
+    let mut settings = settings_lock.write().await;
+    let mut updated = settings.clone();
+    update(&mut updated);
+
+    let file = File::create(TEMP_FILE)?;
+    serde_json::to_writer_pretty(&file, &updated)?;
+    file.sync_all()?;
+    fs::rename(TEMP_FILE, SETTINGS_FILE)?;
+
+    *settings = updated;
+    Ok(())
+}
@@ -0,0 +1,419 @@
+pub mod cache;
+pub mod json;
+pub mod sqlite;
+
+use crate::model::journal::{Storage, StorageError};
+
+use std::path::Path;
+
+const DATA_DIR: &str = "data";
+const DEFAULT_SQLITE_FILE: &str = "data/ledger.db";
+
+/// Opens the backend chosen by `STORAGE_BACKEND`, either `json` (the default) or `sqlite`.
+pub fn open_storage() -> Result<Box<dyn Storage>, StorageError> {
+    let storage: Box<dyn Storage> = match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("json") | Err(_) => Box::new(json::JsonStorage::open(Path::new(DATA_DIR))?),
+        Ok("sqlite") => {
+            let path =
+                std::env::var("SQLITE_FILE").unwrap_or_else(|_e| DEFAULT_SQLITE_FILE.to_string());
+            Box::new(open_sqlite(&path, Path::new(DATA_DIR))?)
+        }
+        Ok(backend) => {
+            return Err(StorageError::Invalid(format!(
+                "unknown storage backend {}",
+                backend
+            )))
+        }
+    };
+    Ok(Box::new(cache::GroupCache::new(storage)))
+}
+
+/// Opens the SQLite database at `path`, first importing the JSON journal and snapshot in
+/// `json_dir` if the database is new, so switching backends keeps every balance and transaction.
+fn open_sqlite(path: &str, json_dir: &Path) -> Result<sqlite::SqliteStorage, StorageError> {
+    let mut storage = sqlite::SqliteStorage::open(path)?;
+    if storage.is_empty()? && json::has_data(json_dir) {
+        let imported = storage.import(&mut json::JsonStorage::open(json_dir)?)?;
+        println!(
+            "Imported {} journal entries from {} into {}.",
+            imported,
+            json_dir.display(),
+            path
+        );
+    }
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::{
+        accounts::{Ledgers, Transfer},
+        currency::USD,
+        ids::{GuildId, UserId},
+        journal::{EntryFilter, JournalEntry},
+        transaction::{BillTransaction, OweTransaction, TransactionType, VoidTransaction},
+        utils::Money,
+    };
+
+    use std::{collections::HashMap, fs, io::Write, path::PathBuf};
+
+    const GUILD: GuildId = GuildId(1);
+    const OTHER_GUILD: GuildId = GuildId(2);
+    const ALICE: UserId = UserId(10);
+    const BOB: UserId = UserId(20);
+    const CAROL: UserId = UserId(30);
+
+    /// A directory of its own for one test, removed again afterwards.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let dir = std::env::temp_dir().join(format!(
+                "discord-balance-tracker-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        fn sqlite_file(&self) -> String {
+            self.0.join("ledger.db").to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn money(minor: i64) -> Money {
+        Money::new(minor).unwrap()
+    }
+
+    fn owe(initiator: UserId, recipient: UserId, minor: i64) -> TransactionType {
+        TransactionType::Owe(OweTransaction {
+            initiator,
+            amount: money(minor),
+            recipient,
+            description: "lunch".to_string(),
+            currency: USD,
+        })
+    }
+
+    fn seqs(entries: Vec<JournalEntry>) -> Vec<u64> {
+        entries.into_iter().map(|entry| entry.seq).collect()
+    }
+
+    /// Balances as replaying every entry in `storage` from the start leaves them.
+    fn replayed(storage: &dyn Storage) -> HashMap<GuildId, Ledgers> {
+        let mut accounts = HashMap::new();
+        let entries = storage
+            .query(&EntryFilter::default(), 0, usize::MAX)
+            .unwrap();
+        for entry in entries.iter().rev() {
+            entry.replay(&mut accounts).unwrap();
+        }
+        accounts
+    }
+
+    fn check_append_and_query(storage: &mut dyn Storage) {
+        let owed = storage.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+        assert_eq!(owed.seq, 1);
+
+        let bill = TransactionType::Bill(BillTransaction {
+            initiator: ALICE,
+            amount: money(600),
+            recipients: vec![(BOB, money(300)), (CAROL, money(300))],
+            description: "pizza".to_string(),
+            currency: USD,
+        });
+        storage.append(GUILD, Some("flat"), &bill).unwrap();
+        storage
+            .append(OTHER_GUILD, None, &owe(BOB, CAROL, 100))
+            .unwrap();
+
+        let void = TransactionType::Void(VoidTransaction {
+            initiator: ALICE,
+            voided: owed.seq,
+            transfers: owed.transfers.clone(),
+        });
+        assert_eq!(storage.append(GUILD, None, &void).unwrap().seq, 4);
+        assert!(storage.append(GUILD, None, &void).is_err());
+
+        assert_eq!(storage.last_seq().unwrap(), 4);
+        assert_eq!(storage.last_seq_in(GUILD).unwrap(), 4);
+        assert_eq!(storage.last_seq_in(OTHER_GUILD).unwrap(), 3);
+        assert_eq!(
+            storage.get(2).unwrap().unwrap().group.as_deref(),
+            Some("flat")
+        );
+        assert!(storage.get(5).unwrap().is_none());
+        assert!(storage.is_voided(1).unwrap());
+        assert!(!storage.is_voided(2).unwrap());
+
+        let in_guild = EntryFilter {
+            guild_id: Some(GUILD),
+            ..Default::default()
+        };
+        assert_eq!(
+            seqs(storage.query(&in_guild, 0, 10).unwrap()),
+            vec![4, 2, 1]
+        );
+        assert_eq!(seqs(storage.query(&in_guild, 1, 1).unwrap()), vec![2]);
+        assert_eq!(storage.count(&in_guild).unwrap(), 3);
+
+        let filters = [
+            (
+                EntryFilter {
+                    involving: Some(CAROL),
+                    ..Default::default()
+                },
+                vec![3, 2],
+            ),
+            (
+                EntryFilter {
+                    involving: Some(ALICE),
+                    counterparty: Some(CAROL),
+                    ..Default::default()
+                },
+                vec![2],
+            ),
+            (
+                EntryFilter {
+                    group: Some("flat".to_string()),
+                    ..Default::default()
+                },
+                vec![2],
+            ),
+            (
+                EntryFilter {
+                    guild_id: Some(GUILD),
+                    voidable: true,
+                    ..Default::default()
+                },
+                vec![2],
+            ),
+            (
+                EntryFilter {
+                    upto: Some(2),
+                    ..Default::default()
+                },
+                vec![2, 1],
+            ),
+        ];
+        for (filter, expected) in filters {
+            assert_eq!(seqs(storage.query(&filter, 0, 10).unwrap()), expected);
+            assert_eq!(storage.count(&filter).unwrap(), expected.len());
+        }
+    }
+
+    /// Balances saved part way through are loaded along with everything recorded after them.
+    fn check_balances(mut open: impl FnMut() -> Box<dyn Storage>) {
+        let expected = {
+            let mut storage = open();
+            storage.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+            storage.save_balances(&replayed(storage.as_ref())).unwrap();
+            storage
+                .append(OTHER_GUILD, None, &owe(BOB, CAROL, 100))
+                .unwrap();
+            replayed(storage.as_ref())
+        };
+
+        let mut storage = open();
+        assert_eq!(storage.last_seq().unwrap(), 2);
+        assert_eq!(storage.load_balances().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_json_append_and_query() {
+        let dir = TestDir::new("json-query");
+        check_append_and_query(&mut json::JsonStorage::open(&dir.0).unwrap());
+    }
+
+    #[test]
+    fn test_sqlite_append_and_query() {
+        check_append_and_query(&mut sqlite::SqliteStorage::open(":memory:").unwrap());
+    }
+
+    #[test]
+    fn test_json_balances() {
+        let dir = TestDir::new("json-balances");
+        check_balances(|| Box::new(json::JsonStorage::open(&dir.0).unwrap()));
+    }
+
+    #[test]
+    fn test_sqlite_balances() {
+        let dir = TestDir::new("sqlite-balances");
+        check_balances(|| Box::new(sqlite::SqliteStorage::open(&dir.sqlite_file()).unwrap()));
+    }
+
+    #[test]
+    fn test_json_recovery() {
+        let dir = TestDir::new("json-recovery");
+        let expected = {
+            let mut storage = json::JsonStorage::open(&dir.0).unwrap();
+            storage.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+            storage.save_balances(&replayed(&storage)).unwrap();
+            storage.append(GUILD, None, &owe(BOB, CAROL, 200)).unwrap();
+            replayed(&storage)
+        };
+
+        // an entry cut short while being written was never acknowledged
+        let mut journal = fs::OpenOptions::new()
+            .append(true)
+            .open(dir.0.join("journal.jsonl"))
+            .unwrap();
+        journal.write_all(b"{\"seq\":3,\"guild_id\":").unwrap();
+        drop(journal);
+
+        // only the entry after the snapshot is replayed onto it
+        let mut storage = json::JsonStorage::open(&dir.0).unwrap();
+        assert_eq!(storage.last_seq().unwrap(), 2);
+        assert_eq!(storage.load_balances().unwrap(), expected);
+
+        assert_eq!(
+            storage
+                .append(GUILD, None, &owe(CAROL, ALICE, 50))
+                .unwrap()
+                .seq,
+            3
+        );
+        drop(storage);
+        let storage = json::JsonStorage::open(&dir.0).unwrap();
+        assert_eq!(storage.count(&EntryFilter::default()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_json_snapshot_ahead_of_journal() {
+        let dir = TestDir::new("json-ahead");
+        {
+            let mut storage = json::JsonStorage::open(&dir.0).unwrap();
+            storage.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+            storage.save_balances(&replayed(&storage)).unwrap();
+        }
+        fs::remove_file(dir.0.join("journal.jsonl")).unwrap();
+
+        let mut storage = json::JsonStorage::open(&dir.0).unwrap();
+        assert!(storage.load_balances().is_err());
+    }
+
+    #[test]
+    fn test_json_snapshot_backup() {
+        let dir = TestDir::new("json-backup");
+        let snapshot = dir.0.join("balances.json");
+        let backup = dir.0.join("balances.json.bak");
+
+        let expected = {
+            let mut storage = json::JsonStorage::open(&dir.0).unwrap();
+            storage.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+            storage.save_balances(&replayed(&storage)).unwrap();
+            assert!(snapshot.exists() && !backup.exists());
+
+            // the snapshot being replaced is kept as the backup
+            let first = fs::read_to_string(&snapshot).unwrap();
+            storage.append(GUILD, None, &owe(BOB, CAROL, 200)).unwrap();
+            storage.save_balances(&replayed(&storage)).unwrap();
+            assert_eq!(fs::read_to_string(&backup).unwrap(), first);
+            assert!(!dir.0.join("balances.json.tmp").exists());
+
+            storage.append(GUILD, None, &owe(CAROL, ALICE, 50)).unwrap();
+            replayed(&storage)
+        };
+        let load = || json::JsonStorage::open(&dir.0).unwrap().load_balances();
+        assert_eq!(load().unwrap(), expected);
+
+        // an unreadable or missing snapshot is recovered from the backup and the journal
+        fs::write(&snapshot, "{\"version\": 1, \"seq\"").unwrap();
+        assert_eq!(load().unwrap(), expected);
+        fs::remove_file(&snapshot).unwrap();
+        assert_eq!(load().unwrap(), expected);
+
+        // but an unreadable snapshot without a backup isn't mistaken for having no balances
+        fs::write(&snapshot, "not json").unwrap();
+        fs::remove_file(&backup).unwrap();
+        assert!(load().is_err());
+    }
+
+    #[test]
+    fn test_sqlite_imports_json() {
+        let dir = TestDir::new("sqlite-import");
+        let expected = {
+            let mut json = json::JsonStorage::open(&dir.0).unwrap();
+            json.append(GUILD, None, &owe(ALICE, BOB, 500)).unwrap();
+            json.save_balances(&replayed(&json)).unwrap();
+            json.append(GUILD, Some("flat"), &owe(BOB, CAROL, 100))
+                .unwrap();
+            json.load_balances().unwrap()
+        };
+
+        let mut sqlite = open_sqlite(&dir.sqlite_file(), &dir.0).unwrap();
+        assert_eq!(sqlite.load_balances().unwrap(), expected);
+        assert_eq!(
+            sqlite.get(2).unwrap().unwrap().group.as_deref(),
+            Some("flat")
+        );
+        assert_eq!(
+            sqlite
+                .append(GUILD, None, &owe(CAROL, ALICE, 50))
+                .unwrap()
+                .seq,
+            3
+        );
+        drop(sqlite);
+
+        // the import only happens once
+        let sqlite = open_sqlite(&dir.sqlite_file(), &dir.0).unwrap();
+        assert_eq!(sqlite.count(&EntryFilter::default()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_group_cache() {
+        let dir = TestDir::new("group-cache");
+        let open = || sqlite::SqliteStorage::open(&dir.sqlite_file()).unwrap();
+        let mut storage = cache::GroupCache::new(Box::new(open()));
+
+        let owed = storage
+            .append(GUILD, Some("flat"), &owe(ALICE, BOB, 500))
+            .unwrap();
+        assert_eq!(
+            storage.group_ledgers(GUILD, "flat").unwrap(),
+            open().group_ledgers(GUILD, "flat").unwrap()
+        );
+
+        // entries appended once the group is cached keep it up to date
+        storage
+            .append(GUILD, Some("flat"), &owe(BOB, CAROL, 200))
+            .unwrap();
+        storage
+            .append(GUILD, None, &owe(CAROL, ALICE, 100))
+            .unwrap();
+        storage
+            .append(OTHER_GUILD, Some("flat"), &owe(ALICE, CAROL, 50))
+            .unwrap();
+        let void = TransactionType::Void(VoidTransaction {
+            initiator: ALICE,
+            voided: owed.seq,
+            transfers: owed
+                .transfers
+                .iter()
+                .map(|transfer| Transfer {
+                    amount: -transfer.amount,
+                    ..transfer.clone()
+                })
+                .collect(),
+        });
+        storage.append(GUILD, Some("flat"), &void).unwrap();
+
+        for guild_id in [GUILD, OTHER_GUILD] {
+            assert_eq!(
+                storage.group_ledgers(guild_id, "flat").unwrap(),
+                open().group_ledgers(guild_id, "flat").unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::model::{
+    accounts::{apply_transfer, Ledgers},
+    ids::GuildId,
+    journal::{EntryFilter, JournalEntry, Storage, StorageError},
+    transaction::TransactionType,
+};
+
+use std::collections::HashMap;
+
+/// A backend that keeps the balances of every group it has been asked for, updating them as
+/// entries are appended, so a group's journal is only replayed the first time it's used.
+pub struct GroupCache {
+    storage: Box<dyn Storage>,
+    groups: HashMap<(GuildId, String), Ledgers>,
+}
+
+impl GroupCache {
+    pub fn new(storage: Box<dyn Storage>) -> GroupCache {
+        GroupCache {
+            storage,
+            groups: HashMap::new(),
+        }
+    }
+}
+
+impl Storage for GroupCache {
+    fn load_balances(&mut self) -> Result<HashMap<GuildId, Ledgers>, StorageError> {
+        self.storage.load_balances()
+    }
+
+    fn save_balances(&mut self, accounts: &HashMap<GuildId, Ledgers>) -> Result<(), StorageError> {
+        self.storage.save_balances(accounts)
+    }
+
+    fn append(
+        &mut self,
+        guild_id: GuildId,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<JournalEntry, StorageError> {
+        let entry = self.storage.append(guild_id, group, transaction)?;
+
+        if let Some(group) = group {
+            let key = (guild_id, group.to_string());
+            if let Some(ledgers) = self.groups.get_mut(&key) {
+                let applied = entry.transfers.iter().try_for_each(|transfer| {
+                    apply_transfer(ledgers.entry(transfer.currency).or_default(), transfer)
+                });
+                // replayed, and the overflow reported, the next time the group is used
+                if applied.is_err() {
+                    self.groups.remove(&key);
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn get(&self, seq: u64) -> Result<Option<JournalEntry>, StorageError> {
+        self.storage.get(seq)
+    }
+
+    fn is_voided(&self, seq: u64) -> Result<bool, StorageError> {
+        self.storage.is_voided(seq)
+    }
+
+    fn last_seq(&self) -> Result<u64, StorageError> {
+        self.storage.last_seq()
+    }
+
+    fn last_seq_in(&self, guild_id: GuildId) -> Result<u64, StorageError> {
+        self.storage.last_seq_in(guild_id)
+    }
+
+    fn query(
+        &self,
+        filter: &EntryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<JournalEntry>, StorageError> {
+        self.storage.query(filter, offset, limit)
+    }
+
+    fn count(&self, filter: &EntryFilter) -> Result<usize, StorageError> {
+        self.storage.count(filter)
+    }
+
+    fn group_ledgers(&mut self, guild_id: GuildId, group: &str) -> Result<Ledgers, StorageError> {
+        let key = (guild_id, group.to_string());
+        if let Some(ledgers) = self.groups.get(&key) {
+            return Ok(ledgers.clone());
+        }
+
+        let ledgers = self.storage.group_ledgers(guild_id, group)?;
+        self.groups.insert(key, ledgers.clone());
+        Ok(ledgers)
+    }
+}
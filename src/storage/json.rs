@@ -0,0 +1,350 @@
+use crate::model::{
+    accounts::{Ledger, Ledgers},
+    currency::Currency,
+    ids::GuildId,
+    journal::{EntryFilter, JournalEntry, Storage, StorageError},
+    transaction::TransactionType,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const DATA_FILE: &str = "balances.json";
+const TEMP_FILE: &str = "balances.json.tmp";
+const BACKUP_FILE: &str = "balances.json.bak";
+const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Snapshots written before balances were kept per currency have no version.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Balances as of journal entry `seq`, so startup only replays entries after it.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<A> {
+    #[serde(default)]
+    version: u32,
+    seq: u64,
+    accounts: A,
+}
+
+/// Keeps the journal as JSON lines and balances as a JSON snapshot, with every entry in memory.
+pub struct JsonStorage {
+    /// The directory holding the journal and snapshots.
+    dir: PathBuf,
+    journal: File,
+    len: u64,
+    entries: Vec<JournalEntry>,
+    voided: HashSet<u64>,
+}
+
+impl JsonStorage {
+    /// Opens the journal in `dir` for appending and reads every entry recorded so far.
+    ///
+    /// A partially written entry at the end of the file was never acknowledged, so it is
+    /// discarded rather than treated as corruption.
+    pub fn open(dir: &Path) -> Result<JsonStorage, StorageError> {
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))?;
+
+        let mut contents = String::new();
+        journal.read_to_string(&mut contents)?;
+
+        let mut entries = Vec::new();
+        let mut len = 0;
+        for line in contents.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                println!("Discarding incomplete journal entry.");
+                break;
+            }
+
+            match serde_json::from_str::<JournalEntry>(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    return Err(StorageError::Invalid(format!(
+                        "corrupt journal entry at byte {}: {}",
+                        len, e
+                    )))
+                }
+            }
+            len += line.len() as u64;
+        }
+
+        if len != contents.len() as u64 {
+            journal.set_len(len)?;
+        }
+
+        let voided = entries.iter().filter_map(|entry| entry.voids).collect();
+
+        Ok(JsonStorage {
+            dir: dir.to_path_buf(),
+            journal,
+            len,
+            entries,
+            voided,
+        })
+    }
+
+    fn matching<'a>(&'a self, filter: &'a EntryFilter) -> impl Iterator<Item = &'a JournalEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |entry| filter.matches(entry, |seq| self.voided.contains(&seq)))
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_balances(&mut self) -> Result<HashMap<GuildId, Ledgers>, StorageError> {
+        let mut snapshot = read_snapshot(&self.dir)?;
+
+        // numbering entries from the journal's end would reuse those the snapshot already includes
+        let last_seq = self.last_seq()?;
+        if snapshot.seq > last_seq {
+            return Err(StorageError::Invalid(format!(
+                "balances snapshot includes journal entries up to #{}, but the journal ends at #{}",
+                snapshot.seq, last_seq
+            )));
+        }
+
+        let mut replayed = 0;
+        for entry in self.entries.iter().filter(|entry| entry.seq > snapshot.seq) {
+            entry.replay(&mut snapshot.accounts)?;
+            replayed += 1;
+        }
+
+        println!("Replayed {} journal entries.", replayed);
+
+        Ok(snapshot.accounts)
+    }
+
+    /// Replaces the snapshot without ever leaving a partially written one in its place.
+    ///
+    /// The new snapshot is written and synced to a temporary file, the current one is kept as
+    /// `balances.json.bak`, and the temporary file is then renamed over it.
+    fn save_balances(&mut self, accounts: &HashMap<GuildId, Ledgers>) -> Result<(), StorageError> {
+        let data_file = self.dir.join(DATA_FILE);
+        let temp_file = self.dir.join(TEMP_FILE);
+        let backup_file = self.dir.join(BACKUP_FILE);
+
+        let mut file = File::create(&temp_file)?;
+        serde_json::to_writer_pretty(
+            &mut file,
+            &Snapshot {
+                version: SNAPSHOT_VERSION,
+                seq: self.last_seq()?,
+                accounts,
+            },
+        )?;
+        file.sync_all()?;
+
+        ignore_missing(fs::remove_file(&backup_file))?;
+        ignore_missing(fs::hard_link(&data_file, &backup_file))?;
+        fs::rename(&temp_file, &data_file)?;
+
+        // make the rename itself durable
+        File::open(&self.dir)?.sync_all()?;
+
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        guild_id: GuildId,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<JournalEntry, StorageError> {
+        if let Some(voided) = transaction.voids() {
+            if self.voided.contains(&voided) {
+                return Err(StorageError::Invalid(format!(
+                    "transaction #{} was already voided",
+                    voided
+                )));
+            }
+        }
+
+        let entry = JournalEntry::new(self.last_seq()? + 1, guild_id, group, transaction)?;
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        if let Err(e) = self
+            .journal
+            .write_all(line.as_bytes())
+            .and_then(|_| self.journal.sync_data())
+        {
+            // drop whatever part of the entry made it out so later appends stay parseable
+            self.journal.set_len(self.len)?;
+            return Err(e.into());
+        }
+
+        self.len += line.len() as u64;
+        if let Some(voided) = entry.voids {
+            self.voided.insert(voided);
+        }
+        self.entries.push(entry.clone());
+
+        Ok(entry)
+    }
+
+    fn get(&self, seq: u64) -> Result<Option<JournalEntry>, StorageError> {
+        Ok(self
+            .entries
+            .binary_search_by_key(&seq, |entry| entry.seq)
+            .ok()
+            .map(|index| self.entries[index].clone()))
+    }
+
+    fn is_voided(&self, seq: u64) -> Result<bool, StorageError> {
+        Ok(self.voided.contains(&seq))
+    }
+
+    fn last_seq(&self) -> Result<u64, StorageError> {
+        Ok(self.entries.last().map_or(0, |entry| entry.seq))
+    }
+
+    fn last_seq_in(&self, guild_id: GuildId) -> Result<u64, StorageError> {
+        Ok(self
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.guild_id == guild_id)
+            .map_or(0, |entry| entry.seq))
+    }
+
+    fn query(
+        &self,
+        filter: &EntryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<JournalEntry>, StorageError> {
+        Ok(self
+            .matching(filter)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn count(&self, filter: &EntryFilter) -> Result<usize, StorageError> {
+        Ok(self.matching(filter).count())
+    }
+}
+
+/// Whether `dir` holds a journal or snapshot written by this backend.
+pub fn has_data(dir: &Path) -> bool {
+    [JOURNAL_FILE, DATA_FILE, BACKUP_FILE]
+        .iter()
+        .any(|file| dir.join(file).exists())
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Reads the latest snapshot, falling back to the previous one if the latest is missing or can't
+/// be read.
+///
+/// An older snapshot is still safe to use, as the journal entries recorded since are replayed.
+fn read_snapshot(dir: &Path) -> Result<Snapshot<HashMap<GuildId, Ledgers>>, StorageError> {
+    let latest = match fs::read_to_string(dir.join(DATA_FILE)) {
+        Ok(contents) => parse_snapshot(&contents),
+        Err(e) => Err(e.into()),
+    };
+    let e = match latest {
+        Ok(snapshot) => return Ok(snapshot),
+        Err(e) => e,
+    };
+
+    match fs::read_to_string(dir.join(BACKUP_FILE)) {
+        Ok(contents) => {
+            println!(
+                "Cannot read balances snapshot ({}), using the previous copy.",
+                e
+            );
+            parse_snapshot(&contents)
+        }
+        Err(backup_e) if backup_e.kind() == io::ErrorKind::NotFound => match e {
+            StorageError::Io(e) if e.kind() == io::ErrorKind::NotFound => Ok(Snapshot {
+                version: SNAPSHOT_VERSION,
+                seq: 0,
+                accounts: HashMap::new(),
+            }),
+            e => Err(e),
+        },
+        Err(backup_e) => Err(backup_e.into()),
+    }
+}
+
+/// Parses a snapshot in the current format or any written by earlier versions.
+fn parse_snapshot(contents: &str) -> Result<Snapshot<HashMap<GuildId, Ledgers>>, StorageError> {
+    if let Ok(snapshot) = serde_json::from_str::<Snapshot<_>>(contents) {
+        if snapshot.version == SNAPSHOT_VERSION {
+            return Ok(snapshot);
+        }
+    }
+
+    if let Ok(snapshot) = serde_json::from_str::<Snapshot<HashMap<GuildId, Ledger>>>(contents) {
+        return Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            seq: snapshot.seq,
+            accounts: in_legacy_currency(snapshot.accounts),
+        });
+    }
+
+    // balances written before the journal existed already include everything up to it
+    let accounts = match serde_json::from_str::<HashMap<GuildId, Ledger>>(contents) {
+        Ok(guilds) => guilds,
+        Err(_e) => migrate_legacy_accounts(contents)?,
+    };
+
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        seq: 0,
+        accounts: in_legacy_currency(accounts),
+    })
+}
+
+/// Balances saved before currencies were tracked were all in dollars.
+fn in_legacy_currency(accounts: HashMap<GuildId, Ledger>) -> HashMap<GuildId, Ledgers> {
+    accounts
+        .into_iter()
+        .map(|(guild_id, ledger)| {
+            let mut ledgers = Ledgers::new();
+            ledgers.insert(Currency::default(), ledger);
+            (guild_id, ledgers)
+        })
+        .collect()
+}
+
+/// Moves balances saved before ledgers were scoped per guild into `DEFAULT_GUILD_ID`.
+fn migrate_legacy_accounts(contents: &str) -> Result<HashMap<GuildId, Ledger>, StorageError> {
+    let ledger: Ledger = serde_json::from_str(contents)?;
+
+    let default_guild = match std::env::var("DEFAULT_GUILD_ID")
+        .ok()
+        .and_then(|id| id.parse().ok())
+    {
+        Some(id) => GuildId(id),
+        None => {
+            return Err(StorageError::Invalid(
+                "a valid DEFAULT_GUILD_ID is needed to migrate legacy balances".to_string(),
+            ))
+        }
+    };
+
+    println!("Migrated legacy balances into guild {}.", default_guild);
+
+    let mut accounts = HashMap::new();
+    accounts.insert(default_guild, ledger);
+    Ok(accounts)
+}
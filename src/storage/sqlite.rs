@@ -0,0 +1,476 @@
+use crate::model::{
+    accounts::{Ledgers, Transfer},
+    currency::Currency,
+    ids::{GuildId, UserId},
+    journal::{EntryFilter, JournalEntry, Storage, StorageError},
+    transaction::{TransactionKind, TransactionType},
+    utils::Money,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row};
+
+use std::collections::HashMap;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        seq INTEGER PRIMARY KEY,
+        timestamp TEXT,
+        guild_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        initiator INTEGER NOT NULL,
+        description TEXT,
+        voids INTEGER UNIQUE
+    );
+    CREATE INDEX IF NOT EXISTS entries_guild ON entries (guild_id, seq);
+
+    CREATE TABLE IF NOT EXISTS transfers (
+        seq INTEGER NOT NULL REFERENCES entries (seq),
+        position INTEGER NOT NULL,
+        creditor INTEGER NOT NULL,
+        debtor INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (seq, position)
+    );
+    CREATE INDEX IF NOT EXISTS transfers_creditor ON transfers (creditor);
+    CREATE INDEX IF NOT EXISTS transfers_debtor ON transfers (debtor);
+
+    CREATE TABLE IF NOT EXISTS balances (
+        guild_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        other_id INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (guild_id, user_id, other_id)
+    );
+";
+
+/// Changes applied after `SCHEMA` in order, with the database's `user_version` counting how many
+/// already have been.
+const MIGRATIONS: &[&str] = &[
+    "
+    ALTER TABLE transfers ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+
+    CREATE TABLE balances_by_currency (
+        guild_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        other_id INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (guild_id, currency, user_id, other_id)
+    );
+    INSERT INTO balances_by_currency
+        SELECT guild_id, user_id, other_id, 'USD', amount FROM balances;
+    DROP TABLE balances;
+    ALTER TABLE balances_by_currency RENAME TO balances;
+",
+    "
+    ALTER TABLE entries ADD COLUMN group_name TEXT;
+    CREATE INDEX entries_group ON entries (guild_id, group_name, seq);
+",
+];
+
+const ENTRY_COLUMNS: &str =
+    "seq, timestamp, guild_id, kind, initiator, description, voids, group_name";
+
+/// Keeps the journal and balances in an embedded SQLite database.
+///
+/// Balances are updated in the same database transaction as each journal entry, so they never
+/// need to be saved separately or rebuilt by replaying the journal.
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<SqliteStorage, StorageError> {
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "FULL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", applied + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(SqliteStorage { connection })
+    }
+
+    /// Whether nothing has been recorded in the database yet.
+    pub fn is_empty(&self) -> Result<bool, StorageError> {
+        Ok(self.connection.query_row(
+            "SELECT NOT EXISTS (SELECT 1 FROM entries) AND NOT EXISTS (SELECT 1 FROM balances)",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Copies every journal entry and the current balances from `source`, keeping each entry's
+    /// sequence number, in a single database transaction.
+    ///
+    /// Balances are copied rather than replayed, as they may include some from before the journal
+    /// was kept.
+    pub fn import(&mut self, source: &mut dyn Storage) -> Result<usize, StorageError> {
+        let accounts = source.load_balances()?;
+        let entries = source.query(&EntryFilter::default(), 0, usize::MAX)?;
+
+        let db_transaction = self.connection.transaction()?;
+        for entry in entries.iter().rev() {
+            insert_entry(&db_transaction, entry)?;
+        }
+        for (&guild_id, ledgers) in &accounts {
+            for (&currency, ledger) in ledgers {
+                for (&user, account) in ledger {
+                    for (&other, &amount) in account {
+                        add_balance(&db_transaction, guild_id, currency, user, other, amount)?;
+                    }
+                }
+            }
+        }
+        db_transaction.commit()?;
+
+        Ok(entries.len())
+    }
+
+    fn read_entry(&self, row: &Row) -> rusqlite::Result<JournalEntry> {
+        let seq: i64 = row.get(0)?;
+        let timestamp: Option<String> = row.get(1)?;
+        let kind: String = row.get(3)?;
+        let voids: Option<i64> = row.get(6)?;
+
+        let mut statement = self.connection.prepare_cached(
+            "SELECT creditor, debtor, amount, currency FROM transfers WHERE seq = ?
+             ORDER BY position",
+        )?;
+        let transfers = statement
+            .query_map([seq], |row| {
+                Ok(Transfer {
+                    creditor: UserId(row.get::<_, i64>(0)? as u64),
+                    debtor: UserId(row.get::<_, i64>(1)? as u64),
+                    amount: read_money(row, 2)?,
+                    currency: read_currency(row, 3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Transfer>>>()?;
+
+        Ok(JournalEntry {
+            seq: seq as u64,
+            timestamp: timestamp
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+                .map(|timestamp| timestamp.with_timezone(&Utc)),
+            guild_id: GuildId(row.get::<_, i64>(2)? as u64),
+            group: row.get(7)?,
+            kind: parse_kind(&kind).ok_or(rusqlite::Error::InvalidColumnType(
+                3,
+                "kind".to_string(),
+                rusqlite::types::Type::Text,
+            ))?,
+            initiator: UserId(row.get::<_, i64>(4)? as u64),
+            description: row.get(5)?,
+            voids: voids.map(|voids| voids as u64),
+            transfers,
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_balances(&mut self) -> Result<HashMap<GuildId, Ledgers>, StorageError> {
+        let mut accounts: HashMap<GuildId, Ledgers> = HashMap::new();
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT guild_id, currency, user_id, other_id, amount FROM balances")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            accounts
+                .entry(GuildId(row.get::<_, i64>(0)? as u64))
+                .or_default()
+                .entry(read_currency(row, 1)?)
+                .or_default()
+                .entry(UserId(row.get::<_, i64>(2)? as u64))
+                .or_default()
+                .insert(UserId(row.get::<_, i64>(3)? as u64), read_money(row, 4)?);
+        }
+
+        Ok(accounts)
+    }
+
+    fn save_balances(&mut self, _accounts: &HashMap<GuildId, Ledgers>) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        guild_id: GuildId,
+        group: Option<&str>,
+        transaction: &TransactionType,
+    ) -> Result<JournalEntry, StorageError> {
+        if let Some(voided) = transaction.voids() {
+            if self.is_voided(voided)? {
+                return Err(StorageError::Invalid(format!(
+                    "transaction #{} was already voided",
+                    voided
+                )));
+            }
+        }
+
+        let entry = JournalEntry::new(self.last_seq()? + 1, guild_id, group, transaction)?;
+
+        let db_transaction = self.connection.transaction()?;
+        insert_entry(&db_transaction, &entry)?;
+        for transfer in &entry.transfers {
+            for (user, other, amount) in [
+                (transfer.creditor, transfer.debtor, transfer.amount),
+                (transfer.debtor, transfer.creditor, -transfer.amount),
+            ] {
+                add_balance(
+                    &db_transaction,
+                    guild_id,
+                    transfer.currency,
+                    user,
+                    other,
+                    amount,
+                )?;
+            }
+        }
+        db_transaction.commit()?;
+
+        Ok(entry)
+    }
+
+    fn get(&self, seq: u64) -> Result<Option<JournalEntry>, StorageError> {
+        Ok(self
+            .connection
+            .query_row(
+                &format!("SELECT {} FROM entries WHERE seq = ?", ENTRY_COLUMNS),
+                [seq as i64],
+                |row| self.read_entry(row),
+            )
+            .optional()?)
+    }
+
+    fn is_voided(&self, seq: u64) -> Result<bool, StorageError> {
+        Ok(self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM entries WHERE voids = ?)",
+            [seq as i64],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn last_seq(&self) -> Result<u64, StorageError> {
+        let seq: i64 =
+            self.connection
+                .query_row("SELECT COALESCE(MAX(seq), 0) FROM entries", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(seq as u64)
+    }
+
+    fn last_seq_in(&self, guild_id: GuildId) -> Result<u64, StorageError> {
+        let seq: i64 = self.connection.query_row(
+            "SELECT COALESCE(MAX(seq), 0) FROM entries WHERE guild_id = ?",
+            [guild_id.0 as i64],
+            |row| row.get(0),
+        )?;
+        Ok(seq as u64)
+    }
+
+    fn query(
+        &self,
+        filter: &EntryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<JournalEntry>, StorageError> {
+        let (condition, mut values) = filter_condition(filter);
+        values.push(Value::Integer(limit as i64));
+        values.push(Value::Integer(offset as i64));
+
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {} FROM entries e WHERE {} ORDER BY seq DESC LIMIT ? OFFSET ?",
+            ENTRY_COLUMNS, condition
+        ))?;
+        let entries = statement
+            .query_map(params_from_iter(values), |row| self.read_entry(row))?
+            .collect::<rusqlite::Result<Vec<JournalEntry>>>()?;
+
+        Ok(entries)
+    }
+
+    fn count(&self, filter: &EntryFilter) -> Result<usize, StorageError> {
+        let (condition, values) = filter_condition(filter);
+
+        let count: i64 = self.connection.query_row(
+            &format!("SELECT COUNT(*) FROM entries e WHERE {}", condition),
+            params_from_iter(values),
+            |row| row.get(0),
+        )?;
+
+        Ok(count as usize)
+    }
+}
+
+/// Inserts `entry` and its transfers into the journal, without changing any balances.
+fn insert_entry(connection: &Connection, entry: &JournalEntry) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO entries
+         (seq, timestamp, guild_id, kind, initiator, description, voids, group_name)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            entry.seq as i64,
+            entry
+                .timestamp
+                .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)),
+            entry.guild_id.0 as i64,
+            kind_name(entry.kind),
+            entry.initiator.0 as i64,
+            entry.description,
+            entry.voids.map(|voids| voids as i64),
+            entry.group,
+        ],
+    )?;
+
+    for (position, transfer) in entry.transfers.iter().enumerate() {
+        connection.execute(
+            "INSERT INTO transfers (seq, position, creditor, debtor, amount, currency)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                entry.seq as i64,
+                position as i64,
+                transfer.creditor.0 as i64,
+                transfer.debtor.0 as i64,
+                transfer.amount.minor(),
+                transfer.currency.code(),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Adds `amount` to what `other` owes `user`.
+fn add_balance(
+    connection: &Connection,
+    guild_id: GuildId,
+    currency: Currency,
+    user: UserId,
+    other: UserId,
+    amount: Money,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO balances (guild_id, currency, user_id, other_id, amount)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (guild_id, currency, user_id, other_id)
+         DO UPDATE SET amount = amount + excluded.amount",
+        params![
+            guild_id.0 as i64,
+            currency.code(),
+            user.0 as i64,
+            other.0 as i64,
+            amount.minor()
+        ],
+    )?;
+    Ok(())
+}
+
+/// The SQL equivalent of [`EntryFilter::matches`], with its parameters in order.
+fn filter_condition(filter: &EntryFilter) -> (String, Vec<Value>) {
+    let mut conditions = vec!["1".to_string()];
+    let mut values = Vec::new();
+
+    if let Some(guild_id) = filter.guild_id {
+        conditions.push("e.guild_id = ?".to_string());
+        values.push(Value::Integer(guild_id.0 as i64));
+    }
+
+    if let Some(group) = &filter.group {
+        conditions.push("e.group_name = ?".to_string());
+        values.push(Value::Text(group.clone()));
+    }
+
+    if let Some(user) = filter.involving {
+        conditions.push(
+            "(e.initiator = ? OR EXISTS (SELECT 1 FROM transfers t
+             WHERE t.seq = e.seq AND (t.creditor = ? OR t.debtor = ?)))"
+                .to_string(),
+        );
+        values.extend([user, user, user].map(|id| Value::Integer(id.0 as i64)));
+
+        if let Some(other) = filter.counterparty {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM transfers t WHERE t.seq = e.seq
+                 AND ((t.creditor = ? AND t.debtor = ?) OR (t.creditor = ? AND t.debtor = ?)))"
+                    .to_string(),
+            );
+            values.extend([user, other, other, user].map(|id| Value::Integer(id.0 as i64)));
+        }
+    }
+
+    if let Some(initiator) = filter.initiator {
+        conditions.push("e.initiator = ?".to_string());
+        values.push(Value::Integer(initiator.0 as i64));
+    }
+
+    if let Some(since) = filter.since {
+        conditions.push("e.timestamp >= ?".to_string());
+        values.push(Value::Text(
+            since.to_rfc3339_opts(SecondsFormat::Micros, true),
+        ));
+    }
+
+    if let Some(upto) = filter.upto {
+        conditions.push("e.seq <= ?".to_string());
+        values.push(Value::Integer(upto as i64));
+    }
+
+    if filter.voidable {
+        conditions.push(
+            "e.kind != 'void' AND NOT EXISTS (SELECT 1 FROM entries v WHERE v.voids = e.seq)"
+                .to_string(),
+        );
+    }
+
+    (conditions.join(" AND "), values)
+}
+
+fn read_currency(row: &Row, index: usize) -> rusqlite::Result<Currency> {
+    let code: String = row.get(index)?;
+    Currency::from_code(&code).ok_or(rusqlite::Error::InvalidColumnType(
+        index,
+        "currency".to_string(),
+        rusqlite::types::Type::Text,
+    ))
+}
+
+fn read_money(row: &Row, index: usize) -> rusqlite::Result<Money> {
+    Money::new(row.get(index)?).ok_or(rusqlite::Error::InvalidColumnType(
+        index,
+        "amount".to_string(),
+        rusqlite::types::Type::Integer,
+    ))
+}
+
+fn kind_name(kind: TransactionKind) -> &'static str {
+    match kind {
+        TransactionKind::Owe => "owe",
+        TransactionKind::Bill => "bill",
+        TransactionKind::Settle => "settle",
+        TransactionKind::Void => "void",
+        TransactionKind::Simplify => "simplify",
+        TransactionKind::Convert => "convert",
+    }
+}
+
+fn parse_kind(name: &str) -> Option<TransactionKind> {
+    [
+        TransactionKind::Owe,
+        TransactionKind::Bill,
+        TransactionKind::Settle,
+        TransactionKind::Void,
+        TransactionKind::Simplify,
+        TransactionKind::Convert,
+    ]
+    .into_iter()
+    .find(|&kind| kind_name(kind) == name)
+}